@@ -1,5 +1,26 @@
+pub mod crawl_stats;
+pub mod domain_stats;
+pub mod export_jsonld;
+pub mod export_markdown;
+pub mod extract_schema;
 pub mod get_links;
 pub mod get_recipe;
+pub mod health;
+pub mod keyword_frequencies;
+pub mod link_graph;
+pub mod list_recipes;
+pub mod metrics;
+pub mod nutrition_per_serving;
+pub mod nutrition_sum;
 pub mod parse_ingredients;
+pub mod preview;
+pub mod ready;
+pub mod recipe_cards;
+pub mod reindex;
+pub mod requeue_failed;
+pub mod scale_recipe;
 pub mod search;
+pub mod set_domain_priority;
 pub mod submit_link;
+pub mod submit_links;
+pub mod suggest;
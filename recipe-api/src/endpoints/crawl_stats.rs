@@ -0,0 +1,134 @@
+use anyhow::Error;
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use recipe_common::{link::{self, LinkStatus}, recipe};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::AppState;
+
+#[derive(Debug, Serialize, ToSchema)]
+struct CrawlStatsErrorResponse {
+    #[schema(example = "some error")]
+    err: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub(super) struct CrawlStatsResponse {
+    pub(super) waiting: usize,
+    pub(super) processing: usize,
+    pub(super) download_failed: usize,
+    pub(super) extraction_failed: usize,
+    pub(super) parsing_failed: usize,
+    pub(super) processed: usize,
+    pub(super) domains_in_system: usize,
+    /// Total size (in bytes) of pages that recipes could not be extracted from
+    pub(super) total_content_size: u64,
+    pub(super) recipe_count: usize,
+}
+
+pub(super) async fn crawl_stats_inner(state: AppState) -> Result<CrawlStatsResponse, Error> {
+    let redis_links = state.redis_links;
+    // the link functions return a LinkError, so their results are joined first and converted,
+    // letting the recipe count run alongside them
+    let link_stats = async {
+        tokio::try_join!(
+            link::links_with_status(redis_links.clone(), LinkStatus::Waiting),
+            link::links_with_status(redis_links.clone(), LinkStatus::Processing),
+            link::links_with_status(redis_links.clone(), LinkStatus::DownloadFailed),
+            link::links_with_status(redis_links.clone(), LinkStatus::ExtractionFailed),
+            link::links_with_status(redis_links.clone(), LinkStatus::ParsingFailed),
+            link::links_with_status(redis_links.clone(), LinkStatus::Processed),
+            link::domains_in_system(redis_links.clone()),
+            link::total_content_size(redis_links.clone()),
+        ).map_err(Error::from)
+    };
+    let (
+        (
+            waiting, 
+            processing, 
+            download_failed, 
+            extraction_failed, 
+            parsing_failed, 
+            processed, 
+            domains_in_system, 
+            total_content_size, 
+        ),
+        recipe_count,
+    ) = tokio::try_join!(
+        link_stats,
+        recipe::recipe_count(state.redis_recipes),
+    )?;
+
+    Ok(CrawlStatsResponse {
+        waiting,
+        processing,
+        download_failed,
+        extraction_failed,
+        parsing_failed,
+        processed,
+        domains_in_system,
+        total_content_size,
+        recipe_count,
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/crawl_stats",
+    description = "Get how many links have each status, how many domains are being crawled, and how many recipes have been found.",
+    responses(
+        (status = OK, body = CrawlStatsResponse),
+        (status = INTERNAL_SERVER_ERROR, body = CrawlStatsErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn crawl_stats(State(state): State<AppState>) -> impl IntoResponse {
+    match crawl_stats_inner(state).await {
+        Err(err) => (
+            StatusCode::INTERNAL_SERVER_ERROR, 
+            Json(CrawlStatsErrorResponse { err: err.to_string() }),
+        ).into_response(),
+
+        Ok(stats) => (StatusCode::OK, Json(stats)).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use recipe_common::{recipe::Recipe, test_redis};
+
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn crawl_stats_counts_links_domains_and_recipes() {
+        let (_guard, redis) = test_redis::connection().await;
+        let links = [
+            ("https://example.com/a", LinkStatus::Waiting),
+            ("https://example.org/c", LinkStatus::ExtractionFailed),
+            ("https://example.org/d", LinkStatus::ParsingFailed),
+            ("https://example.net/e", LinkStatus::Processed),
+        ];
+        for (link, status) in links {
+            link::add(redis.clone(), link, None, 0.0, 1, true, None).await.unwrap();
+            if status != LinkStatus::Waiting {
+                link::update_status(redis.clone(), link, LinkStatus::Processing).await.unwrap();
+                link::update_status(redis.clone(), link, status).await.unwrap();
+            }
+        }
+        link::add(redis.clone(), "https://example.com/b", None, 1.0, 1, true, None).await.unwrap();
+        assert_eq!(link::poll_next_jobs(redis.clone(), 1, false).await.unwrap(), vec!["https://example.com/b".to_string()]);
+        link::set_content_size(redis.clone(), "https://example.org/c", 1000).await.unwrap();
+        let recipe = Recipe { link: "https://example.net/e".to_string(), title: "Pancakes".to_string(), ..Recipe::default() };
+        recipe::add(redis.clone(), recipe).await.unwrap();
+
+        let stats = crawl_stats_inner(AppState::for_tests(redis)).await.unwrap();
+
+        let counts = (stats.waiting, stats.processing, stats.download_failed, stats.extraction_failed, stats.parsing_failed, stats.processed);
+        assert_eq!(counts, (1, 1, 0, 1, 1, 1));
+        // example.com is the only domain with links waiting or processing
+        assert_eq!(stats.domains_in_system, 1);
+        assert_eq!(stats.total_content_size, 1000);
+        assert_eq!(stats.recipe_count, 1);
+    }
+}
@@ -0,0 +1,119 @@
+use axum::{extract::State, http::StatusCode, response::{IntoResponse, Response}, Json};
+use axum_extra::extract::Query;
+use recipe_common::link;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::AppState;
+
+#[derive(Debug, Deserialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DomainStatsRequest {
+    #[schema(example = "www.indianhealthyrecipes.com")]
+    #[param(example = "www.indianhealthyrecipes.com")]
+    domain: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct DomainStatsSuccessResponse {
+    waiting: usize,
+    is_domain_waiting: bool,
+    is_domain_processing: bool,
+    total: usize,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct DomainStatsErrorResponse {
+    #[schema(example = "some error")]
+    err: String,
+}
+
+async fn respond(state: AppState, request: DomainStatsRequest) -> Response {
+    match link::get_domain_stats(state.redis_links, &request.domain).await {
+        Err(err) => (
+            StatusCode::BAD_REQUEST, 
+            Json(DomainStatsErrorResponse { err: err.to_string() }),
+        ).into_response(),
+
+        Ok(stats) => (
+            StatusCode::OK,
+            Json(DomainStatsSuccessResponse { 
+                waiting: stats.waiting, 
+                is_domain_waiting: stats.is_domain_waiting, 
+                is_domain_processing: stats.is_domain_processing, 
+                total: stats.total,
+            }),
+        ).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/domain_stats",
+    description = "Get how many links on a domain are waiting, whether the domain is waiting or being processed, and how many of its links are known in total. Counting the total scans every link, so can be slow.",
+    responses(
+        (status = OK, body = DomainStatsSuccessResponse),
+        (status = BAD_REQUEST, body = DomainStatsErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn domain_stats(
+    State(state): State<AppState>, 
+    Json(request): Json<DomainStatsRequest>
+) -> impl IntoResponse {
+    respond(state, request).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/domain_stats",
+    description = "Get how many links on a domain are waiting, whether the domain is waiting or being processed, and how many of its links are known in total, with the same options as POST /domain_stats given as query parameters.",
+    params(DomainStatsRequest),
+    responses(
+        (status = OK, body = DomainStatsSuccessResponse),
+        (status = BAD_REQUEST, body = DomainStatsErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn domain_stats_by_query(
+    State(state): State<AppState>, 
+    Query(request): Query<DomainStatsRequest>
+) -> impl IntoResponse {
+    respond(state, request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use recipe_common::test_redis;
+
+    use super::*;
+
+    async fn stats(state: AppState, domain: &str) -> serde_json::Value {
+        let response = respond(state, DomainStatsRequest { domain: domain.to_string() }).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn domain_stats_count_only_the_domains_links() {
+        let (_guard, redis) = test_redis::connection().await;
+        for page in ["a", "b", "c"] {
+            link::add(redis.clone(), &format!("https://example.com/{page}"), None, 0.0, 1, true, None).await.unwrap();
+        }
+        assert_eq!(link::poll_next_jobs(redis.clone(), 1, false).await.unwrap().len(), 1);
+        link::add(redis.clone(), "https://example.org/d", None, 0.0, 1, true, None).await.unwrap();
+        let state = AppState::for_tests(redis);
+
+        assert_eq!(stats(state.clone(), "example.com").await, serde_json::json!({
+            "waiting": 2, "is_domain_waiting": false, "is_domain_processing": true, "total": 3,
+        }));
+        assert_eq!(stats(state.clone(), "example.org").await, serde_json::json!({
+            "waiting": 1, "is_domain_waiting": true, "is_domain_processing": false, "total": 1,
+        }));
+        assert_eq!(stats(state, "example.net").await, serde_json::json!({
+            "waiting": 0, "is_domain_waiting": false, "is_domain_processing": false, "total": 0,
+        }));
+    }
+}
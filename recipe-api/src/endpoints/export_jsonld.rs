@@ -0,0 +1,190 @@
+use axum::{extract::{Path, State}, http::{header, StatusCode}, response::{IntoResponse, Response}, Json};
+use recipe_common::recipe::{self, Recipe};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use utoipa::ToSchema;
+
+use crate::AppState;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ExportJsonLdRequest {
+    #[schema(example = 54)]
+    id: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ExportJsonLdErrorResponse {
+    #[schema(example = "some error")]
+    err: String,
+}
+
+/// eg 5400 -> PT1H30M, 90000 -> P1DT1H
+fn seconds_to_iso8601(seconds: u64) -> String {
+    let days = seconds / (60 * 60 * 24);
+    let hours = seconds / (60 * 60) % 24;
+    let minutes = seconds / 60 % 60;
+    let seconds = seconds % 60;
+
+    let mut duration = "P".to_string();
+    if days != 0 {
+        duration += &format!("{days}D");
+    }
+    if hours != 0 || minutes != 0 || seconds != 0 || days == 0 {
+        duration += "T";
+    }
+    if hours != 0 {
+        duration += &format!("{hours}H");
+    }
+    if minutes != 0 {
+        duration += &format!("{minutes}M");
+    }
+    if seconds != 0 || duration.ends_with('T') {
+        duration += &format!("{seconds}S");
+    }
+    duration
+}
+
+fn nutrition(recipe: &Recipe) -> Value {
+    let mut nutrition = Map::new();
+    nutrition.insert("@type".to_string(), json!("NutritionInformation"));
+
+    let fields = [
+        ("calories", recipe.calories, "kcal"),
+        ("carbohydrateContent", recipe.carbohydrates, "g"),
+        ("cholesterolContent", recipe.cholesterol, "mg"),
+        ("fatContent", recipe.fat, "g"),
+        ("fiberContent", recipe.fiber, "g"),
+        ("proteinContent", recipe.protein, "g"),
+        ("saturatedFatContent", recipe.saturated_fat, "g"),
+        ("sodiumContent", recipe.sodium, "mg"),
+        ("sugarContent", recipe.sugar, "g"),
+    ];
+    for (name, value, unit) in fields {
+        if let Some(value) = value {
+            nutrition.insert(name.to_string(), json!(format!("{value} {unit}")));
+        }
+    }
+
+    if let Some(serving_size) = &recipe.serving_size {
+        nutrition.insert("servingSize".to_string(), json!(serving_size));
+    }
+
+    Value::Object(nutrition)
+}
+
+/// Converts a recipe into a schema.org Recipe, the same format it was originally extracted from
+fn to_jsonld(recipe: Recipe) -> Value {
+    let mut schema = Map::new();
+    schema.insert("@context".to_string(), json!("https://schema.org"));
+    schema.insert("@type".to_string(), json!("Recipe"));
+    schema.insert("url".to_string(), json!(recipe.link));
+    schema.insert("name".to_string(), json!(recipe.title));
+    schema.insert("description".to_string(), json!(recipe.description));
+    schema.insert("recipeIngredient".to_string(), json!(recipe.ingredients));
+    schema.insert("recipeInstructions".to_string(), recipe.instructions.iter()
+        .map(|v| json!({ "@type": "HowToStep", "text": v }))
+        .collect());
+    schema.insert("keywords".to_string(), json!(recipe.keywords.join(", ")));
+    schema.insert("author".to_string(), recipe.authors.iter()
+        .map(|v| json!({ "@type": "Person", "name": v }))
+        .collect());
+    schema.insert("image".to_string(), json!(recipe.images));
+    schema.insert("nutrition".to_string(), nutrition(&recipe));
+
+    if let Some(date) = recipe.date {
+        schema.insert("datePublished".to_string(), json!(date));
+    }
+    if let Some(servings) = recipe.servings {
+        schema.insert("recipeYield".to_string(), json!(servings));
+    }
+    if let Some(prep_time_seconds) = recipe.prep_time_seconds {
+        schema.insert("prepTime".to_string(), json!(seconds_to_iso8601(prep_time_seconds)));
+    }
+    if let Some(cook_time_seconds) = recipe.cook_time_seconds {
+        schema.insert("cookTime".to_string(), json!(seconds_to_iso8601(cook_time_seconds)));
+    }
+    if let Some(total_time_seconds) = recipe.total_time_seconds {
+        schema.insert("totalTime".to_string(), json!(seconds_to_iso8601(total_time_seconds)));
+    }
+    if recipe.rating.is_some() || recipe.rating_count.is_some() {
+        let mut rating = Map::new();
+        rating.insert("@type".to_string(), json!("AggregateRating"));
+        if let Some(value) = recipe.rating {
+            rating.insert("ratingValue".to_string(), json!(value.to_string()));
+        }
+        if let Some(count) = recipe.rating_count {
+            rating.insert("ratingCount".to_string(), json!(count.to_string()));
+        }
+        schema.insert("aggregateRating".to_string(), Value::Object(rating));
+    }
+
+    Value::Object(schema)
+}
+
+async fn respond(state: AppState, request: ExportJsonLdRequest) -> Response {
+    match recipe::get_recipe(state.redis_recipes, request.id).await {
+        Err(err) => (
+            StatusCode::BAD_REQUEST, 
+            Json(ExportJsonLdErrorResponse { err: err.to_string() }),
+        ).into_response(),
+
+        Ok(recipe) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "application/ld+json")],
+            Json(to_jsonld(recipe)),
+        ).into_response()
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/export_jsonld",
+    description = "Export a recipe as a schema.org Recipe in JSON-LD.",
+    responses(
+        (status = OK, body = Object, content_type = "application/ld+json"),
+        (status = BAD_REQUEST, body = ExportJsonLdErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn export_jsonld(
+    State(state): State<AppState>, 
+    Json(request): Json<ExportJsonLdRequest>
+) -> impl IntoResponse {
+    respond(state, request).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/recipe/{id}/jsonld",
+    description = "Export a recipe as a schema.org Recipe in JSON-LD.",
+    params(
+        ("id" = u64, Path, description = "Recipe id", example = 54)
+    ),
+    responses(
+        (status = OK, body = Object, content_type = "application/ld+json"),
+        (status = BAD_REQUEST, body = ExportJsonLdErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn export_jsonld_by_id(
+    State(state): State<AppState>, 
+    Path(id): Path<u64>
+) -> impl IntoResponse {
+    respond(state, ExportJsonLdRequest { id }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seconds_to_iso8601_formats_durations() {
+        assert_eq!(seconds_to_iso8601(0), "PT0S");
+        assert_eq!(seconds_to_iso8601(45), "PT45S");
+        assert_eq!(seconds_to_iso8601(600), "PT10M");
+        assert_eq!(seconds_to_iso8601(5400), "PT1H30M");
+        assert_eq!(seconds_to_iso8601(3661), "PT1H1M1S");
+        assert_eq!(seconds_to_iso8601(86400), "P1D");
+        assert_eq!(seconds_to_iso8601(90000), "P1DT1H");
+    }
+}
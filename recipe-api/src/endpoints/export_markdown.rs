@@ -0,0 +1,175 @@
+use axum::{extract::{Path, State}, http::{header, StatusCode}, response::{IntoResponse, Response}, Json};
+use recipe_common::recipe::{self, Recipe};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::AppState;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ExportMarkdownRequest {
+    #[schema(example = 54)]
+    id: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ExportMarkdownErrorResponse {
+    #[schema(example = "some error")]
+    err: String,
+}
+
+/// eg 5400 -> '1 hr 30 min', 45 -> '45 sec'
+fn format_duration(seconds: u64) -> String {
+    let hours = seconds / (60 * 60);
+    let minutes = seconds / 60 % 60;
+
+    let mut parts = vec![];
+    if hours != 0 {
+        parts.push(format!("{hours} hr"));
+    }
+    if minutes != 0 {
+        parts.push(format!("{minutes} min"));
+    }
+    if parts.is_empty() {
+        parts.push(format!("{seconds} sec"));
+    }
+    parts.join(" ")
+}
+
+fn to_markdown(recipe: Recipe) -> String {
+    let mut markdown = format!("# {}\n\n", recipe.title);
+
+    let mut metadata = vec![];
+    if let Some(servings) = &recipe.servings {
+        metadata.push(format!("**Servings:** {servings}"));
+    }
+    if let Some(total_time_seconds) = recipe.total_time_seconds {
+        metadata.push(format!("**Total time:** {}", format_duration(total_time_seconds)));
+    }
+    if let Some(rating) = recipe.rating {
+        match recipe.rating_count {
+            Some(rating_count) => metadata.push(format!("**Rating:** {rating:.1} ({rating_count} ratings)")),
+            None => metadata.push(format!("**Rating:** {rating:.1}")),
+        }
+    }
+    if !metadata.is_empty() {
+        markdown += &format!("{}\n\n", metadata.join(" | "));
+    }
+
+    if let Some(image) = recipe.images.first() {
+        markdown += &format!("![{}]({image})\n\n", recipe.title);
+    }
+
+    if !recipe.description.is_empty() {
+        markdown += &format!("{}\n\n", recipe.description);
+    }
+
+    markdown += "## Ingredients\n\n";
+    for ingredient in &recipe.ingredients {
+        markdown += &format!("- {ingredient}\n");
+    }
+
+    markdown += "\n## Instructions\n\n";
+    for (i, instruction) in recipe.instructions.iter().enumerate() {
+        markdown += &format!("{}. {instruction}\n", i + 1);
+    }
+
+    markdown += &format!("\nSource: <{}>\n", recipe.link);
+
+    markdown
+}
+
+async fn respond(state: AppState, request: ExportMarkdownRequest) -> Response {
+    match recipe::get_recipe(state.redis_recipes, request.id).await {
+        Err(err) => (
+            StatusCode::BAD_REQUEST, 
+            Json(ExportMarkdownErrorResponse { err: err.to_string() }),
+        ).into_response(),
+
+        Ok(recipe) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, "text/markdown; charset=utf-8")],
+            to_markdown(recipe),
+        ).into_response()
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/export_markdown",
+    description = "Export a recipe as Markdown.",
+    responses(
+        (status = OK, body = String, content_type = "text/markdown"),
+        (status = BAD_REQUEST, body = ExportMarkdownErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn export_markdown(
+    State(state): State<AppState>, 
+    Json(request): Json<ExportMarkdownRequest>
+) -> impl IntoResponse {
+    respond(state, request).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/recipe/{id}/markdown",
+    description = "Export a recipe as Markdown.",
+    params(
+        ("id" = u64, Path, description = "Recipe id", example = 54)
+    ),
+    responses(
+        (status = OK, body = String, content_type = "text/markdown"),
+        (status = BAD_REQUEST, body = ExportMarkdownErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn export_markdown_by_id(
+    State(state): State<AppState>, 
+    Path(id): Path<u64>
+) -> impl IntoResponse {
+    respond(state, ExportMarkdownRequest { id }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_duration_is_human_readable() {
+        assert_eq!(format_duration(0), "0 sec");
+        assert_eq!(format_duration(45), "45 sec");
+        assert_eq!(format_duration(600), "10 min");
+        assert_eq!(format_duration(3600), "1 hr");
+        assert_eq!(format_duration(5400), "1 hr 30 min");
+        assert_eq!(format_duration(90000), "25 hr");
+    }
+
+    #[test]
+    fn to_markdown_renders_a_fully_populated_recipe() {
+        let recipe = Recipe {
+            link: "https://example.com/pancakes".to_string(),
+            title: "Pancakes".to_string(),
+            description: "Fluffy pancakes".to_string(),
+            ingredients: vec!["200g flour".to_string(), "2 eggs".to_string()],
+            instructions: vec!["Whisk everything".to_string(), "Fry".to_string()],
+            images: vec!["https://example.com/pancakes.jpg".to_string()],
+            rating: Some(4.25),
+            rating_count: Some(12),
+            total_time_seconds: Some(5400),
+            servings: Some("4".to_string()),
+            ..Recipe::default()
+        };
+
+        assert_eq!(to_markdown(recipe), "# Pancakes\n\n\
+            **Servings:** 4 | **Total time:** 1 hr 30 min | **Rating:** 4.2 (12 ratings)\n\n\
+            ![Pancakes](https://example.com/pancakes.jpg)\n\n\
+            Fluffy pancakes\n\n\
+            ## Ingredients\n\n\
+            - 200g flour\n\
+            - 2 eggs\n\
+            \n## Instructions\n\n\
+            1. Whisk everything\n\
+            2. Fry\n\
+            \nSource: <https://example.com/pancakes>\n");
+    }
+}
@@ -0,0 +1,137 @@
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use recipe_common::{extractor, parser, recipe::Recipe};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ExtractSchemaRequest {
+    #[schema(example = "<html><head><script type=\"application/ld+json\">{\"@type\": \"Recipe\", ...}</script></head></html>")]
+    html: String,
+    /// The page the HTML came from, used to resolve relative links and as the recipe's link
+    #[schema(example = "https://www.indianhealthyrecipes.com/cauliflower-curry-recipe/")]
+    link: String,
+    /// Reject recipes unless at least one instruction has this many characters, like the
+    /// crawler's option of the same name
+    #[schema(example = 20)]
+    min_instruction_chars: Option<usize>,
+    /// Reject recipes unless their instructions have this many characters in total, like the
+    /// crawler's option of the same name
+    #[schema(example = 100)]
+    min_total_instruction_chars: Option<usize>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ExtractSchemaSuccessResponse {
+    recipes: Vec<Recipe>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ExtractSchemaErrorResponse {
+    #[schema(example = "some error")]
+    err: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/extract_schema",
+    description = "Extract and parse the recipes in a page's HTML, exactly as the crawler would, without storing anything. Recipes with instructions shorter than the given minimums are rejected, as the crawler does with the same options. Fails if no recipe schema was found or no recipes were left.",
+    responses(
+        (status = OK, body = ExtractSchemaSuccessResponse),
+        (status = UNPROCESSABLE_ENTITY, body = ExtractSchemaErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(request))]
+pub async fn extract_schema(Json(request): Json<ExtractSchemaRequest>) -> impl IntoResponse {
+    let schemas = match extractor::extract_all(&request.link, &request.html).await {
+        Err(err) => return (
+            StatusCode::UNPROCESSABLE_ENTITY, 
+            Json(ExtractSchemaErrorResponse { err: format!("Failed to extract schema: {err}") }),
+        ).into_response(),
+        Ok(schemas) => schemas,
+    };
+
+    if schemas.is_empty() {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY, 
+            Json(ExtractSchemaErrorResponse { err: "No recipe schema found".to_string() }),
+        ).into_response();
+    }
+
+    let mut recipes = vec![];
+    for (i, schema) in schemas.into_iter().enumerate() {
+        if let Some(recipe) = parser::parse(parser::recipe_link(&request.link, i), schema).await {
+            recipes.push(recipe);
+        }
+    }
+
+    if recipes.is_empty() {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY, 
+            Json(ExtractSchemaErrorResponse { err: "Found a recipe schema but failed to parse it".to_string() }),
+        ).into_response();
+    }
+
+    recipes.retain(|recipe| recipe.has_plausible_instructions(request.min_instruction_chars, request.min_total_instruction_chars));
+    if recipes.is_empty() {
+        return (
+            StatusCode::UNPROCESSABLE_ENTITY, 
+            Json(ExtractSchemaErrorResponse { err: "Parsed a recipe but its instructions are too short".to_string() }),
+        ).into_response();
+    }
+
+    (StatusCode::OK, Json(ExtractSchemaSuccessResponse { recipes })).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECIPE_PAGE: &str = r#"<html><head><script type="application/ld+json">{
+        "@context": "https://schema.org",
+        "@type": "Recipe",
+        "name": "Pancakes",
+        "description": "Fluffy pancakes",
+        "image": "/images/pancakes.jpg",
+        "recipeYield": "4 servings",
+        "totalTime": "PT20M",
+        "recipeIngredient": ["2 eggs", "100g flour", "300ml milk"],
+        "recipeInstructions": [{"@type": "HowToStep", "text": "Whisk everything together, then fry in a hot pan"}]
+    }</script></head><body></body></html>"#;
+
+    async fn extract(html: &str, min_instruction_chars: Option<usize>) -> (StatusCode, serde_json::Value) {
+        let request = ExtractSchemaRequest {
+            html: html.to_string(),
+            link: "https://example.com/pancakes".to_string(),
+            min_instruction_chars,
+            min_total_instruction_chars: None,
+        };
+        let response = extract_schema(Json(request)).await.into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn recipe_page_is_parsed_like_the_crawler_would() {
+        let (status, body) = extract(RECIPE_PAGE, None).await;
+
+        assert_eq!(status, StatusCode::OK);
+        let recipe = &body["recipes"][0];
+        assert_eq!(body["recipes"].as_array().unwrap().len(), 1);
+        assert_eq!((&recipe["link"], &recipe["title"]), (&serde_json::json!("https://example.com/pancakes"), &serde_json::json!("Pancakes")));
+        assert_eq!(recipe["ingredients"], serde_json::json!(["2 eggs", "100g flour", "300ml milk"]));
+        assert_eq!(recipe["images"], serde_json::json!(["https://example.com/images/pancakes.jpg"]));
+        assert_eq!((&recipe["servings_min"], &recipe["total_time_seconds"]), (&serde_json::json!(4.0), &serde_json::json!(1200)));
+    }
+
+    #[tokio::test]
+    async fn pages_without_a_usable_recipe_are_unprocessable() {
+        let (status, body) = extract("<html><body>Pancakes</body></html>", None).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["err"], "No recipe schema found");
+
+        let (status, body) = extract(RECIPE_PAGE, Some(100)).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["err"], "Parsed a recipe but its instructions are too short");
+    }
+}
@@ -1,14 +1,32 @@
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum::{extract::State, http::StatusCode, response::{IntoResponse, Response}, Json};
+use axum_extra::extract::Query;
 use recipe_common::link::{self, LinkStatus};
 use serde::{Deserialize, Serialize};
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 
 use crate::AppState;
 
-#[derive(Debug, Deserialize, ToSchema)]
+const MAX_LIMIT: usize = 1000;
+
+fn limit_default() -> usize {
+    100
+}
+
+#[derive(Debug, Deserialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct GetLinksRequest {
     #[schema(example = "waiting")]
+    #[param(example = "waiting")]
     status: String,
+    #[serde(default)]
+    #[schema(default = 0)]
+    #[param(default = 0)]
+    offset: usize,
+    /// At most 1000
+    #[serde(default = "limit_default")]
+    #[schema(default = 100)]
+    #[param(default = 100)]
+    limit: usize,
 }
 
 #[derive(Debug, Serialize, ToSchema)]
@@ -24,12 +42,53 @@ struct Link {
     parent: Option<String>,
 }
 
+#[derive(Debug, Serialize, ToSchema)]
+struct GetLinksSuccessResponse {
+    total: usize,
+    links: Vec<Link>,
+}
+
+async fn respond(state: AppState, request: GetLinksRequest) -> Response {
+    let Some(status) = LinkStatus::from_string(&request.status) else {
+        let valid: Vec<&str> = LinkStatus::ALL.iter()
+            .map(|v| v.to_string())
+            .collect();
+        let err = format!("Invalid status '{}' (expected one of: {})", request.status, valid.join(", "));
+        return (StatusCode::BAD_REQUEST, Json(GetLinksErrorResponse { err })).into_response()
+    };
+
+    let total = link::links_with_status(state.redis_links.clone(), status).await;
+    if let Err(err) = total {
+        return (StatusCode::BAD_REQUEST, Json(GetLinksErrorResponse { err: err.to_string() })).into_response()
+    }
+    let total = total.unwrap();
+
+    let links = link::get_links_by_status_range(state.redis_links.clone(), status, request.offset, request.limit.min(MAX_LIMIT)).await;
+    if let Err(err) = links {
+        return (StatusCode::BAD_REQUEST, Json(GetLinksErrorResponse { err: err.to_string() })).into_response()
+    }
+    let links = links.unwrap();
+
+    let details = link::get_links_detailed(state.redis_links.clone(), &links).await;
+    if let Err(err) = details {
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(GetLinksErrorResponse { err: err.to_string() })).into_response()
+    }
+    let details = details.unwrap();
+
+    let links = links.into_iter()
+        .zip(details)
+        .map(|(link, (priority, parent))| Link { link, priority, parent })
+        .collect();
+
+    (StatusCode::OK, Json(GetLinksSuccessResponse { total, links })).into_response()
+}
+
 #[utoipa::path(
     post,
     path = "/get_links",
-    description = "Get links by status.",
+    description = "Get a page of links by status, highest priority first.",
     responses(
-        (status = OK, body = Vec<Link>),
+        (status = OK, body = GetLinksSuccessResponse),
         (status = BAD_REQUEST, body = GetLinksErrorResponse)
     ),
 )]
@@ -38,34 +97,23 @@ pub async fn get_links(
     State(state): State<AppState>,
     Json(request): Json<GetLinksRequest>,
 ) -> impl IntoResponse {
-    let Some(status) = LinkStatus::from_string(&request.status) else {
-        return (StatusCode::BAD_REQUEST, Json(GetLinksErrorResponse { err: "".to_string() })).into_response()
-    };
-
-    let links = link::get_links_by_status(state.redis_links.clone(), status).await;
-    if let Err(err) = links {
-        return (StatusCode::BAD_REQUEST, Json(GetLinksErrorResponse { err: err.to_string() })).into_response()
-    }
-    let links = links.unwrap();
-
-    let mut response_links = vec![];
-    for link in links {
-
-        let priority = link::get_priority(state.redis_links.clone(), &link).await;
-        if let Err(err) = priority {
-            return (StatusCode::INTERNAL_SERVER_ERROR, Json(GetLinksErrorResponse { err: err.to_string() })).into_response()
-        }
-        let priority = priority.unwrap();
-
-        let parent = link::get_parent(state.redis_links.clone(), &link).await;
-        if let Err(err) = parent {
-            return (StatusCode::INTERNAL_SERVER_ERROR, Json(GetLinksErrorResponse { err: err.to_string() })).into_response()
-        }
-        let parent = parent.unwrap();
-
-        response_links.push(Link { link, priority, parent });
-    }
-
-    (StatusCode::OK, Json(response_links)).into_response()
+    respond(state, request).await
 }
 
+#[utoipa::path(
+    get,
+    path = "/get_links",
+    description = "Get a page of links by status, highest priority first, with the same options as POST /get_links given as query parameters.",
+    params(GetLinksRequest),
+    responses(
+        (status = OK, body = GetLinksSuccessResponse),
+        (status = BAD_REQUEST, body = GetLinksErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn get_links_by_query(
+    State(state): State<AppState>, 
+    Query(request): Query<GetLinksRequest>
+) -> impl IntoResponse {
+    respond(state, request).await
+}
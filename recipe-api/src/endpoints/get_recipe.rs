@@ -1,4 +1,4 @@
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use axum::{extract::{Path, State}, http::StatusCode, response::{IntoResponse, Response}, Json};
 use recipe_common::recipe::{self, Recipe};
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
@@ -17,6 +17,20 @@ struct GetRecipeErrorResponse {
     err: String,
 }
 
+async fn respond(state: AppState, id: u64) -> Response {
+    match recipe::get_recipe(state.redis_recipes, id).await {
+        Err(err) => (
+            StatusCode::BAD_REQUEST, 
+            Json(GetRecipeErrorResponse { err: err.to_string() }),
+        ).into_response(),
+
+        Ok(recipe) => (
+            StatusCode::OK,
+            Json(recipe),
+        ).into_response()
+    }
+}
+
 #[utoipa::path(
     post,
     path = "/get_recipe",
@@ -31,16 +45,25 @@ pub async fn get_recipe(
     State(state): State<AppState>, 
     Json(request): Json<GetRecipeRequest>
 ) -> impl IntoResponse {
-    match recipe::get_recipe(state.redis_recipes, request.id).await {
-        Err(err) => (
-            StatusCode::BAD_REQUEST, 
-            Json(GetRecipeErrorResponse { err: err.to_string() }),
-        ).into_response(),
-
-        Ok(recipe) => (
-            StatusCode::OK,
-            Json(recipe),
-        ).into_response()
-    }
+    respond(state, request.id).await
 }
 
+#[utoipa::path(
+    get,
+    path = "/recipe/{id}",
+    description = "Get a recipe by id.",
+    params(
+        ("id" = u64, Path, description = "Recipe id", example = 54)
+    ),
+    responses(
+        (status = OK, body = Recipe),
+        (status = BAD_REQUEST, body = GetRecipeErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn get_recipe_by_id(
+    State(state): State<AppState>, 
+    Path(id): Path<u64>
+) -> impl IntoResponse {
+    respond(state, id).await
+}
@@ -0,0 +1,14 @@
+use axum::{http::StatusCode, response::IntoResponse};
+
+#[utoipa::path(
+    get,
+    path = "/health",
+    description = "Liveness probe. Always succeeds if the server is running.",
+    responses(
+        (status = OK)
+    ),
+)]
+#[tracing::instrument]
+pub async fn health() -> impl IntoResponse {
+    StatusCode::OK
+}
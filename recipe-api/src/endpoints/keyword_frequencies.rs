@@ -0,0 +1,117 @@
+use axum::{extract::State, http::StatusCode, response::{IntoResponse, Response}, Json};
+use axum_extra::extract::Query;
+use recipe_common::recipe;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::AppState;
+
+fn limit_default() -> usize {
+    50
+}
+
+#[derive(Debug, Deserialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct KeywordFrequenciesRequest {
+    #[serde(default = "limit_default")]
+    #[schema(default = 50)]
+    #[param(default = 50)]
+    limit: usize,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct KeywordFrequency {
+    #[schema(example = "dinner")]
+    keyword: String,
+    recipe_count: usize,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct KeywordFrequenciesErrorResponse {
+    #[schema(example = "some error")]
+    err: String,
+}
+
+async fn respond(state: AppState, request: KeywordFrequenciesRequest) -> Response {
+    match recipe::keyword_frequencies(state.redis_recipes, request.limit).await {
+        Err(err) => (
+            StatusCode::BAD_REQUEST, 
+            Json(KeywordFrequenciesErrorResponse { err: err.to_string() }),
+        ).into_response(),
+
+        Ok(frequencies) => {
+            let frequencies: Vec<KeywordFrequency> = frequencies.into_iter()
+                .map(|(keyword, recipe_count)| KeywordFrequency { keyword, recipe_count })
+                .collect();
+            (StatusCode::OK, Json(frequencies)).into_response()
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/keyword_frequencies",
+    description = "Get the most common recipe keywords across all recipes, along with how many recipes have each keyword.",
+    responses(
+        (status = OK, body = Vec<KeywordFrequency>),
+        (status = BAD_REQUEST, body = KeywordFrequenciesErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn keyword_frequencies(
+    State(state): State<AppState>, 
+    Json(request): Json<KeywordFrequenciesRequest>
+) -> impl IntoResponse {
+    respond(state, request).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/keyword_frequencies",
+    description = "Get the most common recipe keywords across all recipes, along with how many recipes have each keyword, with the same options as POST /keyword_frequencies given as query parameters.",
+    params(KeywordFrequenciesRequest),
+    responses(
+        (status = OK, body = Vec<KeywordFrequency>),
+        (status = BAD_REQUEST, body = KeywordFrequenciesErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn keyword_frequencies_by_query(
+    State(state): State<AppState>, 
+    Query(request): Query<KeywordFrequenciesRequest>
+) -> impl IntoResponse {
+    respond(state, request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use recipe_common::{recipe::Recipe, test_redis};
+
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn most_common_keywords_come_first() {
+        let (_guard, redis) = test_redis::connection().await;
+        let keywords: [&[&str]; 3] = [&["Dinner", "Quick"], &["dinner"], &["Dessert", "quick", "DINNER"]];
+        for (i, keywords) in keywords.iter().enumerate() {
+            let recipe = Recipe {
+                link: format!("https://example.com/{i}"),
+                title: format!("Recipe {i}"),
+                keywords: keywords.iter().map(|v| v.to_string()).collect(),
+                ..Recipe::default()
+            };
+            recipe::add(redis.clone(), recipe).await.unwrap();
+        }
+
+        let response = respond(AppState::for_tests(redis), KeywordFrequenciesRequest { limit: 2 }).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(body, serde_json::json!([
+            { "keyword": "dinner", "recipe_count": 3 },
+            { "keyword": "quick", "recipe_count": 2 },
+        ]));
+    }
+}
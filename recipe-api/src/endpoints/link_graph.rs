@@ -0,0 +1,95 @@
+use axum::{extract::State, http::StatusCode, response::{IntoResponse, Response}, Json};
+use axum_extra::extract::Query;
+use recipe_common::link;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::AppState;
+
+const MAX_DEPTH: usize = 5;
+const MAX_NODES: usize = 1000;
+
+fn max_depth_default() -> usize {
+    2
+}
+
+#[derive(Debug, Deserialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LinkGraphRequest {
+    #[schema(example = "https://www.indianhealthyrecipes.com/cauliflower-curry-recipe/")]
+    #[param(example = "https://www.indianhealthyrecipes.com/cauliflower-curry-recipe/")]
+    root: String,
+    #[serde(default = "max_depth_default")]
+    #[schema(default = 2)]
+    #[param(default = 2)]
+    max_depth: usize,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct LinkGraphNode {
+    link: String,
+    children: Vec<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct LinkGraphSuccessResponse {
+    nodes: Vec<LinkGraphNode>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct LinkGraphErrorResponse {
+    #[schema(example = "some error")]
+    err: String,
+}
+
+async fn respond(state: AppState, request: LinkGraphRequest) -> Response {
+    let max_depth = request.max_depth.min(MAX_DEPTH);
+    match link::get_link_graph(state.redis_links, &request.root, max_depth, MAX_NODES).await {
+        Err(err) => (
+            StatusCode::BAD_REQUEST, 
+            Json(LinkGraphErrorResponse { err: err.to_string() }),
+        ).into_response(),
+
+        Ok(graph) => {
+            let nodes = graph.into_iter()
+                .map(|(link, children)| LinkGraphNode { link, children })
+                .collect();
+            (StatusCode::OK, Json(LinkGraphSuccessResponse { nodes })).into_response()
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/link_graph",
+    description = "Get the links discovered from a root link, as an adjacency list of each link and the links that were added from it. Links up to max_depth (at most 5) away from the root are included, up to 1000 links. Links added before this was tracked have no children.",
+    responses(
+        (status = OK, body = LinkGraphSuccessResponse),
+        (status = BAD_REQUEST, body = LinkGraphErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn link_graph(
+    State(state): State<AppState>, 
+    Json(request): Json<LinkGraphRequest>
+) -> impl IntoResponse {
+    respond(state, request).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/link_graph",
+    description = "Get the links discovered from a root link, as an adjacency list of each link and the links that were added from it, with the same options as POST /link_graph given as query parameters.",
+    params(LinkGraphRequest),
+    responses(
+        (status = OK, body = LinkGraphSuccessResponse),
+        (status = BAD_REQUEST, body = LinkGraphErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn link_graph_by_query(
+    State(state): State<AppState>, 
+    Query(request): Query<LinkGraphRequest>
+) -> impl IntoResponse {
+    respond(state, request).await
+}
@@ -0,0 +1,92 @@
+use axum::{extract::State, http::StatusCode, response::{IntoResponse, Response}, Json};
+use axum_extra::extract::Query;
+use recipe_common::recipe;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::AppState;
+
+const MAX_COUNT: usize = 1000;
+
+fn count_default() -> usize {
+    100
+}
+
+#[derive(Debug, Deserialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListRecipesRequest {
+    /// 0 for the first page, then the cursor returned by the previous page
+    #[serde(default)]
+    #[schema(default = 0)]
+    #[param(default = 0)]
+    cursor: u64,
+    /// Roughly how many ids to return (at most 1000)
+    #[serde(default = "count_default")]
+    #[schema(default = 100)]
+    #[param(default = 100)]
+    count: usize,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ListRecipesSuccessResponse {
+    total: usize,
+    /// 0 once every recipe has been returned
+    next_cursor: u64,
+    recipe_ids: Vec<u64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ListRecipesErrorResponse {
+    #[schema(example = "some error")]
+    err: String,
+}
+
+async fn respond(state: AppState, request: ListRecipesRequest) -> Response {
+    let count = request.count.clamp(1, MAX_COUNT);
+    match recipe::list_recipes(state.redis_recipes, request.cursor, count).await {
+        Err(err) => (
+            StatusCode::BAD_REQUEST, 
+            Json(ListRecipesErrorResponse { err: err.to_string() }),
+        ).into_response(),
+
+        Ok((total, next_cursor, recipe_ids)) => (
+            StatusCode::OK,
+            Json(ListRecipesSuccessResponse { total, next_cursor, recipe_ids }),
+        ).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/list_recipes",
+    description = "Get a page of the ids of every stored recipe, along with the total number of recipes. Start with a cursor of 0 and pass the returned cursor to get the next page, until it is 0. Ids are in no particular order, and an id can be returned more than once.",
+    responses(
+        (status = OK, body = ListRecipesSuccessResponse),
+        (status = BAD_REQUEST, body = ListRecipesErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn list_recipes(
+    State(state): State<AppState>, 
+    Json(request): Json<ListRecipesRequest>
+) -> impl IntoResponse {
+    respond(state, request).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/list_recipes",
+    description = "Get a page of the ids of every stored recipe, along with the total number of recipes, with the same options as POST /list_recipes given as query parameters.",
+    params(ListRecipesRequest),
+    responses(
+        (status = OK, body = ListRecipesSuccessResponse),
+        (status = BAD_REQUEST, body = ListRecipesErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn list_recipes_by_query(
+    State(state): State<AppState>, 
+    Query(request): Query<ListRecipesRequest>
+) -> impl IntoResponse {
+    respond(state, request).await
+}
@@ -0,0 +1,73 @@
+use anyhow::Error;
+use axum::{extract::State, http::{header, StatusCode}, response::IntoResponse};
+use prometheus::{Encoder, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder};
+use recipe_common::metrics;
+
+use crate::AppState;
+
+use super::crawl_stats::crawl_stats_inner;
+
+/// Builds a fresh registry on every scrape, since all of the values live in Redis
+async fn metrics_inner(state: AppState) -> Result<String, Error> {
+    let stats = crawl_stats_inner(state.clone()).await?;
+    let counters = metrics::get_counters(state.redis_links).await?;
+
+    let registry = Registry::new();
+
+    let links = IntGaugeVec::new(Opts::new("recipe_links", "Number of links with each status"), &["status"])?;
+    for (status, count) in [
+        ("waiting", stats.waiting),
+        ("processing", stats.processing),
+        ("download_failed", stats.download_failed),
+        ("extraction_failed", stats.extraction_failed),
+        ("parsing_failed", stats.parsing_failed),
+        ("processed", stats.processed),
+    ] {
+        links.with_label_values(&[status]).set(count as i64);
+    }
+    registry.register(Box::new(links))?;
+
+    let domains_in_system = IntGauge::new("recipe_domains_in_system", "Number of domains with links waiting or processing")?;
+    domains_in_system.set(stats.domains_in_system as i64);
+    registry.register(Box::new(domains_in_system))?;
+
+    let total_content_size = IntGauge::new("recipe_total_content_size_bytes", "Total size of pages that recipes could not be extracted from")?;
+    total_content_size.set(stats.total_content_size as i64);
+    registry.register(Box::new(total_content_size))?;
+
+    let recipe_count = IntGauge::new("recipe_count", "Number of recipes")?;
+    recipe_count.set(stats.recipe_count as i64);
+    registry.register(Box::new(recipe_count))?;
+
+    let stages = IntCounterVec::new(Opts::new("recipe_finder_stages_total", "Number of links that completed each stage of the finder pipeline"), &["stage"])?;
+    for (stage, count) in counters {
+        stages.with_label_values(&[stage]).inc_by(count);
+    }
+    registry.register(Box::new(stages))?;
+
+    let mut buf = vec![];
+    TextEncoder::new().encode(&registry.gather(), &mut buf)?;
+
+    Ok(String::from_utf8(buf)?)
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    description = "Get crawl metrics in the Prometheus text exposition format.",
+    responses(
+        (status = OK, body = String, content_type = "text/plain"),
+        (status = INTERNAL_SERVER_ERROR, body = String, content_type = "text/plain")
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    match metrics_inner(state).await {
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        Ok(metrics) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, TextEncoder::new().format_type().to_string())],
+            metrics,
+        ).into_response(),
+    }
+}
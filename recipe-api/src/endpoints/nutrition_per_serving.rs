@@ -0,0 +1,163 @@
+use axum::{extract::{Path, State}, http::StatusCode, response::{IntoResponse, Response}, Json};
+use recipe_common::recipe::{self, Recipe};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::AppState;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NutritionPerServingRequest {
+    #[schema(example = 54)]
+    id: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct Nutrition {
+    calories: Option<f32>,
+    carbohydrates: Option<f32>,
+    cholesterol: Option<f32>,
+    fat: Option<f32>,
+    fiber: Option<f32>,
+    protein: Option<f32>,
+    saturated_fat: Option<f32>,
+    sodium: Option<f32>,
+    sugar: Option<f32>,
+}
+
+impl Nutrition {
+    fn from_recipe(recipe: &Recipe, servings: f32) -> Self {
+        let per_serving = |v: Option<f32>| v.map(|v| v / servings);
+        Nutrition {
+            calories: per_serving(recipe.calories),
+            carbohydrates: per_serving(recipe.carbohydrates),
+            cholesterol: per_serving(recipe.cholesterol),
+            fat: per_serving(recipe.fat),
+            fiber: per_serving(recipe.fiber),
+            protein: per_serving(recipe.protein),
+            saturated_fat: per_serving(recipe.saturated_fat),
+            sodium: per_serving(recipe.sodium),
+            sugar: per_serving(recipe.sugar),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct NutritionPerServingSuccessResponse {
+    /// The number of servings the stored values were divided by, if it could be worked out
+    servings: Option<f32>,
+    /// False if the number of servings could not be worked out, in which case the stored values
+    /// are returned unchanged
+    per_serving: bool,
+    nutrition: Nutrition,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct NutritionPerServingErrorResponse {
+    #[schema(example = "some error")]
+    err: String,
+}
+
+async fn respond(state: AppState, request: NutritionPerServingRequest) -> Response {
+    let recipe = match recipe::get_recipe(state.redis_recipes, request.id).await {
+        Err(err) => return (
+            StatusCode::BAD_REQUEST, 
+            Json(NutritionPerServingErrorResponse { err: err.to_string() }),
+        ).into_response(),
+        Ok(recipe) => recipe,
+    };
+
+    let servings = recipe.servings_count().filter(|v| *v > 0.0);
+    let nutrition = Nutrition::from_recipe(&recipe, servings.unwrap_or(1.0));
+
+    (
+        StatusCode::OK,
+        Json(NutritionPerServingSuccessResponse { servings, per_serving: servings.is_some(), nutrition }),
+    ).into_response()
+}
+
+#[utoipa::path(
+    post,
+    path = "/nutrition_per_serving",
+    description = "Get a recipe's nutrition divided by its number of servings. If the number of servings can't be worked out, the stored values are returned as they are, with per_serving set to false.",
+    responses(
+        (status = OK, body = NutritionPerServingSuccessResponse),
+        (status = BAD_REQUEST, body = NutritionPerServingErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn nutrition_per_serving(
+    State(state): State<AppState>, 
+    Json(request): Json<NutritionPerServingRequest>
+) -> impl IntoResponse {
+    respond(state, request).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/recipe/{id}/nutrition_per_serving",
+    description = "Get a recipe's nutrition divided by its number of servings. If the number of servings can't be worked out, the stored values are returned as they are, with per_serving set to false.",
+    params(
+        ("id" = u64, Path, description = "Recipe id", example = 54)
+    ),
+    responses(
+        (status = OK, body = NutritionPerServingSuccessResponse),
+        (status = BAD_REQUEST, body = NutritionPerServingErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn nutrition_per_serving_by_id(
+    State(state): State<AppState>, 
+    Path(id): Path<u64>
+) -> impl IntoResponse {
+    respond(state, NutritionPerServingRequest { id }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use recipe_common::test_redis;
+
+    use super::*;
+
+    /// Store a recipe with 800 calories and 40g of protein, and get its nutrition
+    async fn nutrition(servings: &str) -> serde_json::Value {
+        let (_guard, redis) = test_redis::connection().await;
+        let recipe = Recipe {
+            link: "https://example.com/stew".to_string(),
+            title: "Stew".to_string(),
+            servings: Some(servings.to_string()),
+            calories: Some(800.0),
+            protein: Some(40.0),
+            ..Recipe::default()
+        };
+        recipe::add(redis.clone(), recipe).await.unwrap();
+        let (_, _, ids) = recipe::list_recipes(redis.clone(), 0, 10).await.unwrap();
+
+        let response = respond(AppState::for_tests(redis), NutritionPerServingRequest { id: ids[0] }).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn nutrition_is_divided_by_the_number_of_servings() {
+        let body = nutrition("4 servings").await;
+
+        assert_eq!(body["servings"], serde_json::json!(4.0));
+        assert_eq!(body["per_serving"], serde_json::json!(true));
+        assert_eq!(body["nutrition"]["calories"], serde_json::json!(200.0));
+        assert_eq!(body["nutrition"]["protein"], serde_json::json!(10.0));
+        assert_eq!(body["nutrition"]["fat"], serde_json::Value::Null);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn nutrition_is_unchanged_if_servings_cant_be_parsed() {
+        let body = nutrition("a big pot").await;
+
+        assert_eq!(body["servings"], serde_json::Value::Null);
+        assert_eq!(body["per_serving"], serde_json::json!(false));
+        assert_eq!(body["nutrition"]["calories"], serde_json::json!(800.0));
+        assert_eq!(body["nutrition"]["protein"], serde_json::json!(40.0));
+    }
+}
@@ -0,0 +1,205 @@
+use anyhow::Error;
+use axum::{extract::State, http::StatusCode, response::{IntoResponse, Response}, Json};
+use axum_extra::extract::Query;
+use recipe_common::recipe::{get_field_value, get_recipes_fields, RecipeField};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::AppState;
+
+const MAX_IDS: usize = 100;
+
+const NUTRITION_FIELDS: [RecipeField; 9] = [
+    RecipeField::Calories,
+    RecipeField::Carbohydrates,
+    RecipeField::Cholesterol,
+    RecipeField::Fat,
+    RecipeField::Fiber,
+    RecipeField::Protein,
+    RecipeField::SaturatedFat,
+    RecipeField::Sodium,
+    RecipeField::Sugar,
+];
+
+#[derive(Debug, Deserialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct NutritionSumRequest {
+    /// In a query string, given as a repeated parameter (eg ?ids=54&ids=55)
+    #[schema(example = json!([54, 55]))]
+    #[param(example = json!([54, 55]))]
+    ids: Vec<u64>,
+}
+
+#[derive(Debug, Default, Serialize, ToSchema)]
+struct NutrientSum {
+    total: f32,
+    /// How many of the recipes had this nutrient. If fewer than the number of recipes found,
+    /// the total is missing the other recipes' contributions
+    recipe_count: usize,
+}
+
+impl NutrientSum {
+    fn add(&mut self, value: Option<f32>) {
+        if let Some(value) = value {
+            self.total += value;
+            self.recipe_count += 1;
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, ToSchema)]
+struct NutritionSum {
+    calories: NutrientSum,
+    carbohydrates: NutrientSum,
+    cholesterol: NutrientSum,
+    fat: NutrientSum,
+    fiber: NutrientSum,
+    protein: NutrientSum,
+    saturated_fat: NutrientSum,
+    sodium: NutrientSum,
+    sugar: NutrientSum,
+}
+
+impl NutritionSum {
+    fn nutrients(&self) -> [&NutrientSum; 9] {
+        [&self.calories, &self.carbohydrates, &self.cholesterol, &self.fat, &self.fiber, &self.protein, &self.saturated_fat, &self.sodium, &self.sugar]
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct NutritionSumSuccessResponse {
+    /// How many of the requested recipes exist
+    recipe_count: usize,
+    /// Requested recipes that don't exist, which are not included in the sums
+    missing_ids: Vec<u64>,
+    /// False if any found recipe is missing any nutrient, in which case some totals are partial
+    complete: bool,
+    nutrition: NutritionSum,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct NutritionSumErrorResponse {
+    #[schema(example = "some error")]
+    err: String,
+}
+
+async fn nutrition_sum_inner(state: AppState, ids: &[u64]) -> Result<NutritionSumSuccessResponse, Error> {
+    let mut fields = NUTRITION_FIELDS.to_vec();
+    fields.push(RecipeField::Link);
+    let values = get_recipes_fields(state.redis_recipes, ids, &fields).await?;
+
+    let mut nutrition = NutritionSum::default();
+    let mut recipe_count = 0;
+    let mut missing_ids = vec![];
+    for (id, values) in ids.iter().zip(values) {
+        // every recipe has a link
+        let link: Option<String> = get_field_value(&values, RecipeField::Link)?;
+        if link.is_none() {
+            missing_ids.push(*id);
+            continue;
+        }
+        recipe_count += 1;
+
+        nutrition.calories.add(get_field_value(&values, RecipeField::Calories)?);
+        nutrition.carbohydrates.add(get_field_value(&values, RecipeField::Carbohydrates)?);
+        nutrition.cholesterol.add(get_field_value(&values, RecipeField::Cholesterol)?);
+        nutrition.fat.add(get_field_value(&values, RecipeField::Fat)?);
+        nutrition.fiber.add(get_field_value(&values, RecipeField::Fiber)?);
+        nutrition.protein.add(get_field_value(&values, RecipeField::Protein)?);
+        nutrition.saturated_fat.add(get_field_value(&values, RecipeField::SaturatedFat)?);
+        nutrition.sodium.add(get_field_value(&values, RecipeField::Sodium)?);
+        nutrition.sugar.add(get_field_value(&values, RecipeField::Sugar)?);
+    }
+
+    let complete = nutrition.nutrients()
+        .iter()
+        .all(|nutrient| nutrient.recipe_count == recipe_count);
+
+    Ok(NutritionSumSuccessResponse { recipe_count, missing_ids, complete, nutrition })
+}
+
+async fn respond(state: AppState, request: NutritionSumRequest) -> Response {
+    if request.ids.len() > MAX_IDS {
+        return (
+            StatusCode::BAD_REQUEST, 
+            Json(NutritionSumErrorResponse { err: format!("At most {MAX_IDS} recipes can be summed at once") }),
+        ).into_response();
+    }
+
+    match nutrition_sum_inner(state, &request.ids).await {
+        Err(err) => (
+            StatusCode::BAD_REQUEST, 
+            Json(NutritionSumErrorResponse { err: err.to_string() }),
+        ).into_response(),
+
+        Ok(response) => (StatusCode::OK, Json(response)).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/nutrition_sum",
+    description = "Sum the nutrition of several recipes (at most 100), eg for a meal plan. Each total comes with how many recipes contributed to it, and complete is false if any recipe is missing any nutrient.",
+    responses(
+        (status = OK, body = NutritionSumSuccessResponse),
+        (status = BAD_REQUEST, body = NutritionSumErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn nutrition_sum(
+    State(state): State<AppState>, 
+    Json(request): Json<NutritionSumRequest>
+) -> impl IntoResponse {
+    respond(state, request).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/nutrition_sum",
+    description = "Sum the nutrition of several recipes (at most 100), eg for a meal plan, with the same options as POST /nutrition_sum given as query parameters.",
+    params(NutritionSumRequest),
+    responses(
+        (status = OK, body = NutritionSumSuccessResponse),
+        (status = BAD_REQUEST, body = NutritionSumErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn nutrition_sum_by_query(
+    State(state): State<AppState>, 
+    Query(request): Query<NutritionSumRequest>
+) -> impl IntoResponse {
+    respond(state, request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use recipe_common::{recipe::{self, Recipe}, test_redis};
+
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn nutrition_of_each_recipe_is_summed() {
+        let (_guard, redis) = test_redis::connection().await;
+        for (name, calories, fat) in [("soup", 300.0, Some(10.0)), ("bread", 450.0, None)] {
+            let recipe = Recipe {
+                link: format!("https://example.com/{name}"),
+                title: name.to_string(),
+                calories: Some(calories),
+                fat,
+                ..Recipe::default()
+            };
+            recipe::add(redis.clone(), recipe).await.unwrap();
+        }
+        let (_, _, mut ids) = recipe::list_recipes(redis.clone(), 0, 10).await.unwrap();
+        ids.push(9999);
+
+        let response = nutrition_sum_inner(AppState::for_tests(redis), &ids).await.unwrap();
+
+        assert_eq!(response.recipe_count, 2);
+        assert_eq!(response.missing_ids, vec![9999]);
+        assert_eq!((response.nutrition.calories.total, response.nutrition.calories.recipe_count), (750.0, 2));
+        assert_eq!((response.nutrition.fat.total, response.nutrition.fat.recipe_count), (10.0, 1));
+        assert!(!response.complete);
+    }
+}
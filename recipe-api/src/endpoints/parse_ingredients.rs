@@ -1,27 +1,54 @@
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
-use ingredient::Ingredient;
+use axum::{extract::{Path, State}, http::StatusCode, response::{IntoResponse, Response}, Json};
+use ingredient::{unit::{Measure, MeasureKind, Unit}, Ingredient};
+use axum_extra::extract::Query;
 use recipe_common::recipe;
 use serde::{Deserialize, Serialize};
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 
 use crate::AppState;
 
+const MILLILITERS_PER_TEASPOON: f64 = 4.92892;
+const GRAMS_PER_OUNCE: f64 = 28.3495;
+const OUNCES_PER_POUND: f64 = 16.0;
+
+#[derive(Debug, Clone, Copy, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum UnitSystem {
+    Metric,
+    Imperial,
+}
+
 #[derive(Debug, Deserialize, ToSchema)]
 pub struct ParseIngredientsRequest {
     #[schema(example = 54)]
     id: u64,
+    /// Also convert each amount to this system of units
+    #[schema(example = "metric")]
+    target_system: Option<UnitSystem>,
+}
+
+/// The options of `ParseIngredientsRequest` other than the id, for when the id is in the path
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ParseIngredientsQuery {
+    /// Also convert each amount to this system of units
+    #[param(example = "metric")]
+    target_system: Option<UnitSystem>,
 }
 
 #[derive(Debug, Serialize, ToSchema)]
-struct ParseIngredientsAmount {
+pub(crate) struct ParseIngredientsAmount {
     raw: String,
     value: String,
     upper_value: Option<String>,
     unit: String,
+    /// The amount converted to the requested system of units, if one was requested and the
+    /// amount has a unit that can be converted
+    converted: Option<String>,
 }
 
 #[derive(Debug, Serialize, ToSchema)]
-struct ParseIngredientsIngredient {
+pub(crate) struct ParseIngredientsIngredient {
     original: String,
     raw: String,
     name: String,
@@ -40,10 +67,132 @@ struct ParseIngredientErrorResponse {
     err: String,
 }
 
+/// Multiplies every amount by `scale`, except for times and temperatures which don't
+/// change with the size of the recipe
+fn scale_ingredient(mut ingredient: Ingredient, scale: f64) -> Ingredient {
+    ingredient.amounts = ingredient.amounts.iter()
+        .map(|amount| match amount.kind() {
+            Ok(MeasureKind::Time) | Ok(MeasureKind::Temperature) => amount.clone(),
+            _ => {
+                let (value, upper_value) = amount.values();
+                Measure::new_with_upper(amount.unit(), value * scale, upper_value.map(|v| v * scale))
+            }
+        })
+        .collect();
+    ingredient
+}
+
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+fn convert_values(amount: &Measure, unit: Unit, convert: impl Fn(f64) -> f64) -> Measure {
+    let (value, upper_value) = amount.values();
+    Measure::new_with_upper(unit, round(convert(value)), upper_value.map(|v| round(convert(v))))
+}
+
+/// Converts an amount to metric or imperial units. Returns None for amounts that are already
+/// in the target system or have no convertible unit (eg '2 eggs', times)
+fn convert_amount(amount: &Measure, target_system: UnitSystem) -> Option<Measure> {
+    // normalizing leaves every weight in grams and every volume in teaspoons or milliliters
+    let normalized = amount.normalize();
+    match (target_system, amount.unit()) {
+        (UnitSystem::Metric, Unit::Ounce | Unit::Pound) => if normalized.values().0 >= 1000.0 {
+            Some(convert_values(&normalized, Unit::Kilogram, |v| v / 1000.0))
+        } else {
+            Some(convert_values(&normalized, Unit::Gram, |v| v))
+        },
+        (UnitSystem::Metric, Unit::Teaspoon | Unit::Tablespoon | Unit::Cup | Unit::Quart | Unit::FluidOunce) => {
+            let milliliters = normalized.values().0 * MILLILITERS_PER_TEASPOON;
+            if milliliters >= 1000.0 {
+                Some(convert_values(&normalized, Unit::Liter, |v| v * MILLILITERS_PER_TEASPOON / 1000.0))
+            } else {
+                Some(convert_values(&normalized, Unit::Milliliter, |v| v * MILLILITERS_PER_TEASPOON))
+            }
+        }
+        (UnitSystem::Metric, Unit::Farhenheit) => Some(convert_values(&normalized, Unit::Celcius, |v| (v - 32.0) * 5.0 / 9.0)),
+
+        (UnitSystem::Imperial, Unit::Gram | Unit::Kilogram) => if normalized.values().0 / GRAMS_PER_OUNCE >= OUNCES_PER_POUND {
+            Some(convert_values(&normalized, Unit::Pound, |v| v / GRAMS_PER_OUNCE / OUNCES_PER_POUND))
+        } else {
+            Some(convert_values(&normalized, Unit::Ounce, |v| v / GRAMS_PER_OUNCE))
+        },
+        (UnitSystem::Imperial, Unit::Milliliter | Unit::Liter) => {
+            // denormalize picks the most readable of teaspoons, tablespoons, cups and quarts
+            let teaspoons = convert_values(&normalized, Unit::Teaspoon, |v| v / MILLILITERS_PER_TEASPOON).denormalize();
+            Some(convert_values(&teaspoons, teaspoons.unit(), |v| v))
+        }
+        (UnitSystem::Imperial, Unit::Celcius) => Some(convert_values(&normalized, Unit::Farhenheit, |v| v * 9.0 / 5.0 + 32.0)),
+
+        _ => None,
+    }
+}
+
+/// Parses each ingredient, multiplying every amount by `scale` and optionally converting it to
+/// `target_system`
+pub(crate) fn parse(
+    ingredients: Vec<String>, 
+    scale: f64, 
+    target_system: Option<UnitSystem>
+) -> Result<Vec<ParseIngredientsIngredient>, String> {
+    let parsed_ingredients: Vec<Ingredient> = ingredients.iter()
+        .map(|v| Ingredient::try_from(v.as_str()).map(|v| scale_ingredient(v, scale)))
+        .collect::<Result<_, _>>()?;
+
+    let mut formatted_ingredients = vec![];
+    for (parsed_ingredient, original_ingredient) in parsed_ingredients.iter().zip(ingredients) {
+
+        let mut formatted_amounts = vec![];
+        for amount in &parsed_ingredient.amounts {
+            let converted = target_system.and_then(|target_system| convert_amount(amount, target_system));
+            let amount = amount.normalize();
+            let (value, upper_value) = amount.values();
+            formatted_amounts.push(ParseIngredientsAmount { 
+                raw: amount.to_string(),
+                value: value.to_string(),
+                upper_value: upper_value.map(|v| v.to_string()),
+                unit: amount.unit().to_str(),
+                converted: converted.map(|v| v.to_string()),
+            });
+        }
+
+        formatted_ingredients.push(ParseIngredientsIngredient {
+            original: original_ingredient,
+            raw: parsed_ingredient.to_string(),
+            name: parsed_ingredient.name.clone(),
+            amounts: formatted_amounts,
+            modifier: parsed_ingredient.modifier.clone(),
+        });
+    }
+
+    Ok(formatted_ingredients)
+}
+
+async fn respond(state: AppState, request: ParseIngredientsRequest) -> Response {
+    match recipe::get_recipe(state.redis_recipes, request.id).await {
+        Err(err) => (
+            StatusCode::BAD_REQUEST, 
+            Json(ParseIngredientErrorResponse { err: err.to_string() }),
+        ).into_response(),
+
+        Ok(recipe) => match parse(recipe.ingredients, 1.0, request.target_system) {
+            Err(err) => (
+                StatusCode::BAD_REQUEST, 
+                Json(ParseIngredientErrorResponse { err: err.to_string() }),
+            ).into_response(),
+
+            Ok(formatted_ingredients) => (
+                StatusCode::OK,
+                Json(ParseIngredientsSuccessResponse { ingredients: formatted_ingredients }),
+            ).into_response(),
+        }
+    }
+}
+
 #[utoipa::path(
     post,
     path = "/parse_ingredients",
-    description = "Parse a recipe's ingredient list.",
+    description = "Parse a recipe's ingredient list, optionally converting amounts to metric or imperial units.",
     responses(
         (status = OK, body = ParseIngredientsSuccessResponse),
         (status = BAD_REQUEST, body = ParseIngredientErrorResponse)
@@ -54,56 +203,77 @@ pub async fn parse_ingredients(
     State(state): State<AppState>, 
     Json(request): Json<ParseIngredientsRequest>
 ) -> impl IntoResponse {
-    match recipe::get_recipe(state.redis_recipes, request.id).await {
-        Err(err) => (
-            StatusCode::BAD_REQUEST, 
-            Json(ParseIngredientErrorResponse { err: err.to_string() }),
-        ).into_response(),
+    respond(state, request).await
+}
 
-        Ok(recipe) => {
-            let parsed_ingredients: Result<Vec<Ingredient>, _> = recipe.ingredients.iter()
-                .map(|v| Ingredient::try_from(v.as_str()))
-                .collect();
-
-            match parsed_ingredients {
-                Err(err) => (
-                    StatusCode::BAD_REQUEST, 
-                    Json(ParseIngredientErrorResponse { err: err.to_string() }),
-                ).into_response(),
-
-                Ok(parsed_ingredients) => {
-
-                    let mut formatted_ingredients = vec![];
-                    for (parsed_ingredient, original_ingredient) in parsed_ingredients.iter().zip(recipe.ingredients) {
-
-                        let mut formatted_amounts = vec![];
-                        for amount in &parsed_ingredient.amounts {
-                            let amount = amount.normalize();
-                            let (value, upper_value) = amount.values();
-                            formatted_amounts.push(ParseIngredientsAmount { 
-                                raw: amount.to_string(),
-                                value: value.to_string(),
-                                upper_value: upper_value.map(|v| v.to_string()),
-                                unit: amount.unit().to_str(),
-                            });
-                        }
-
-                        formatted_ingredients.push(ParseIngredientsIngredient {
-                            original: original_ingredient,
-                            raw: parsed_ingredient.to_string(),
-                            name: parsed_ingredient.name.clone(),
-                            amounts: formatted_amounts,
-                            modifier: parsed_ingredient.modifier.clone(),
-                        });
-                    }
-
-                    (
-                        StatusCode::OK,
-                        Json(ParseIngredientsSuccessResponse { ingredients: formatted_ingredients }),
-                    ).into_response()
-                }
-            }
-        }
-    }
+#[utoipa::path(
+    get,
+    path = "/recipe/{id}/ingredients",
+    description = "Parse a recipe's ingredient list, optionally converting amounts to metric or imperial units.",
+    params(
+        ("id" = u64, Path, description = "Recipe id", example = 54),
+        ParseIngredientsQuery
+    ),
+    responses(
+        (status = OK, body = ParseIngredientsSuccessResponse),
+        (status = BAD_REQUEST, body = ParseIngredientErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn parse_ingredients_by_id(
+    State(state): State<AppState>, 
+    Path(id): Path<u64>,
+    Query(query): Query<ParseIngredientsQuery>
+) -> impl IntoResponse {
+    respond(state, ParseIngredientsRequest { id, target_system: query.target_system }).await
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The converted amount of each ingredient
+    fn converted(ingredients: &[&str], target_system: UnitSystem) -> Vec<Option<String>> {
+        parse(ingredients.iter().map(|v| v.to_string()).collect(), 1.0, Some(target_system))
+            .unwrap()
+            .into_iter()
+            .map(|v| v.amounts[0].converted.clone())
+            .collect()
+    }
+
+    #[test]
+    fn volumes_and_weights_are_converted_to_metric() {
+        assert_eq!(converted(&["1 cup milk", "8 oz cheese", "5 cups stock", "3 lb beef"], UnitSystem::Metric), vec![
+            Some("236.59 ml".to_string()),
+            Some("226.8 g".to_string()),
+            Some("1.18 l".to_string()),
+            Some("1.36 kg".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn volumes_and_weights_are_converted_to_imperial() {
+        assert_eq!(converted(&["500 g flour", "250ml water"], UnitSystem::Imperial), vec![
+            Some("1.1 lb".to_string()),
+            Some("1.06 cups".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn temperatures_are_converted() {
+        assert_eq!(converted(&["350 F"], UnitSystem::Metric), vec![Some("176.67 °c".to_string())]);
+    }
+
+    #[test]
+    fn amounts_without_a_convertible_unit_are_not_converted() {
+        assert_eq!(converted(&["2 eggs"], UnitSystem::Metric), vec![None]);
+        assert_eq!(converted(&["250ml water"], UnitSystem::Metric), vec![None]);
+    }
+
+    #[test]
+    fn amounts_are_not_converted_without_a_target_system() {
+        let ingredients = parse(vec!["1 cup milk".to_string()], 1.0, None).unwrap();
+        assert_eq!(ingredients[0].amounts[0].converted, None);
+        assert_eq!(ingredients[0].name, "milk");
+    }
+}
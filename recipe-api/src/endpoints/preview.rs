@@ -0,0 +1,182 @@
+use axum::{extract::State, http::StatusCode, response::{IntoResponse, Response}, Json};
+use recipe_common::{download, extractor, link, link_blacklist, parser, recipe::Recipe, robots};
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+use url::Url;
+use utoipa::ToSchema;
+
+use crate::AppState;
+
+use super::submit_link;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct PreviewRequest {
+    #[schema(example = "https://www.indianhealthyrecipes.com/cauliflower-curry-recipe/")]
+    link: String,
+    /// Reject recipes unless at least one instruction has this many characters, like the
+    /// crawler's option of the same name
+    #[schema(example = 20)]
+    min_instruction_chars: Option<usize>,
+    /// Reject recipes unless their instructions have this many characters in total, like the
+    /// crawler's option of the same name
+    #[schema(example = 100)]
+    min_total_instruction_chars: Option<usize>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct PreviewSuccessResponse {
+    recipes: Vec<Recipe>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct PreviewErrorResponse {
+    #[schema(example = "some error")]
+    err: String,
+}
+
+fn error(status: StatusCode, err: String) -> Response {
+    (status, Json(PreviewErrorResponse { err })).into_response()
+}
+
+#[utoipa::path(
+    post,
+    path = "/preview",
+    description = "Download a page and return the recipes the crawler would store from it, without queueing or storing anything. Blacklisted links, links to local or private addresses and links disallowed by the site's robots.txt are rejected, as are recipes with instructions shorter than the given minimums. On failure, the error says whether downloading, extraction or parsing failed.",
+    responses(
+        (status = OK, body = PreviewSuccessResponse),
+        (status = BAD_REQUEST, body = PreviewErrorResponse),
+        (status = FORBIDDEN, body = PreviewErrorResponse),
+        (status = BAD_GATEWAY, body = PreviewErrorResponse),
+        (status = UNPROCESSABLE_ENTITY, body = PreviewErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn preview(
+    State(state): State<AppState>, 
+    Json(request): Json<PreviewRequest>
+) -> impl IntoResponse {
+    if let Err(err) = submit_link::validate(&request.link) {
+        return error(StatusCode::BAD_REQUEST, err);
+    }
+    // validated above
+    let url = Url::parse(&request.link).unwrap();
+
+    match link_blacklist::is_allowed(state.redis_links.clone(), &link::canonicalize(&request.link)).await {
+        Err(err) => return error(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()),
+        Ok(false) => return error(StatusCode::BAD_REQUEST, "Link is blacklisted".to_string()),
+        Ok(true) => (),
+    }
+
+    // the client's resolver and redirect policy refuse local or private addresses from here on
+    if !robots::fetch(&state.client, &state.headers, &url).await.is_link_allowed(&url) {
+        return error(StatusCode::FORBIDDEN, "Link is disallowed by robots.txt".to_string());
+    }
+
+    let response = state.client.request(Method::GET, &request.link)
+        .headers(download::headers(&state.headers))
+        .send()
+        .await;
+    let response = match response {
+        Err(err) => return error(StatusCode::BAD_GATEWAY, format!("Failed to download: {err}")),
+        Ok(response) if !response.status().is_success() => {
+            return error(StatusCode::BAD_GATEWAY, format!("Failed to download: got status code {}", response.status()))
+        }
+        Ok(response) => response,
+    };
+
+    let contents = match download::read_limited(response, download::DEFAULT_MAX_CONTENT_SIZE).await
+        .and_then(|v| download::decode(&v, download::DEFAULT_MAX_CONTENT_SIZE))
+    {
+        Err(err) => return error(StatusCode::BAD_GATEWAY, format!("Failed to download: {err}")),
+        Ok(contents) => contents,
+    };
+
+    let schemas = match extractor::extract_all(&request.link, &contents).await {
+        Err(err) => return error(StatusCode::UNPROCESSABLE_ENTITY, format!("Failed to extract schema: {err}")),
+        Ok(schemas) if schemas.is_empty() => return error(StatusCode::UNPROCESSABLE_ENTITY, "Failed to extract schema: no recipe schema found".to_string()),
+        Ok(schemas) => schemas,
+    };
+
+    let mut recipes = vec![];
+    for (i, schema) in schemas.into_iter().enumerate() {
+        if let Some(recipe) = parser::parse(parser::recipe_link(&request.link, i), schema).await {
+            recipes.push(recipe);
+        }
+    }
+
+    if recipes.is_empty() {
+        return error(StatusCode::UNPROCESSABLE_ENTITY, "Failed to parse: found a recipe schema but failed to parse it".to_string());
+    }
+
+    recipes.retain(|recipe| recipe.has_plausible_instructions(request.min_instruction_chars, request.min_total_instruction_chars));
+    if recipes.is_empty() {
+        return error(StatusCode::UNPROCESSABLE_ENTITY, "Failed to parse: parsed a recipe but its instructions are too short".to_string());
+    }
+
+    (StatusCode::OK, Json(PreviewSuccessResponse { recipes })).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use recipe_common::{link_blacklist::MatchType, test_redis, test_server::{self, response}};
+    use reqwest::Client;
+
+    use super::*;
+
+    const RECIPE_PAGE: &str = r#"<html><head><script type="application/ld+json">{
+        "@context": "https://schema.org",
+        "@type": "Recipe",
+        "name": "Pancakes",
+        "description": "Fluffy pancakes",
+        "image": "/images/pancakes.jpg",
+        "recipeYield": "4 servings",
+        "totalTime": "PT20M",
+        "recipeIngredient": ["2 eggs", "100g flour", "300ml milk"],
+        "recipeInstructions": [{"@type": "HowToStep", "text": "Whisk everything together, then fry in a hot pan"}]
+    }</script></head><body></body></html>"#;
+
+    async fn preview_link(state: AppState, link: &str) -> (StatusCode, serde_json::Value) {
+        let request = PreviewRequest { link: link.to_string(), min_instruction_chars: None, min_total_instruction_chars: None };
+        let response = preview(State(state), Json(request)).await.into_response();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn preview_downloads_and_parses_without_storing() {
+        let (_guard, mut redis) = test_redis::connection().await;
+        let server = test_server::serve(|path| Some(match path {
+            "/pancakes" | "/blocked" => response("200 OK", &[("Content-Type", "text/html")], RECIPE_PAGE.as_bytes()),
+            _ => response("404 Not Found", &[], b""),
+        })).await;
+        link_blacklist::add(redis.clone(), "/blocked", MatchType::Substring).await.unwrap();
+        // local addresses are rejected, so the server is given a name of its own
+        let address = server.url.trim_start_matches("http://").trim_end_matches('/').parse().unwrap();
+        let state = AppState {
+            client: Client::builder().no_proxy().resolve("preview.test", address).build().unwrap(),
+            ..AppState::for_tests(redis.clone())
+        };
+        let url = format!("http://preview.test:{}", address.port());
+
+        let (status, body) = preview_link(state.clone(), &format!("{url}/pancakes")).await;
+        assert_eq!(status, StatusCode::OK);
+        let recipe = &body["recipes"][0];
+        assert_eq!((&recipe["link"], &recipe["title"]), (&serde_json::json!(format!("{url}/pancakes")), &serde_json::json!("Pancakes")));
+        assert_eq!(recipe["ingredients"], serde_json::json!(["2 eggs", "100g flour", "300ml milk"]));
+        assert_eq!(recipe["servings_min"], 4.0);
+
+        let (status, body) = preview_link(state.clone(), &format!("{url}/missing")).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+        assert_eq!(body["err"], "Failed to download: got status code 404 Not Found");
+
+        let (status, body) = preview_link(state, &format!("{url}/blocked")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["err"], "Link is blacklisted");
+
+        // nothing is stored, apart from the blacklist
+        let keys: Vec<String> = redis::cmd("KEYS").arg("*").query_async(&mut redis).await.unwrap();
+        assert!(keys.iter().all(|key| key.starts_with("blacklist")), "{keys:?}");
+    }
+}
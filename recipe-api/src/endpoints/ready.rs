@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+use anyhow::Error;
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use redis::aio::MultiplexedConnection;
+use serde::Serialize;
+use tokio::time::timeout;
+use utoipa::ToSchema;
+
+use crate::AppState;
+
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ReadyErrorResponse {
+    #[schema(example = "some error")]
+    err: String,
+}
+
+async fn ping(mut redis: MultiplexedConnection) -> Result<(), Error> {
+    let _: String = timeout(PING_TIMEOUT, redis::cmd("PING").query_async(&mut redis)).await??;
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = "/ready",
+    description = "Readiness probe. Fails if either Redis database can't be reached.",
+    responses(
+        (status = OK),
+        (status = SERVICE_UNAVAILABLE, body = ReadyErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn ready(State(state): State<AppState>) -> impl IntoResponse {
+    match tokio::try_join!(ping(state.redis_links), ping(state.redis_recipes)) {
+        Err(err) => (
+            StatusCode::SERVICE_UNAVAILABLE, 
+            Json(ReadyErrorResponse { err: err.to_string() }),
+        ).into_response(),
+
+        Ok(_) => StatusCode::OK.into_response(),
+    }
+}
@@ -0,0 +1,84 @@
+use axum::{extract::State, http::StatusCode, response::{IntoResponse, Response}, Json};
+use axum_extra::extract::Query;
+use recipe_common::recipe::{self, RecipeCard};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::AppState;
+
+#[derive(Debug, Deserialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RecipeCardsRequest {
+    /// In a query string, given as a repeated parameter (eg ?ids=54&ids=55)
+    #[schema(example = json!([54, 55]))]
+    #[param(example = json!([54, 55]))]
+    ids: Vec<u64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct RecipeCardsSuccessResponse {
+    cards: Vec<RecipeCard>,
+    /// Requested ids that don't have a recipe
+    #[schema(example = json!([55]))]
+    missing: Vec<u64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct RecipeCardsErrorResponse {
+    #[schema(example = "some error")]
+    err: String,
+}
+
+async fn respond(state: AppState, request: RecipeCardsRequest) -> Response {
+    match recipe::get_recipe_cards(state.redis_recipes, &request.ids).await {
+        Err(err) => (
+            StatusCode::BAD_REQUEST, 
+            Json(RecipeCardsErrorResponse { err: err.to_string() }),
+        ).into_response(),
+
+        Ok(cards) => {
+            let missing = request.ids.iter()
+                .zip(&cards)
+                .filter(|(_, card)| card.is_none())
+                .map(|(id, _)| *id)
+                .collect();
+            let cards = cards.into_iter().flatten().collect();
+            (StatusCode::OK, Json(RecipeCardsSuccessResponse { cards, missing })).into_response()
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/recipe_cards",
+    description = "Get summaries of recipes by id (title, image, rating, and total time), without ingredients or instructions. Ids that don't have a recipe are listed in missing.",
+    responses(
+        (status = OK, body = RecipeCardsSuccessResponse),
+        (status = BAD_REQUEST, body = RecipeCardsErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn recipe_cards(
+    State(state): State<AppState>, 
+    Json(request): Json<RecipeCardsRequest>
+) -> impl IntoResponse {
+    respond(state, request).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/recipe_cards",
+    description = "Get summaries of recipes by id (title, image, rating, and total time), without ingredients or instructions, with the same options as POST /recipe_cards given as query parameters.",
+    params(RecipeCardsRequest),
+    responses(
+        (status = OK, body = RecipeCardsSuccessResponse),
+        (status = BAD_REQUEST, body = RecipeCardsErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn recipe_cards_by_query(
+    State(state): State<AppState>, 
+    Query(request): Query<RecipeCardsRequest>
+) -> impl IntoResponse {
+    respond(state, request).await
+}
@@ -0,0 +1,43 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use log::{info, warn};
+use recipe_common::recipe;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::AppState;
+
+/// Reindexing walks every recipe, so only one can run at a time
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ReindexSuccessResponse {
+    /// False if a reindex was already running
+    started: bool,
+}
+
+#[utoipa::path(
+    post,
+    path = "/reindex",
+    description = "Start recomputing the search terms and tags of every stored recipe in the background and update the indexes to match, eg after the term extraction or tagging logic changes. Entries left in the indexes by recipes that were deleted or reindexed before their terms and tags were recorded are removed afterwards. Only one reindex runs at a time, and the result is logged.",
+    responses(
+        (status = OK, body = ReindexSuccessResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn reindex(State(state): State<AppState>) -> impl IntoResponse {
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        return (StatusCode::OK, Json(ReindexSuccessResponse { started: false })).into_response();
+    }
+
+    tokio::spawn(async move {
+        match recipe::reindex(state.redis_recipes).await {
+            Err(err) => warn!("Error reindexing recipes: {err}"),
+            Ok((reindexed, pruned)) => info!("Reindexed {reindexed} recipes and removed {pruned} stale index entries"),
+        }
+        RUNNING.store(false, Ordering::SeqCst);
+    });
+
+    (StatusCode::OK, Json(ReindexSuccessResponse { started: true })).into_response()
+}
@@ -0,0 +1,57 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use recipe_common::link::{self, LinkStatus};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::AppState;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct RequeueFailedRequest {
+    #[schema(example = "download_failed")]
+    status: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct RequeueFailedSuccessResponse {
+    requeued: usize,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct RequeueFailedErrorResponse {
+    #[schema(example = "some error")]
+    err: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/requeue_failed",
+    description = "Move every link with a failed status (download_failed, extraction_failed or parsing_failed) back to waiting so it is retried, highest priority first, returning how many links were requeued. Links on domains that already have the finder's maximum number of waiting links are left as they are.",
+    responses(
+        (status = OK, body = RequeueFailedSuccessResponse),
+        (status = BAD_REQUEST, body = RequeueFailedErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn requeue_failed(
+    State(state): State<AppState>, 
+    Json(request): Json<RequeueFailedRequest>
+) -> impl IntoResponse {
+    let Some(status) = LinkStatus::from_string(&request.status).filter(|status| status.is_failed()) else {
+        return (
+            StatusCode::BAD_REQUEST, 
+            Json(RequeueFailedErrorResponse { err: format!("'{}' is not a failed status", request.status) }),
+        ).into_response();
+    };
+
+    match link::requeue_failed(state.redis_links, status).await {
+        Err(err) => (
+            StatusCode::BAD_REQUEST, 
+            Json(RequeueFailedErrorResponse { err: err.to_string() }),
+        ).into_response(),
+
+        Ok(requeued) => (
+            StatusCode::OK,
+            Json(RequeueFailedSuccessResponse { requeued }),
+        ).into_response(),
+    }
+}
@@ -0,0 +1,170 @@
+use axum::{extract::{Path, State}, http::StatusCode, response::{IntoResponse, Response}, Json};
+use axum_extra::extract::Query;
+use recipe_common::recipe;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::AppState;
+
+use super::parse_ingredients::{self, ParseIngredientsIngredient};
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ScaleRecipeRequest {
+    #[schema(example = 54)]
+    id: u64,
+    #[schema(example = 8)]
+    target_servings: f32,
+}
+
+/// The options of `ScaleRecipeRequest` other than the id, for when the id is in the path
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ScaleRecipeQuery {
+    #[param(example = 8)]
+    target_servings: f32,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ScaleRecipeSuccessResponse {
+    servings: f32,
+    target_servings: f32,
+    scale: f32,
+    ingredients: Vec<ParseIngredientsIngredient>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct ScaleRecipeErrorResponse {
+    #[schema(example = "some error")]
+    err: String,
+}
+
+async fn respond(state: AppState, request: ScaleRecipeRequest) -> Response {
+    if request.target_servings <= 0.0 {
+        return (
+            StatusCode::BAD_REQUEST, 
+            Json(ScaleRecipeErrorResponse { err: "target_servings must be greater than 0".to_string() }),
+        ).into_response();
+    }
+
+    let recipe = match recipe::get_recipe(state.redis_recipes, request.id).await {
+        Err(err) => return (
+            StatusCode::BAD_REQUEST, 
+            Json(ScaleRecipeErrorResponse { err: err.to_string() }),
+        ).into_response(),
+        Ok(recipe) => recipe,
+    };
+
+    let Some(servings) = recipe.servings_count() else {
+        return (
+            StatusCode::BAD_REQUEST, 
+            Json(ScaleRecipeErrorResponse { err: format!("Could not get a number of servings from '{}'", recipe.servings.unwrap_or_default()) }),
+        ).into_response();
+    };
+
+    let scale = request.target_servings / servings;
+
+    match parse_ingredients::parse(recipe.ingredients, scale as f64, None) {
+        Err(err) => (
+            StatusCode::BAD_REQUEST, 
+            Json(ScaleRecipeErrorResponse { err }),
+        ).into_response(),
+
+        Ok(ingredients) => (
+            StatusCode::OK,
+            Json(ScaleRecipeSuccessResponse { servings, target_servings: request.target_servings, scale, ingredients }),
+        ).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/scale_recipe",
+    description = "Parse a recipe's ingredient list, with every amount scaled so that the recipe makes the target number of servings.",
+    responses(
+        (status = OK, body = ScaleRecipeSuccessResponse),
+        (status = BAD_REQUEST, body = ScaleRecipeErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn scale_recipe(
+    State(state): State<AppState>, 
+    Json(request): Json<ScaleRecipeRequest>
+) -> impl IntoResponse {
+    respond(state, request).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/recipe/{id}/scale",
+    description = "Parse a recipe's ingredient list, with every amount scaled so that the recipe makes the target number of servings.",
+    params(
+        ("id" = u64, Path, description = "Recipe id", example = 54),
+        ScaleRecipeQuery
+    ),
+    responses(
+        (status = OK, body = ScaleRecipeSuccessResponse),
+        (status = BAD_REQUEST, body = ScaleRecipeErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn scale_recipe_by_id(
+    State(state): State<AppState>, 
+    Path(id): Path<u64>,
+    Query(query): Query<ScaleRecipeQuery>
+) -> impl IntoResponse {
+    respond(state, ScaleRecipeRequest { id, target_servings: query.target_servings }).await
+}
+
+#[cfg(test)]
+mod tests {
+    use recipe_common::{recipe::Recipe, test_redis};
+
+    use super::*;
+
+    const INGREDIENTS: [&str; 3] = ["200g flour", "2-3 eggs", "1 cup milk"];
+
+    /// The scaled amount of each ingredient
+    fn scaled(scale: f64) -> Vec<String> {
+        parse_ingredients::parse(INGREDIENTS.iter().map(|v| v.to_string()).collect(), scale, None)
+            .unwrap()
+            .into_iter()
+            .map(|v| serde_json::to_value(v).unwrap()["amounts"][0]["raw"].as_str().unwrap().to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn doubling_doubles_every_amount() {
+        assert_eq!(scaled(2.0), vec!["400 g", "4 - 6 whole", "2 cups"]);
+    }
+
+    #[test]
+    fn halving_halves_every_amount() {
+        assert_eq!(scaled(0.5), vec!["100 g", "1 - 1.5 whole", "0.5 cups"]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn recipes_with_a_range_of_servings_are_scaled_from_the_lower_end() {
+        let (_guard, redis) = test_redis::connection().await;
+        let recipe = Recipe {
+            link: "https://example.com/pancakes".to_string(),
+            title: "Pancakes".to_string(),
+            servings: Some("2-3 servings".to_string()),
+            ingredients: INGREDIENTS.iter().map(|v| v.to_string()).collect(),
+            ..Recipe::default()
+        };
+        recipe::add(redis.clone(), recipe).await.unwrap();
+        let (_, _, ids) = recipe::list_recipes(redis.clone(), 0, 10).await.unwrap();
+
+        let response = respond(AppState::for_tests(redis), ScaleRecipeRequest { id: ids[0], target_servings: 4.0 }).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!((&body["servings"], &body["scale"]), (&serde_json::json!(2.0), &serde_json::json!(2.0)));
+        let amounts: Vec<&str> = body["ingredients"].as_array().unwrap().iter()
+            .map(|v| v["amounts"][0]["raw"].as_str().unwrap())
+            .collect();
+        assert_eq!(amounts, vec!["400 g", "4 - 6 whole", "2 cups"]);
+    }
+}
@@ -1,16 +1,99 @@
 use std::collections::HashSet;
 
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
-use recipe_common::recipe::get_recipes_by_term;
+use anyhow::Error;
+use axum::{extract::State, http::StatusCode, response::{IntoResponse, Response}, Json};
+use axum_extra::extract::Query;
+use recipe_common::recipe::{get_field_value, get_recipes, get_recipes_by_tags, get_recipes_fields, normalize_tag, rank_recipes_by_terms, Recipe, RecipeField};
 use serde::{Deserialize, Serialize};
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 
 use crate::AppState;
 
+/// Recipes are large, so only this many are included in a response, even if the limit is higher
+const MAX_INCLUDED_RECIPES: usize = 50;
+
+fn limit_default() -> usize {
+    20
+}
+
 #[derive(Debug, Deserialize, ToSchema)]
 pub struct SearchRequest {
     #[schema(example = json!(["vegetarian", "aubergine", "cake"]))]
     terms: Vec<String>,
+    #[serde(default)]
+    #[schema(default = 0)]
+    offset: usize,
+    #[serde(default = "limit_default")]
+    #[schema(default = 20)]
+    limit: usize,
+    #[schema(example = 4.0)]
+    min_rating: Option<f32>,
+    #[schema(example = 3600)]
+    max_total_time_seconds: Option<u64>,
+    #[serde(default)]
+    #[schema(example = json!(["vegan", "nut free"]))]
+    tags: Vec<String>,
+    /// Also return the full recipes (for at most the first 50 ids)
+    #[serde(default)]
+    #[schema(default = false)]
+    include_recipes: bool,
+}
+
+/// Query string version of `SearchRequest`, where terms and tags are given as repeated
+/// parameters (eg ?term=aubergine&term=cake&tag=vegan)
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct SearchQuery {
+    #[serde(default, rename = "term")]
+    #[param(rename = "term", example = json!(["aubergine", "cake"]))]
+    terms: Vec<String>,
+    #[serde(default)]
+    #[param(default = 0)]
+    offset: usize,
+    #[serde(default = "limit_default")]
+    #[param(default = 20)]
+    limit: usize,
+    #[param(example = 4.0)]
+    min_rating: Option<f32>,
+    #[param(example = 3600)]
+    max_total_time_seconds: Option<u64>,
+    #[serde(default, rename = "tag")]
+    #[param(rename = "tag", example = json!(["vegan"]))]
+    tags: Vec<String>,
+    /// Also return the full recipes (for at most the first 50 ids)
+    #[serde(default)]
+    #[param(default = false)]
+    include_recipes: bool,
+}
+
+impl From<SearchQuery> for SearchRequest {
+    fn from(query: SearchQuery) -> Self {
+        SearchRequest {
+            terms: query.terms,
+            offset: query.offset,
+            limit: query.limit,
+            min_rating: query.min_rating,
+            max_total_time_seconds: query.max_total_time_seconds,
+            tags: query.tags,
+            include_recipes: query.include_recipes,
+        }
+    }
+}
+
+impl SearchRequest {
+    fn has_filters(&self) -> bool {
+        self.min_rating.is_some() || self.max_total_time_seconds.is_some() || !self.tags.is_empty()
+    }
+
+    fn filter_fields(&self) -> Vec<RecipeField> {
+        let mut fields = vec![];
+        if self.min_rating.is_some() {
+            fields.push(RecipeField::Rating);
+        }
+        if self.max_total_time_seconds.is_some() {
+            fields.push(RecipeField::TotalTimeSeconds);
+        }
+        fields
+    }
 }
 
 #[derive(Debug, Serialize, ToSchema)]
@@ -21,13 +104,94 @@ struct SearchErrorResponse {
 
 #[derive(Debug, Serialize, ToSchema)]
 struct SearchSuccessResponse {
+    total: usize,
     recipe_ids: Vec<usize>,
+    /// The recipes with the first (at most 50) of `recipe_ids`, in the same order, if requested
+    #[serde(skip_serializing_if = "Option::is_none")]
+    recipes: Option<Vec<Recipe>>,
+}
+
+/// Recipes missing a field that is being filtered on are excluded
+async fn filter(state: &AppState, request: &SearchRequest, ids: Vec<usize>) -> Result<Vec<usize>, Error> {
+    let fields = request.filter_fields();
+    let ids_u64: Vec<u64> = ids.iter().map(|v| *v as u64).collect();
+    let values = get_recipes_fields(state.redis_recipes.clone(), &ids_u64, &fields).await?;
+
+    let tagged = if request.tags.is_empty() {
+        None
+    } else {
+        // an unknown tag can't match any recipe
+        let tags: Option<Vec<&str>> = request.tags.iter()
+            .map(|v| normalize_tag(v))
+            .collect();
+        match tags {
+            Some(tags) => Some(get_recipes_by_tags(state.redis_recipes.clone(), &tags).await?),
+            None => Some(HashSet::new()),
+        }
+    };
+
+    let mut filtered = vec![];
+    for (id, values) in ids.into_iter().zip(values) {
+        if let Some(min_rating) = request.min_rating {
+            let rating: Option<f32> = get_field_value(&values, RecipeField::Rating)?;
+            if rating.is_none_or(|v| v < min_rating) {
+                continue;
+            }
+        }
+
+        if let Some(max_total_time_seconds) = request.max_total_time_seconds {
+            let total_time_seconds: Option<u64> = get_field_value(&values, RecipeField::TotalTimeSeconds)?;
+            if total_time_seconds.is_none_or(|v| v > max_total_time_seconds) {
+                continue;
+            }
+        }
+
+        if tagged.as_ref().is_some_and(|tagged| !tagged.contains(&id)) {
+            continue;
+        }
+
+        filtered.push(id);
+    }
+
+    Ok(filtered)
+}
+
+async fn respond(state: AppState, request: SearchRequest) -> Response {
+    let mut recipe_ids = rank_recipes_by_terms(state.redis_recipes.clone(), &request.terms).await;
+
+    if request.has_filters() {
+        recipe_ids = match filter(&state, &request, recipe_ids).await {
+            Err(err) => return (StatusCode::BAD_REQUEST, Json(SearchErrorResponse { err: err.to_string() })).into_response(),
+            Ok(recipe_ids) => recipe_ids,
+        };
+    }
+
+    let total = recipe_ids.len();
+    let recipe_ids: Vec<usize> = recipe_ids.into_iter()
+        .skip(request.offset)
+        .take(request.limit)
+        .collect();
+
+    let recipes = if request.include_recipes {
+        let ids: Vec<u64> = recipe_ids.iter()
+            .take(MAX_INCLUDED_RECIPES)
+            .map(|v| *v as u64)
+            .collect();
+        match get_recipes(state.redis_recipes.clone(), &ids).await {
+            Err(err) => return (StatusCode::BAD_REQUEST, Json(SearchErrorResponse { err: err.to_string() })).into_response(),
+            Ok(recipes) => Some(recipes),
+        }
+    } else {
+        None
+    };
+
+    (StatusCode::OK, Json(SearchSuccessResponse { total, recipe_ids, recipes })).into_response()
 }
 
 #[utoipa::path(
     post,
     path = "/search",
-    description = "Search recipes.",
+    description = "Search recipes. Results are ranked by the terms each recipe matches, with rarer terms weighted more heavily, and can be filtered by minimum rating, maximum total time, and dietary tags such as 'vegan' or 'gluten free' (all of which must match).",
     responses(
         (status = OK, body = SearchSuccessResponse),
         (status = BAD_REQUEST, body = SearchErrorResponse)
@@ -38,10 +202,118 @@ pub async fn search(
     State(state): State<AppState>,
     Json(request): Json<SearchRequest>,
 ) -> impl IntoResponse {
-    let mut recipes = HashSet::<usize>::new();
-    for term in request.terms {
-        recipes.extend(&get_recipes_by_term(state.redis_recipes.clone(), &term).await)
-    }
-    (StatusCode::OK, Json(recipes)).into_response()
+    respond(state, request).await
 }
 
+#[utoipa::path(
+    get,
+    path = "/search",
+    description = "Search recipes, with the same options as POST /search given as query parameters.",
+    params(SearchQuery),
+    responses(
+        (status = OK, body = SearchSuccessResponse),
+        (status = BAD_REQUEST, body = SearchErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn search_by_query(
+    State(state): State<AppState>,
+    Query(query): Query<SearchQuery>,
+) -> impl IntoResponse {
+    respond(state, query.into()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use recipe_common::{recipe, test_redis};
+
+    use super::*;
+
+    /// Searches for 'soup' with the filters in `request` among a tomato soup (id 1, rated 4.5,
+    /// 30 minutes), a chicken soup (id 2, rated 4.8, 2 hours), and a lentil soup (id 3, rated 3,
+    /// 40 minutes), returning the ids found
+    async fn search(request: serde_json::Value) -> Vec<usize> {
+        let (_guard, redis) = test_redis::connection().await;
+        let soups = [
+            ("Tomato soup", "tomatoes", 4.5, 1800),
+            ("Chicken soup", "chicken thighs", 4.8, 7200),
+            ("Lentil soup", "red lentils", 3.0, 2400),
+        ];
+        for (title, ingredient, rating, total_time_seconds) in soups {
+            let recipe = Recipe {
+                link: format!("https://example.com/{}", title.to_lowercase().replace(' ', "-")),
+                title: title.to_string(),
+                ingredients: vec![ingredient.to_string(), "1 litre water".to_string()],
+                rating: Some(rating),
+                total_time_seconds: Some(total_time_seconds),
+                ..Recipe::default()
+            };
+            recipe::add(redis.clone(), recipe).await.unwrap();
+        }
+
+        let mut request = request;
+        request["terms"] = serde_json::json!(["soup"]);
+        let response = respond(AppState::for_tests(redis), serde_json::from_value(request).unwrap()).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        serde_json::from_value(body["recipe_ids"].clone()).unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn search_without_filters_finds_every_match() {
+        assert_eq!(search(serde_json::json!({})).await, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn search_filters_by_time_limit() {
+        assert_eq!(search(serde_json::json!({ "max_total_time_seconds": 3600 })).await, vec![1, 3]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn search_filters_by_rating() {
+        assert_eq!(search(serde_json::json!({ "min_rating": 4.0 })).await, vec![1, 2]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn search_excludes_recipes_with_meat_ingredients_when_vegetarian() {
+        assert_eq!(search(serde_json::json!({ "tags": ["Vegetarian"] })).await, vec![1, 3]);
+        assert!(search(serde_json::json!({ "tags": ["not a tag"] })).await.is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn search_filters_must_all_match() {
+        let request = serde_json::json!({ "min_rating": 4.0, "max_total_time_seconds": 3600, "tags": ["vegan"] });
+        assert_eq!(search(request).await, vec![1]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn included_recipes_match_the_ids() {
+        let (_guard, redis) = test_redis::connection().await;
+        for title in ["Tomato soup", "Tomato salad", "Tomato pasta"] {
+            let recipe = Recipe { link: format!("https://example.com/{title}"), title: title.to_string(), ..Recipe::default() };
+            recipe::add(redis.clone(), recipe).await.unwrap();
+        }
+
+        let mut bodies = vec![];
+        for include_recipes in [false, true] {
+            let request = serde_json::json!({ "terms": ["tomato"], "limit": 2, "include_recipes": include_recipes });
+            let response = respond(AppState::for_tests(redis.clone()), serde_json::from_value(request).unwrap()).await;
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            bodies.push(serde_json::from_slice::<serde_json::Value>(&body).unwrap());
+        }
+
+        assert_eq!((&bodies[0]["total"], &bodies[0]["recipe_ids"]), (&bodies[1]["total"], &bodies[1]["recipe_ids"]));
+        assert!(bodies[0].get("recipes").is_none());
+        let titles: Vec<&str> = bodies[1]["recipes"].as_array().unwrap().iter()
+            .map(|v| v["title"].as_str().unwrap())
+            .collect();
+        assert_eq!(titles, vec!["Tomato soup", "Tomato salad"]);
+    }
+}
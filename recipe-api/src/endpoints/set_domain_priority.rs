@@ -0,0 +1,52 @@
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use recipe_common::link;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::AppState;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SetDomainPriorityRequest {
+    #[schema(example = "www.indianhealthyrecipes.com")]
+    domain: String,
+    #[schema(example = 1.0)]
+    priority: f32,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct SetDomainPrioritySuccessResponse {
+    updated: usize,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct SetDomainPriorityErrorResponse {
+    #[schema(example = "some error")]
+    err: String,
+}
+
+#[utoipa::path(
+    post,
+    path = "/set_domain_priority",
+    description = "Set the priority of every waiting link on a domain, returning how many links were updated.",
+    responses(
+        (status = OK, body = SetDomainPrioritySuccessResponse),
+        (status = BAD_REQUEST, body = SetDomainPriorityErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn set_domain_priority(
+    State(state): State<AppState>, 
+    Json(request): Json<SetDomainPriorityRequest>
+) -> impl IntoResponse {
+    match link::set_domain_priority(state.redis_links, &request.domain, request.priority).await {
+        Err(err) => (
+            StatusCode::BAD_REQUEST, 
+            Json(SetDomainPriorityErrorResponse { err: err.to_string() }),
+        ).into_response(),
+
+        Ok(updated) => (
+            StatusCode::OK,
+            Json(SetDomainPrioritySuccessResponse { updated }),
+        ).into_response(),
+    }
+}
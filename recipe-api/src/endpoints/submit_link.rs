@@ -1,18 +1,39 @@
 use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
-use recipe_common::link::{self};
+use recipe_common::{address, link};
 use serde::{Deserialize, Serialize};
+use url::Url;
 use utoipa::ToSchema;
 
 use crate::AppState;
 
-fn priority_default() -> f32 {
+pub(crate) fn priority_default() -> f32 {
     0.0
 }
 
-fn remaining_follows_default() -> i32 {
+pub(crate) fn remaining_follows_default() -> i32 {
     2
 }
 
+pub(crate) fn follow_default() -> bool {
+    true
+}
+
+/// Rejects links we should never crawl, with a message saying why
+pub(crate) fn validate(link: &str) -> Result<(), String> {
+    let url = Url::parse(link)
+        .map_err(|err| format!("Invalid URL '{link}': {err}"))?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(format!("Unsupported scheme '{}' (expected http or https)", url.scheme()));
+    }
+    let Some(host) = url.host() else {
+        return Err(format!("URL '{link}' has no host"));
+    };
+    if address::is_blocked_host(&host) {
+        return Err(format!("Host '{host}' is a local or private address"));
+    }
+    Ok(())
+}
+
 #[derive(Debug, Deserialize, ToSchema)]
 pub struct SubmitLinkRequest {
     #[schema(example = "https://www.indianhealthyrecipes.com/cauliflower-curry-recipe/")]
@@ -23,6 +44,10 @@ pub struct SubmitLinkRequest {
     #[serde(default = "remaining_follows_default")]
     #[schema(default = 2)]
     remaining_follows: i32,
+    /// If false, only this page is downloaded and none of its links are followed
+    #[serde(default = "follow_default")]
+    #[schema(default = true)]
+    follow: bool,
 }
 
 #[derive(Debug, Serialize, ToSchema)]
@@ -39,7 +64,7 @@ struct SubmitLinkErrorResponse {
 #[utoipa::path(
     post,
     path = "/submit_link",
-    description = "Add a link to the waiting queue, with an optional priority and optional remaining follows (how deep we should follow any links on the page)",
+    description = "Add a link to the waiting queue, with an optional priority and optional remaining follows (how deep we should follow any links on the page). Set follow to false to only download the page itself. Only http(s) links to public hosts are accepted.",
     responses(
         (status = OK, body = SubmitLinkSuccessResponse),
         (status = BAD_REQUEST, body = SubmitLinkErrorResponse)
@@ -50,7 +75,14 @@ pub async fn submit_link(
     State(state): State<AppState>, 
     Json(request): Json<SubmitLinkRequest>
 ) -> impl IntoResponse {
-    match link::add(state.redis_links, &request.link, None, request.priority, request.remaining_follows).await {
+    if let Err(err) = validate(&request.link) {
+        return (
+            StatusCode::BAD_REQUEST, 
+            Json(SubmitLinkErrorResponse { err }),
+        ).into_response();
+    }
+
+    match link::add(state.redis_links, &request.link, None, request.priority, request.remaining_follows, request.follow, None).await {
         Err(err) => (
             StatusCode::BAD_REQUEST, 
             Json(SubmitLinkErrorResponse { err: err.to_string() }),
@@ -63,3 +95,34 @@ pub async fn submit_link(
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn public_http_links_are_valid() {
+        assert!(validate("https://www.example.com/pancakes").is_ok());
+        assert!(validate("http://example.com/").is_ok());
+    }
+
+    #[test]
+    fn file_links_are_rejected() {
+        let err = validate("file:///etc/passwd").unwrap_err();
+        assert!(err.contains("Unsupported scheme 'file'"), "{err}");
+    }
+
+    #[test]
+    fn schemeless_links_are_rejected() {
+        let err = validate("www.example.com/pancakes").unwrap_err();
+        assert!(err.starts_with("Invalid URL 'www.example.com/pancakes'"), "{err}");
+    }
+
+    #[test]
+    fn local_links_are_rejected() {
+        let err = validate("http://127.0.0.1/admin").unwrap_err();
+        assert!(err.contains("local or private"), "{err}");
+
+        assert!(validate("http://localhost:8080/").is_err());
+        assert!(validate("http://192.168.1.1/").is_err());
+    }
+}
@@ -0,0 +1,107 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use recipe_common::link;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::Semaphore, task::JoinSet};
+use utoipa::ToSchema;
+
+use crate::AppState;
+
+use super::submit_link::{self, follow_default, priority_default, remaining_follows_default};
+
+const MAX_CONCURRENT_ADDS: usize = 64;
+pub(crate) const MAX_LINKS: usize = 10_000;
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SubmitLinksEntry {
+    #[schema(example = "https://www.indianhealthyrecipes.com/cauliflower-curry-recipe/")]
+    link: String,
+    #[serde(default = "priority_default")]
+    #[schema(default = 0.0)]
+    priority: f32,
+    #[serde(default = "remaining_follows_default")]
+    #[schema(default = 2)]
+    remaining_follows: i32,
+    /// If false, only this page is downloaded and none of its links are followed
+    #[serde(default = "follow_default")]
+    #[schema(default = true)]
+    follow: bool,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct SubmitLinksRequest {
+    links: Vec<SubmitLinksEntry>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct SubmitLinksErrorResponse {
+    #[schema(example = "some error")]
+    err: String,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct SubmitLinksResult {
+    link: String,
+    added: bool,
+    #[schema(example = "some error")]
+    err: Option<String>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/submit_links",
+    description = "Add up to 10000 links to the waiting queue at once. Returns whether each link was added, in the same order as the request. Links on domains that already have as many waiting links as the finder allows are not added.",
+    responses(
+        (status = OK, body = Vec<SubmitLinksResult>),
+        (status = BAD_REQUEST, body = SubmitLinksErrorResponse),
+        (status = INTERNAL_SERVER_ERROR, body = SubmitLinksErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state, request))]
+pub async fn submit_links(
+    State(state): State<AppState>, 
+    Json(request): Json<SubmitLinksRequest>
+) -> impl IntoResponse {
+    if request.links.len() > MAX_LINKS {
+        return (
+            StatusCode::BAD_REQUEST, 
+            Json(SubmitLinksErrorResponse { err: format!("Too many links ({}, the maximum is {MAX_LINKS})", request.links.len()) }),
+        ).into_response();
+    }
+
+    let max_domain_waiting_links = match link::get_max_domain_waiting_links(state.redis_links.clone()).await {
+        Ok(max_domain_waiting_links) => max_domain_waiting_links,
+        Err(err) => return (
+            StatusCode::INTERNAL_SERVER_ERROR, 
+            Json(SubmitLinksErrorResponse { err: err.to_string() }),
+        ).into_response(),
+    };
+
+    let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_ADDS));
+
+    let mut futures = JoinSet::new();
+    for (index, entry) in request.links.into_iter().enumerate() {
+        let redis_links = state.redis_links.clone();
+        let semaphore = semaphore.clone();
+        futures.spawn(async move {
+            let _permit = semaphore.acquire().await.unwrap();
+            if let Err(err) = submit_link::validate(&entry.link) {
+                return (index, SubmitLinksResult { link: entry.link, added: false, err: Some(err) });
+            }
+            let result = match link::add(redis_links, &entry.link, None, entry.priority, entry.remaining_follows, entry.follow, max_domain_waiting_links).await {
+                Err(err) => SubmitLinksResult { link: entry.link, added: false, err: Some(err.to_string()) },
+                Ok(added) => SubmitLinksResult { link: entry.link, added, err: None },
+            };
+            (index, result)
+        });
+    }
+
+    let mut results = futures.join_all().await;
+    results.sort_by_key(|(index, _)| *index);
+    let results: Vec<SubmitLinksResult> = results.into_iter()
+        .map(|(_, result)| result)
+        .collect();
+
+    (StatusCode::OK, Json(results)).into_response()
+}
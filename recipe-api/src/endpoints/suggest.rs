@@ -0,0 +1,87 @@
+use axum::{extract::State, http::StatusCode, response::{IntoResponse, Response}, Json};
+use axum_extra::extract::Query;
+use recipe_common::recipe;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::AppState;
+
+fn limit_default() -> usize {
+    10
+}
+
+#[derive(Debug, Deserialize, ToSchema, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SuggestRequest {
+    #[schema(example = "panc")]
+    #[param(example = "panc")]
+    prefix: String,
+    #[serde(default = "limit_default")]
+    #[schema(default = 10)]
+    #[param(default = 10)]
+    limit: usize,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct Suggestion {
+    #[schema(example = "pancake")]
+    term: String,
+    recipe_count: usize,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct SuggestErrorResponse {
+    #[schema(example = "some error")]
+    err: String,
+}
+
+async fn respond(state: AppState, request: SuggestRequest) -> Response {
+    match recipe::suggest_terms(state.redis_recipes, &request.prefix, request.limit).await {
+        Err(err) => (
+            StatusCode::BAD_REQUEST, 
+            Json(SuggestErrorResponse { err: err.to_string() }),
+        ).into_response(),
+
+        Ok(suggestions) => {
+            let suggestions: Vec<Suggestion> = suggestions.into_iter()
+                .map(|(term, recipe_count)| Suggestion { term, recipe_count })
+                .collect();
+            (StatusCode::OK, Json(suggestions)).into_response()
+        }
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/suggest",
+    description = "Get search terms starting with a prefix (in alphabetical order), along with how many recipes have each term. Only terms with at least one recipe are returned.",
+    responses(
+        (status = OK, body = Vec<Suggestion>),
+        (status = BAD_REQUEST, body = SuggestErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn suggest(
+    State(state): State<AppState>, 
+    Json(request): Json<SuggestRequest>
+) -> impl IntoResponse {
+    respond(state, request).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/suggest",
+    description = "Get search terms starting with a prefix (in alphabetical order), along with how many recipes have each term, with the same options as POST /suggest given as query parameters.",
+    params(SuggestRequest),
+    responses(
+        (status = OK, body = Vec<Suggestion>),
+        (status = BAD_REQUEST, body = SuggestErrorResponse)
+    ),
+)]
+#[tracing::instrument(skip(state))]
+pub async fn suggest_by_query(
+    State(state): State<AppState>, 
+    Query(request): Query<SuggestRequest>
+) -> impl IntoResponse {
+    respond(state, request).await
+}
@@ -1,23 +1,73 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::Router;
 use clap::Parser;
-use endpoints::get_links::get_links;
-use endpoints::get_recipe::get_recipe;
-use endpoints::search::search;
-use endpoints::{parse_ingredients::parse_ingredients, submit_link::submit_link};
+use endpoints::crawl_stats::crawl_stats;
+use endpoints::domain_stats::{domain_stats, domain_stats_by_query};
+use endpoints::export_jsonld::{export_jsonld, export_jsonld_by_id};
+use endpoints::export_markdown::{export_markdown, export_markdown_by_id};
+use endpoints::extract_schema::extract_schema;
+use endpoints::get_links::{get_links, get_links_by_query};
+use endpoints::get_recipe::{get_recipe, get_recipe_by_id};
+use endpoints::health::health;
+use endpoints::keyword_frequencies::{keyword_frequencies, keyword_frequencies_by_query};
+use endpoints::link_graph::{link_graph, link_graph_by_query};
+use endpoints::list_recipes::{list_recipes, list_recipes_by_query};
+use endpoints::metrics::metrics;
+use endpoints::nutrition_per_serving::{nutrition_per_serving, nutrition_per_serving_by_id};
+use endpoints::nutrition_sum::{nutrition_sum, nutrition_sum_by_query};
+use endpoints::preview::preview;
+use endpoints::ready::ready;
+use endpoints::recipe_cards::{recipe_cards, recipe_cards_by_query};
+use endpoints::reindex::reindex;
+use endpoints::requeue_failed::requeue_failed;
+use endpoints::scale_recipe::{scale_recipe, scale_recipe_by_id};
+use endpoints::search::{search, search_by_query};
+use endpoints::set_domain_priority::set_domain_priority;
+use endpoints::submit_links::submit_links;
+use endpoints::suggest::{suggest, suggest_by_query};
+use endpoints::{parse_ingredients::{parse_ingredients, parse_ingredients_by_id}, submit_link::submit_link};
 use log::info;
+use recipe_common::address::AddressGuard;
+use recipe_common::download::{self, HeaderConfig};
+use recipe_common::logging::{self, LogFormat};
 use redis::aio::MultiplexedConnection;
+use reqwest::{Client, ClientBuilder};
 use tokio::net::TcpListener;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
 use utoipa::OpenApi;
 use utoipa_axum::{router::OpenApiRouter, routes};
 use utoipa_redoc::{Redoc, Servable};
-use crate::endpoints::get_links::__path_get_links;
-use crate::endpoints::get_recipe::__path_get_recipe;
-use crate::endpoints::parse_ingredients::__path_parse_ingredients;
-use crate::endpoints::search::__path_search;
+use crate::endpoints::crawl_stats::__path_crawl_stats;
+use crate::endpoints::domain_stats::{__path_domain_stats, __path_domain_stats_by_query};
+use crate::endpoints::export_jsonld::{__path_export_jsonld, __path_export_jsonld_by_id};
+use crate::endpoints::export_markdown::{__path_export_markdown, __path_export_markdown_by_id};
+use crate::endpoints::extract_schema::__path_extract_schema;
+use crate::endpoints::get_links::{__path_get_links, __path_get_links_by_query};
+use crate::endpoints::get_recipe::{__path_get_recipe, __path_get_recipe_by_id};
+use crate::endpoints::health::__path_health;
+use crate::endpoints::keyword_frequencies::{__path_keyword_frequencies, __path_keyword_frequencies_by_query};
+use crate::endpoints::link_graph::{__path_link_graph, __path_link_graph_by_query};
+use crate::endpoints::list_recipes::{__path_list_recipes, __path_list_recipes_by_query};
+use crate::endpoints::metrics::__path_metrics;
+use crate::endpoints::nutrition_per_serving::{__path_nutrition_per_serving, __path_nutrition_per_serving_by_id};
+use crate::endpoints::nutrition_sum::{__path_nutrition_sum, __path_nutrition_sum_by_query};
+use crate::endpoints::parse_ingredients::{__path_parse_ingredients, __path_parse_ingredients_by_id};
+use crate::endpoints::preview::__path_preview;
+use crate::endpoints::ready::__path_ready;
+use crate::endpoints::recipe_cards::{__path_recipe_cards, __path_recipe_cards_by_query};
+use crate::endpoints::reindex::__path_reindex;
+use crate::endpoints::requeue_failed::__path_requeue_failed;
+use crate::endpoints::scale_recipe::{__path_scale_recipe, __path_scale_recipe_by_id};
+use crate::endpoints::search::{__path_search, __path_search_by_query};
+use crate::endpoints::set_domain_priority::__path_set_domain_priority;
 use crate::endpoints::submit_link::__path_submit_link;
+use crate::endpoints::submit_links::__path_submit_links;
+use crate::endpoints::suggest::{__path_suggest, __path_suggest_by_query};
 
 pub mod endpoints;
 
+const PREVIEW_DOWNLOAD_TIMEOUT_SECS: u64 = 30;
+
 #[derive(Parser, Debug)]
 struct Args {
     #[arg(long)]
@@ -26,6 +76,15 @@ struct Args {
     redis_links_url: String,
     #[arg(long)]
     redis_recipes_url: String,
+    /// 'pretty' or 'json'
+    #[arg(long, default_value = "pretty")]
+    log_format: LogFormat,
+    /// User agent to identify as when downloading pages to preview
+    #[arg(long, default_value = download::USER_AGENT, value_parser = download::parse_header_value)]
+    user_agent: String,
+    /// Accept-Language header to send when downloading pages to preview
+    #[arg(long, default_value = download::ACCEPT_LANGUAGE, value_parser = download::parse_header_value)]
+    accept_language: String,
 }
 
 #[derive(Debug, Clone)]
@@ -34,22 +93,81 @@ pub struct AppState {
     redis_links: MultiplexedConnection,
     #[allow(unused)]
     redis_recipes: MultiplexedConnection,
+    /// For downloading pages to preview
+    client: Client,
+    headers: HeaderConfig,
+}
+
+#[cfg(test)]
+impl AppState {
+    /// State for tests, with both databases on the one connection
+    pub fn for_tests(redis: MultiplexedConnection) -> Self {
+        AppState {
+            redis_links: redis.clone(),
+            redis_recipes: redis,
+            client: Client::new(),
+            headers: HeaderConfig::default(),
+        }
+    }
+}
+
+#[derive(OpenApi)]
+struct ApiDocs;
+
+/// The API is versioned under /api/v1, but the probes and metrics are at the root where
+/// orchestrators and Prometheus expect them
+fn router(state: AppState) -> Router {
+    let api_router = OpenApiRouter::new()
+        .routes(routes!(crawl_stats))
+        .routes(routes!(domain_stats, domain_stats_by_query))
+        .routes(routes!(export_jsonld))
+        .routes(routes!(export_jsonld_by_id))
+        .routes(routes!(export_markdown))
+        .routes(routes!(export_markdown_by_id))
+        .routes(routes!(extract_schema))
+        .routes(routes!(get_links, get_links_by_query))
+        .routes(routes!(get_recipe))
+        .routes(routes!(get_recipe_by_id))
+        .routes(routes!(keyword_frequencies, keyword_frequencies_by_query))
+        .routes(routes!(link_graph, link_graph_by_query))
+        .routes(routes!(list_recipes, list_recipes_by_query))
+        .routes(routes!(nutrition_per_serving))
+        .routes(routes!(nutrition_per_serving_by_id))
+        .routes(routes!(nutrition_sum, nutrition_sum_by_query))
+        .routes(routes!(parse_ingredients))
+        .routes(routes!(parse_ingredients_by_id))
+        .routes(routes!(preview))
+        .routes(routes!(recipe_cards, recipe_cards_by_query))
+        .routes(routes!(reindex))
+        .routes(routes!(requeue_failed))
+        .routes(routes!(scale_recipe))
+        .routes(routes!(scale_recipe_by_id))
+        .routes(routes!(search, search_by_query))
+        .routes(routes!(set_domain_priority))
+        .routes(routes!(submit_link))
+        .routes(routes!(submit_links))
+        .routes(routes!(suggest, suggest_by_query))
+        .with_state(state.clone());
+
+    let (router, api) = OpenApiRouter::with_openapi(ApiDocs::openapi())
+        .routes(routes!(health))
+        .routes(routes!(metrics))
+        .routes(routes!(ready))
+        .with_state(state)
+        .nest("/api/v1", api_router)
+        .split_for_parts();
+
+    router.merge(Redoc::with_url("/docs", api))
 }
 
 #[tokio::main]
 async fn main() {
-    let fmt_layer = tracing_subscriber::fmt::layer()
-        .with_line_number(true)
-        .with_filter(EnvFilter::new("recipe_api=trace,recipe_common=trace,axum=trace"));
+    let args = Args::parse();
 
-    tracing_subscriber::registry()
-        .with(fmt_layer)
-        .init();
+    logging::init("recipe_api=trace,recipe_common=trace,axum=trace", args.log_format);
 
     info!("Starting...");
 
-    let args = Args::parse();
-
     let redis_links = redis::Client::open(args.redis_links_url)
         .unwrap()
         .get_multiplexed_tokio_connection()
@@ -62,30 +180,307 @@ async fn main() {
         .await
         .unwrap();
 
+    // previews download whatever link they are given, so must not be able to reach internal services
+    let address_guard = AddressGuard::default();
+    let client = ClientBuilder::new()
+        .gzip(true)
+        .brotli(true)
+        .deflate(true)
+        .timeout(Duration::from_secs(PREVIEW_DOWNLOAD_TIMEOUT_SECS))
+        .dns_resolver(Arc::new(address_guard.clone()))
+        .redirect(address_guard.redirect_policy(None))
+        .build()
+        .unwrap();
+
     let state = AppState {
         redis_links,
         redis_recipes,
+        client,
+        headers: HeaderConfig {
+            user_agent: args.user_agent,
+            accept_language: args.accept_language,
+            contact: None,
+        },
     };
 
-    let api_router = OpenApiRouter::new()
-        .routes(routes!(get_links))
-        .routes(routes!(get_recipe))
-        .routes(routes!(parse_ingredients))
-        .routes(routes!(search))
-        .routes(routes!(submit_link))
-        .with_state(state);
+    let listener = TcpListener::bind(format!("127.0.0.1:{}", args.port)).await.unwrap();
 
+    axum::serve(listener, router(state).into_make_service()).await.unwrap();
+}
 
-    #[derive(OpenApi)]
-    pub struct ApiDocs;
+#[cfg(test)]
+mod tests {
+    use recipe_common::{link::{self, LinkStatus}, metrics::{self as counters, Counter}, recipe::{self, Recipe}, test_redis};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-    let (main_router, api) = OpenApiRouter::with_openapi(ApiDocs::openapi())
-        .nest("/api/v1", api_router)
-        .split_for_parts();
-    let main_router = main_router.merge(Redoc::with_url("/docs", api.clone()));
+    use super::*;
 
-    let listener = TcpListener::bind(format!("127.0.0.1:{}", args.port)).await.unwrap();
+    /// Serves the whole router on a local port, returning its URL (eg http://127.0.0.1:1234)
+    async fn serve(state: AppState) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(listener, router(state).into_make_service()).await.unwrap();
+        });
+        url
+    }
 
-    axum::serve(listener, main_router.into_make_service()).await.unwrap();
-}
+    /// A connection to a "Redis" that answers every command with an error, as a database that
+    /// is up but not working would
+    async fn broken_redis() -> MultiplexedConnection {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("redis://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buffer = [0; 4096];
+            while let Ok(n) = stream.read(&mut buffer).await {
+                if n == 0 {
+                    return;
+                }
+                // every command is an array, eg '*1\r\n$4\r\nPING\r\n'
+                let commands = String::from_utf8_lossy(&buffer[..n])
+                    .split("\r\n")
+                    .filter(|v| v.starts_with('*'))
+                    .count();
+                let _ = stream.write_all("-ERR unavailable\r\n".repeat(commands).as_bytes()).await;
+            }
+        });
+
+        redis::Client::open(url)
+            .unwrap()
+            .get_multiplexed_tokio_connection()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn health_is_served_at_the_root() {
+        let url = serve(AppState::for_tests(broken_redis().await)).await;
+
+        let response = reqwest::get(format!("{url}/health")).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "");
+
+        let response = reqwest::get(format!("{url}/api/v1/health")).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    }
 
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn ready_succeeds_when_redis_is_reachable() {
+        let (_guard, redis) = test_redis::connection().await;
+        let url = serve(AppState::for_tests(redis)).await;
+
+        let response = reqwest::get(format!("{url}/ready")).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "");
+    }
+
+    #[tokio::test]
+    async fn ready_fails_when_redis_is_broken() {
+        let url = serve(AppState::for_tests(broken_redis().await)).await;
+
+        let response = reqwest::get(format!("{url}/ready")).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = serde_json::from_str(&response.text().await.unwrap()).unwrap();
+        assert!(body["err"].as_str().is_some_and(|err| err.contains("unavailable")), "{body}");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn metrics_are_served_at_the_root_in_prometheus_format() {
+        let (_guard, redis) = test_redis::connection().await;
+        link::add(redis.clone(), "https://example.com/pancakes", None, 0.0, 5, true, None).await.unwrap();
+        counters::increment(redis.clone(), Counter::Downloads).await.unwrap();
+        counters::increment(redis.clone(), Counter::Downloads).await.unwrap();
+        let recipe = Recipe {
+            link: "https://example.com/pancakes".to_string(),
+            title: "Pancakes".to_string(),
+            ..Recipe::default()
+        };
+        recipe::add(redis.clone(), recipe).await.unwrap();
+        let url = serve(AppState::for_tests(redis)).await;
+
+        let response = reqwest::get(format!("{url}/metrics")).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::OK);
+        assert!(response.headers()["content-type"].to_str().unwrap().starts_with("text/plain; version=0.0.4"));
+        let body = response.text().await.unwrap();
+        for line in [
+            "# TYPE recipe_links gauge",
+            "recipe_links{status=\"waiting\"} 1",
+            "recipe_links{status=\"processed\"} 0",
+            "recipe_count 1",
+            "# TYPE recipe_finder_stages_total counter",
+            "recipe_finder_stages_total{stage=\"downloads\"} 2",
+            "recipe_finder_stages_total{stage=\"parses\"} 0",
+        ] {
+            assert!(body.lines().any(|v| v == line), "missing '{line}' in:\n{body}");
+        }
+
+        let response = reqwest::get(format!("{url}/api/v1/metrics")).await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+    }
+
+    async fn post_json(url: &str, body: &serde_json::Value) -> (reqwest::StatusCode, serde_json::Value) {
+        let response = reqwest::Client::new()
+            .post(url)
+            .header("content-type", "application/json")
+            .body(body.to_string())
+            .send()
+            .await
+            .unwrap();
+        let status = response.status();
+        (status, serde_json::from_str(&response.text().await.unwrap()).unwrap())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn submit_links_reports_each_link_in_order() {
+        let (_guard, redis) = test_redis::connection().await;
+        link::add(redis.clone(), "https://example.com/pancakes", None, 0.0, 5, true, None).await.unwrap();
+        let url = serve(AppState::for_tests(redis.clone())).await;
+
+        let (status, body) = post_json(&format!("{url}/api/v1/submit_links"), &serde_json::json!({
+            "links": [
+                { "link": "https://example.com/waffles" },
+                { "link": "https://example.com/pancakes" },
+                { "link": "http://127.0.0.1/admin" },
+                { "link": "https://example.com/crepes", "priority": 2.0 },
+            ],
+        })).await;
+
+        assert_eq!(status, reqwest::StatusCode::OK);
+        let results: Vec<(&str, bool, bool)> = body.as_array().unwrap().iter()
+            .map(|v| (v["link"].as_str().unwrap(), v["added"].as_bool().unwrap(), v["err"].is_string()))
+            .collect();
+        assert_eq!(results, vec![
+            ("https://example.com/waffles", true, false),
+            ("https://example.com/pancakes", false, false),
+            ("http://127.0.0.1/admin", false, true),
+            ("https://example.com/crepes", true, false),
+        ]);
+        assert_eq!(link::get_priority(redis.clone(), "https://example.com/crepes").await.unwrap(), 2.0);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn submit_links_respects_max_domain_waiting_links() {
+        let (_guard, redis) = test_redis::connection().await;
+        link::set_max_domain_waiting_links(redis.clone(), Some(2)).await.unwrap();
+        link::add(redis.clone(), "https://example.com/pancakes", None, 1.0, 5, true, None).await.unwrap();
+        link::add(redis.clone(), "https://example.com/waffles", None, 1.0, 5, true, None).await.unwrap();
+        let url = serve(AppState::for_tests(redis.clone())).await;
+
+        let (status, body) = post_json(&format!("{url}/api/v1/submit_links"), &serde_json::json!({
+            "links": [
+                { "link": "https://example.com/crepes", "priority": 0.0 },
+                { "link": "https://other.com/crepes", "priority": 0.0 },
+            ],
+        })).await;
+
+        assert_eq!(status, reqwest::StatusCode::OK);
+        assert_eq!(body[0]["added"], false);
+        assert_eq!(body[1]["added"], true);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn requeue_failed_moves_failed_links_back_to_waiting() {
+        let (_guard, redis) = test_redis::connection().await;
+        for (page, status) in [("a", LinkStatus::DownloadFailed), ("b", LinkStatus::DownloadFailed), ("c", LinkStatus::Processing)] {
+            let link = format!("https://example.com/{page}");
+            link::add(redis.clone(), &link, None, 0.0, 5, true, None).await.unwrap();
+            link::update_status(redis.clone(), &link, LinkStatus::Processing).await.unwrap();
+            link::update_status(redis.clone(), &link, status).await.unwrap();
+        }
+        let url = serve(AppState::for_tests(redis.clone())).await;
+
+        let (status, body) = post_json(&format!("{url}/api/v1/requeue_failed"), &serde_json::json!({ "status": "download_failed" })).await;
+        assert_eq!(status, reqwest::StatusCode::OK);
+        assert_eq!(body, serde_json::json!({ "requeued": 2 }));
+        for page in ["a", "b"] {
+            assert_eq!(link::get_status(redis.clone(), &format!("https://example.com/{page}")).await.unwrap(), LinkStatus::Waiting);
+        }
+
+        let (status, body) = post_json(&format!("{url}/api/v1/requeue_failed"), &serde_json::json!({ "status": "processing" })).await;
+        assert_eq!(status, reqwest::StatusCode::BAD_REQUEST);
+        assert_eq!(body, serde_json::json!({ "err": "'processing' is not a failed status" }));
+        assert_eq!(link::get_status(redis.clone(), "https://example.com/c").await.unwrap(), LinkStatus::Processing);
+    }
+
+    async fn get_json(url: &str) -> (reqwest::StatusCode, serde_json::Value) {
+        let response = reqwest::get(url).await.unwrap();
+        let status = response.status();
+        (status, serde_json::from_str(&response.text().await.unwrap()).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn get_routes_take_ids_from_the_path_and_options_from_the_query() {
+        let (_guard, redis) = test_redis::connection().await;
+        link::add(redis.clone(), "https://example.com/pancakes", None, 2.0, 5, true, None).await.unwrap();
+        let recipe = Recipe {
+            link: "https://example.com/pancakes".to_string(),
+            title: "Pancakes".to_string(),
+            servings: Some("2".to_string()),
+            ingredients: vec!["100g flour".to_string(), "2 eggs".to_string()],
+            calories: Some(400.0),
+            ..Recipe::default()
+        };
+        recipe::add(redis.clone(), recipe).await.unwrap();
+        let url = serve(AppState::for_tests(redis)).await;
+
+        let (status, body) = get_json(&format!("{url}/api/v1/recipe/1")).await;
+        assert_eq!(status, reqwest::StatusCode::OK);
+        assert_eq!(body["title"], "Pancakes");
+
+        let (_, body) = get_json(&format!("{url}/api/v1/search?term=pancakes&term=waffles")).await;
+        assert_eq!(body["recipe_ids"], serde_json::json!([1]));
+
+        let (_, body) = get_json(&format!("{url}/api/v1/get_links?status=waiting&limit=10")).await;
+        assert_eq!(body["links"], serde_json::json!([{ "link": "https://example.com/pancakes", "priority": 2.0, "parent": null }]));
+
+        let (_, body) = get_json(&format!("{url}/api/v1/recipe/1/scale?target_servings=4")).await;
+        assert_eq!(body["ingredients"][0]["amounts"][0]["raw"], "200 g");
+
+        let (_, body) = get_json(&format!("{url}/api/v1/nutrition_sum?ids=1&ids=2")).await;
+        assert_eq!((&body["recipe_count"], &body["missing_ids"]), (&serde_json::json!(1), &serde_json::json!([2])));
+        assert_eq!(body["nutrition"]["calories"]["total"], 400.0);
+
+        let (status, _) = get_json(&format!("{url}/api/v1/recipe/pancakes")).await;
+        assert_eq!(status, reqwest::StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn get_links_with_an_invalid_status_lists_the_valid_ones() {
+        let url = serve(AppState::for_tests(broken_redis().await)).await;
+
+        let (status, body) = post_json(&format!("{url}/api/v1/get_links"), &serde_json::json!({ "status": "done" })).await;
+
+        assert_eq!(status, reqwest::StatusCode::BAD_REQUEST);
+        assert_eq!(body, serde_json::json!({
+            "err": "Invalid status 'done' (expected one of: waiting, processing, download_failed, extraction_failed, parsing_failed, processed)",
+        }));
+    }
+
+    #[tokio::test]
+    async fn submit_link_rejects_invalid_links_with_the_reason() {
+        let url = serve(AppState::for_tests(broken_redis().await)).await;
+
+        let (status, body) = post_json(&format!("{url}/api/v1/submit_link"), &serde_json::json!({ "link": "file:///etc/passwd" })).await;
+
+        assert_eq!(status, reqwest::StatusCode::BAD_REQUEST);
+        assert_eq!(body, serde_json::json!({ "err": "Unsupported scheme 'file' (expected http or https)" }));
+    }
+
+    #[tokio::test]
+    async fn submit_links_rejects_too_many_links() {
+        let url = serve(AppState::for_tests(broken_redis().await)).await;
+        let links = vec![serde_json::json!({ "link": "https://example.com/pancakes" }); endpoints::submit_links::MAX_LINKS + 1];
+
+        let (status, body) = post_json(&format!("{url}/api/v1/submit_links"), &serde_json::json!({ "links": links })).await;
+
+        assert_eq!(status, reqwest::StatusCode::BAD_REQUEST);
+        assert!(body["err"].as_str().is_some_and(|err| err.contains("Too many links")), "{body}");
+    }
+}
@@ -1,5 +1,5 @@
 fn main() {
-    println!("cargo:rerun-if-changed=src/extractor.c");
+    println!("cargo:rerun-if-changed=src/c/extractor.c");
 
     cc::Build::new()
         .file("src/c/extractor.c")
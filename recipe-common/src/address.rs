@@ -0,0 +1,319 @@
+use std::{fmt, io, net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs}, sync::Arc};
+
+use reqwest::{dns::{Addrs, Name, Resolve, Resolving}, redirect::Policy};
+use tokio::{net::lookup_host, runtime::{Handle, RuntimeFlavor}, task::block_in_place};
+use url::{Host, Url};
+
+const MAX_REDIRECTS: usize = 10;
+
+fn is_blocked_ipv4(ip: Ipv4Addr) -> bool {
+    let [first, second, ..] = ip.octets();
+    ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        // 100.64.0.0/10 (carrier-grade NAT)
+        || (first == 100 && (second & 0b1100_0000) == 0b0100_0000)
+}
+
+fn is_blocked_ipv6(ip: Ipv6Addr) -> bool {
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        return is_blocked_ipv4(ip);
+    }
+    let segments = ip.segments();
+    let [.., a, b, c, d] = ip.octets();
+    // 64:ff9b::/96 (NAT64), which reaches the IPv4 address in the last 32 bits
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        return is_blocked_ipv4(Ipv4Addr::new(a, b, c, d));
+    }
+    let first = segments[0];
+    ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // ::/96 (deprecated IPv4-compatible addresses, eg ::127.0.0.1)
+        || segments[..6] == [0; 6]
+        // fc00::/7 (unique local)
+        || (first & 0xfe00) == 0xfc00
+        // fe80::/10 (link local)
+        || (first & 0xffc0) == 0xfe80
+}
+
+/// Whether an address is loopback, private, link-local, or otherwise not on the public internet,
+/// so we should never send requests to it
+pub fn is_blocked_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_blocked_ipv4(ip),
+        IpAddr::V6(ip) => is_blocked_ipv6(ip),
+    }
+}
+
+/// Catches internal hosts that are obvious without resolving them (IPs and localhost)
+pub fn is_blocked_host(host: &Host<&str>) -> bool {
+    match host {
+        Host::Domain(domain) => {
+            let domain = domain.trim_end_matches('.').to_lowercase();
+            domain == "localhost" || domain.ends_with(".localhost")
+        }
+        Host::Ipv4(ip) => is_blocked_ipv4(*ip),
+        Host::Ipv6(ip) => is_blocked_ipv6(*ip),
+    }
+}
+
+#[derive(Debug)]
+pub struct BlockedAddressError {
+    host: String,
+    address: IpAddr,
+}
+
+impl fmt::Display for BlockedAddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Host '{}' is a local or private address ({})", self.host, self.address)
+    }
+}
+
+impl std::error::Error for BlockedAddressError {}
+
+/// Stops requests from reaching local or private addresses, eg a page linking to
+/// http://169.254.169.254 to get at cloud metadata
+/// Installed as the client's DNS resolver (so the addresses checked are the ones connected to) and
+/// redirect policy, and used to check links before requesting them, which catches IP addresses
+/// (never passed to the resolver) and hosts that a proxy resolves for us
+#[derive(Debug, Clone, Default)]
+pub struct AddressGuard {
+    /// Local or private addresses that are allowed anyway (eg for testing against a local server)
+    allowed_addresses: Arc<Vec<IpAddr>>,
+    /// Hosts whose addresses are never checked (eg the proxy)
+    exempt_hosts: Arc<Vec<String>>,
+}
+
+impl AddressGuard {
+    pub fn new(allowed_addresses: Vec<IpAddr>) -> Self {
+        AddressGuard { allowed_addresses: Arc::new(allowed_addresses), exempt_hosts: Arc::new(vec![]) }
+    }
+
+    pub fn with_exempt_host(mut self, host: &str) -> Self {
+        Arc::make_mut(&mut self.exempt_hosts).push(host.to_lowercase());
+        self
+    }
+
+    fn is_allowed(&self, ip: IpAddr) -> bool {
+        !is_blocked_ip(ip) || self.allowed_addresses.contains(&ip)
+    }
+
+    fn is_exempt(&self, host: &str) -> bool {
+        self.exempt_hosts.iter().any(|v| v.eq_ignore_ascii_case(host))
+    }
+
+    /// Checks the hosts that are obviously local without resolving them (IPs and localhost)
+    pub fn check_host(&self, host: &Host<&str>) -> Result<(), BlockedAddressError> {
+        let address = match host {
+            Host::Domain(domain) if self.is_exempt(domain) => return Ok(()),
+            Host::Domain(domain) if is_blocked_host(host) => (domain.to_string(), IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            Host::Domain(_) => return Ok(()),
+            Host::Ipv4(ip) => (ip.to_string(), IpAddr::V4(*ip)),
+            Host::Ipv6(ip) => (ip.to_string(), IpAddr::V6(*ip)),
+        };
+        match address {
+            (_, ip) if self.is_allowed(ip) => Ok(()),
+            (host, ip) => Err(BlockedAddressError { host, address: ip }),
+        }
+    }
+
+    /// Checks a link before requesting it. The resolver checks hosts when we connect to them
+    /// directly, but `proxied` requests are resolved by the proxy, so they are looked up here
+    /// instead (and let through if that fails, as the proxy may be able to resolve hosts we can't)
+    pub async fn check(&self, url: &Url, proxied: bool) -> Result<(), BlockedAddressError> {
+        let Some(host) = url.host() else {
+            return Ok(());
+        };
+        self.check_host(&host)?;
+
+        let Host::Domain(domain) = host else {
+            return Ok(());
+        };
+        if !proxied || self.is_exempt(domain) {
+            return Ok(());
+        }
+
+        match lookup_host((domain, 0)).await {
+            Ok(addresses) => self.check_addresses(domain, addresses),
+            Err(_) => Ok(()),
+        }
+    }
+
+    fn check_addresses(&self, host: &str, mut addresses: impl Iterator<Item = SocketAddr>) -> Result<(), BlockedAddressError> {
+        match addresses.find(|address| !self.is_allowed(address.ip())) {
+            Some(address) => Err(BlockedAddressError { host: host.to_owned(), address: address.ip() }),
+            None => Ok(()),
+        }
+    }
+
+    /// Rechecks the host of every redirect, following at most 10 like reqwest's default policy
+    /// Hosts that are connected to directly are checked by the resolver, but redirects to
+    /// `proxied_scheme` (eg 'https' when https requests go through a proxy) are resolved by the
+    /// proxy, so are looked up and checked here
+    pub fn redirect_policy(&self, proxied_scheme: Option<&str>) -> Policy {
+        let guard = self.clone();
+        let proxied_scheme = proxied_scheme.map(|v| v.to_owned());
+        Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                return attempt.error("too many redirects");
+            }
+            let url = attempt.url();
+            let proxied = proxied_scheme.as_deref() == Some(url.scheme());
+            match url.host().map(|host| guard.check_redirect_host(&host, proxied)) {
+                Some(Err(err)) => attempt.error(err),
+                _ => attempt.follow(),
+            }
+        })
+    }
+
+    /// Like `check`, but blocking, as redirect policies can't be async
+    fn check_redirect_host(&self, host: &Host<&str>, proxied: bool) -> Result<(), BlockedAddressError> {
+        self.check_host(host)?;
+
+        let Host::Domain(domain) = host else {
+            return Ok(());
+        };
+        if !proxied || self.is_exempt(domain) {
+            return Ok(());
+        }
+
+        match lookup_host_blocking(domain) {
+            Ok(addresses) => self.check_addresses(domain, addresses.into_iter()),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+/// Blocks the current thread, telling the runtime first if it can move other tasks off it
+fn lookup_host_blocking(host: &str) -> io::Result<Vec<SocketAddr>> {
+    let lookup = || (host, 0).to_socket_addrs().map(|addresses| addresses.collect());
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => block_in_place(lookup),
+        _ => lookup(),
+    }
+}
+
+/// Fails to resolve hosts with any blocked address, rather than only connecting to the allowed
+/// ones, as a host with a mix of addresses is most likely trying to get around the check
+/// A failed lookup fails the request as normal
+impl Resolve for AddressGuard {
+    fn resolve(&self, name: Name) -> Resolving {
+        let guard = self.clone();
+        Box::pin(async move {
+            let host = name.as_str().to_owned();
+            let addresses: Vec<SocketAddr> = lookup_host((host.as_str(), 0)).await?.collect();
+            if !guard.is_exempt(&host)
+                && let Some(address) = addresses.iter().find(|address| !guard.is_allowed(address.ip()))
+            {
+                return Err(Box::new(BlockedAddressError { host, address: address.ip() }) as Box<dyn std::error::Error + Send + Sync>);
+            }
+            let addresses: Addrs = Box::new(addresses.into_iter());
+            Ok(addresses)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{error::Error, str::FromStr};
+
+    use crate::test_server::{response, serve_response};
+
+    use super::*;
+
+    fn url(link: &str) -> Url {
+        Url::parse(link).unwrap()
+    }
+
+    #[test]
+    fn blocks_local_and_private_ips() {
+        for ip in ["127.0.0.1", "10.1.2.3", "172.16.0.1", "192.168.1.1", "169.254.169.254", "0.0.0.0", "100.64.0.1", "::1", "fe80::1", "fd00::1", "::ffff:127.0.0.1"] {
+            assert!(is_blocked_ip(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["93.184.216.34", "8.8.8.8", "100.128.0.1", "2606:4700::1111", "64:ff9b::808:808"] {
+            assert!(!is_blocked_ip(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn blocks_ipv6_addresses_embedding_blocked_ipv4() {
+        // NAT64
+        for ip in ["64:ff9b::7f00:1", "64:ff9b::a00:1", "64:ff9b::a9fe:a9fe"] {
+            assert!(is_blocked_ip(ip.parse().unwrap()), "{ip}");
+        }
+        // IPv4-compatible
+        for ip in ["::127.0.0.1", "::10.0.0.1", "::8.8.8.8"] {
+            assert!(is_blocked_ip(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn blocks_multicast() {
+        for ip in ["224.0.0.1", "239.255.255.250", "ff02::1", "ff05::1:3"] {
+            assert!(is_blocked_ip(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn checks_resolved_address_of_proxied_redirect() {
+        let guard = AddressGuard::default();
+        // a name rather than an IP, which the system resolver turns into 127.0.0.1
+        let host = Host::Domain("2130706433");
+
+        let err = guard.check_redirect_host(&host, true).unwrap_err();
+        assert_eq!(err.address, "127.0.0.1".parse::<IpAddr>().unwrap());
+        // otherwise the resolver checks it when connecting
+        assert!(guard.check_redirect_host(&host, false).is_ok());
+        assert!(guard.with_exempt_host("2130706433").check_redirect_host(&host, true).is_ok());
+    }
+
+    #[test]
+    fn blocks_localhost() {
+        assert!(is_blocked_host(&url("http://localhost/").host().unwrap()));
+        assert!(is_blocked_host(&url("http://api.localhost./").host().unwrap()));
+        assert!(!is_blocked_host(&url("http://localhost.example.com/").host().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn refuses_link_local_address() {
+        let guard = AddressGuard::default();
+        let err = guard.check(&url("http://169.254.169.254/latest/meta-data"), false).await.unwrap_err();
+        assert_eq!(err.address, "169.254.169.254".parse::<IpAddr>().unwrap());
+        assert!(guard.resolve(Name::from_str("169.254.169.254").unwrap()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn allows_explicitly_allowed_address() {
+        let guard = AddressGuard::new(vec!["169.254.169.254".parse().unwrap()]);
+        assert!(guard.check(&url("http://169.254.169.254/"), false).await.is_ok());
+        assert!(guard.resolve(Name::from_str("169.254.169.254").unwrap()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn resolver_refuses_localhost() {
+        let guard = AddressGuard::default();
+        assert!(guard.resolve(Name::from_str("localhost").unwrap()).await.is_err());
+        assert!(guard.with_exempt_host("localhost").resolve(Name::from_str("localhost").unwrap()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn refuses_redirect_to_link_local_address() {
+        let server = serve_response(response("302 Found", &[("Location", "http://169.254.169.254/latest/meta-data")], b"")).await;
+
+        // the test server itself is on a local address
+        let guard = AddressGuard::new(vec!["127.0.0.1".parse().unwrap()]);
+        let client = reqwest::Client::builder()
+            .dns_resolver(Arc::new(guard.clone()))
+            .redirect(guard.redirect_policy(None))
+            .build()
+            .unwrap();
+
+        let err = client.get(&server.url).send().await.unwrap_err();
+        assert!(err.is_redirect());
+        assert!(err.source().is_some_and(|source| source.is::<BlockedAddressError>()));
+    }
+}
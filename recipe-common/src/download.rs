@@ -0,0 +1,243 @@
+use std::{fmt, io::Read};
+
+use anyhow::Error;
+use flate2::read::GzDecoder;
+use reqwest::{header::{HeaderMap, HeaderValue, InvalidHeaderValue}, Response};
+
+pub const USER_AGENT: &str = "Prototype recipe search engine indexer";
+pub const ACCEPT_LANGUAGE: &str = "en-GB,en;q=0.5";
+pub const DEFAULT_MAX_CONTENT_SIZE: usize = 8 * 1024 * 1024;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+#[derive(Debug)]
+pub struct ContentTooLargeError {
+    /// How many bytes we know the content has (which is more than the limit, but may not be all
+    /// of it if it was streamed without a Content-Length)
+    pub size: usize,
+}
+
+impl fmt::Display for ContentTooLargeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Content is larger than the limit (at least {} bytes)", self.size)
+    }
+}
+
+impl std::error::Error for ContentTooLargeError {}
+
+/// What we identify as when downloading pages
+#[derive(Debug, Clone)]
+pub struct HeaderConfig {
+    pub user_agent: String,
+    pub accept_language: String,
+    /// Contact details (eg an email address) included in the user agent so site owners can reach us
+    pub contact: Option<String>,
+}
+
+impl Default for HeaderConfig {
+    fn default() -> Self {
+        HeaderConfig {
+            user_agent: USER_AGENT.to_string(),
+            accept_language: ACCEPT_LANGUAGE.to_string(),
+            contact: None,
+        }
+    }
+}
+
+impl HeaderConfig {
+    /// The full user agent, including the contact details if there are any
+    pub fn user_agent(&self) -> String {
+        match &self.contact {
+            Some(contact) => format!("{} (+{contact})", self.user_agent),
+            None => self.user_agent.clone(),
+        }
+    }
+}
+
+/// For validating header config when it is read, eg as a clap value parser
+pub fn parse_header_value(value: &str) -> Result<String, InvalidHeaderValue> {
+    HeaderValue::from_str(value)?;
+    Ok(value.to_owned())
+}
+
+/// Panics if the configured user agent, language, or contact are not valid header values, so
+/// they should be checked with `parse_header_value` when read from config
+pub fn headers(config: &HeaderConfig) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("Accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8".parse().unwrap());
+    // must match the decompression features enabled for reqwest, which decodes these transparently
+    headers.insert("Accept-Encoding", "gzip, deflate, br".parse().unwrap());
+    headers.insert("Accept-Language", config.accept_language.parse().unwrap());
+    headers.insert("Cache-Control", "no-cache".parse().unwrap());
+    headers.insert("Pragma", "no-cache".parse().unwrap());
+    headers.insert("Priority", "u=0, i".parse().unwrap());
+    headers.insert("Sec-Ch-Ua", "\"Brave\";v=\"131\", \"Chromium\";v=\"131\", \"Not_A Brand\";v=\"24\"".parse().unwrap());
+    headers.insert("Sec-Ch-Ua-Mobile", "?0".parse().unwrap());
+    headers.insert("Sec-Ch-Ua-Platform", "Linux".parse().unwrap());
+    headers.insert("Sec-Fetch-Dest", "document".parse().unwrap());
+    headers.insert("Sec-Fetch-Mode", "navigate".parse().unwrap());
+    headers.insert("Sec-Fetch-Site", "none".parse().unwrap());
+    headers.insert("Sec-Fetch-User", "?1".parse().unwrap());
+    headers.insert("Sec-Gpc", "1".parse().unwrap());
+    headers.insert("Upgrade-Insecure-Requests", "1".parse().unwrap());
+    headers.insert("User-Agent", config.user_agent().parse().unwrap());
+    headers
+}
+
+/// Reads the body in chunks so a huge (or endless) response can't use up all our memory
+pub async fn read_limited(mut response: Response, max_size: usize) -> Result<Vec<u8>, Error> {
+    if let Some(content_length) = response.content_length().map(|v| v as usize).filter(|v| *v > max_size) {
+        return Err(ContentTooLargeError { size: content_length }.into());
+    }
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        bytes.extend_from_slice(&chunk);
+        if bytes.len() > max_size {
+            return Err(ContentTooLargeError { size: bytes.len() }.into());
+        }
+    }
+
+    Ok(bytes)
+}
+
+/// Content encodings (gzip, deflate and br, as advertised by `headers`) are decompressed by
+/// reqwest, as long as the client is built with them enabled, but bodies that are gzip files in
+/// their own right (eg sitemap.xml.gz, which is served without a content encoding) still need to
+/// be detected by their magic bytes so everything after the downloader only sees plain UTF-8.
+/// Decompression stops once the output is larger than `max_size`, as a small body can inflate to
+/// gigabytes
+pub fn decode(bytes: &[u8], max_size: usize) -> Result<String, Error> {
+    if !bytes.starts_with(&GZIP_MAGIC) {
+        return Ok(String::from_utf8_lossy(bytes).into_owned());
+    }
+
+    let mut decoded = vec![];
+    GzDecoder::new(bytes)
+        .take(max_size as u64 + 1)
+        .read_to_end(&mut decoded)?;
+    if decoded.len() > max_size {
+        return Err(ContentTooLargeError { size: decoded.len() }.into());
+    }
+
+    Ok(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+    use crate::test_server::{response, serve_response};
+
+    use super::*;
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], Compression::best());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    async fn get(url: &str) -> Response {
+        reqwest::get(url).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn read_limited_rejects_streamed_body_over_limit() {
+        let mut response = b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nConnection: close\r\n\r\n".to_vec();
+        response.extend(vec![b'a'; 64 * 1024]);
+        let server = serve_response(response).await;
+
+        let err = read_limited(get(&server.url).await, 1024).await.unwrap_err();
+        assert!(err.downcast_ref::<ContentTooLargeError>().is_some_and(|err| err.size > 1024));
+    }
+
+    #[tokio::test]
+    async fn read_limited_rejects_content_length_over_limit() {
+        let mut response = b"HTTP/1.1 200 OK\r\nContent-Length: 4096\r\nConnection: close\r\n\r\n".to_vec();
+        response.extend(vec![b'a'; 4096]);
+        let server = serve_response(response).await;
+
+        let err = read_limited(get(&server.url).await, 1024).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<ContentTooLargeError>().unwrap().size, 4096);
+    }
+
+    #[tokio::test]
+    async fn read_limited_reads_body_under_limit() {
+        let response = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello".to_vec();
+        let server = serve_response(response).await;
+
+        assert_eq!(read_limited(get(&server.url).await, 1024).await.unwrap(), b"hello");
+    }
+
+    #[tokio::test]
+    async fn configured_headers_are_sent() {
+        let server = serve_response(response("200 OK", &[], b"")).await;
+        let config = HeaderConfig {
+            user_agent: "Test indexer".to_string(),
+            accept_language: "fr-FR".to_string(),
+            contact: Some("crawler@example.com".to_string()),
+        };
+
+        reqwest::Client::new()
+            .get(&server.url)
+            .headers(headers(&config))
+            .send()
+            .await
+            .unwrap();
+
+        let request = server.requests()[0].to_lowercase();
+        for header in [
+            "user-agent: test indexer (+crawler@example.com)\r\n",
+            "accept-language: fr-fr\r\n",
+            "accept-encoding: gzip, deflate, br\r\n",
+        ] {
+            assert!(request.contains(header), "missing '{header}' in:\n{request}");
+        }
+    }
+
+    #[test]
+    fn user_agent_without_contact_is_unchanged() {
+        assert_eq!(HeaderConfig::default().user_agent(), USER_AGENT);
+    }
+
+    #[test]
+    fn decode_passes_through_plain_text() {
+        assert_eq!(decode(b"<html></html>", 1024).unwrap(), "<html></html>");
+    }
+
+    #[test]
+    fn decode_inflates_gzip() {
+        assert_eq!(decode(&gzip(b"<html></html>"), 1024).unwrap(), "<html></html>");
+    }
+
+    #[tokio::test]
+    async fn gzipped_sitemap_is_decoded() {
+        let sitemap = "<urlset><url><loc>https://example.com/pancakes</loc></url></urlset>";
+        // served as a file, without a Content-Encoding, so reqwest leaves it compressed
+        let server = serve_response(response("200 OK", &[("Content-Type", "application/x-gzip")], &gzip(sitemap.as_bytes()))).await;
+
+        let bytes = read_limited(get(&format!("{}sitemap.xml.gz", server.url)).await, 1024).await.unwrap();
+        assert!(bytes.starts_with(&GZIP_MAGIC));
+        assert_eq!(decode(&bytes, 1024).unwrap(), sitemap);
+    }
+
+    #[tokio::test]
+    async fn brotli_encoded_page_is_decoded() {
+        let mut encoder = brotli::CompressorWriter::new(vec![], 4096, 11, 22);
+        encoder.write_all(b"<html>Pancakes</html>").unwrap();
+        let server = serve_response(response("200 OK", &[("Content-Type", "text/html"), ("Content-Encoding", "br")], &encoder.into_inner())).await;
+
+        let bytes = read_limited(get(&server.url).await, 1024).await.unwrap();
+        assert_eq!(decode(&bytes, 1024).unwrap(), "<html>Pancakes</html>");
+    }
+
+    #[test]
+    fn decode_stops_inflating_at_limit() {
+        // a few KB that inflate to 16 MiB
+        let bomb = gzip(&vec![0; 16 * 1024 * 1024]);
+        assert!(bomb.len() < 64 * 1024);
+
+        let err = decode(&bomb, 1024 * 1024).unwrap_err();
+        assert_eq!(err.downcast_ref::<ContentTooLargeError>().unwrap().size, 1024 * 1024 + 1);
+    }
+}
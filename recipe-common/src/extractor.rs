@@ -0,0 +1,196 @@
+use anyhow::Error;
+use serde_json::Value;
+
+use crate::microdata;
+
+mod c_extractor {
+    use std::{ffi::{c_char, CStr, CString}, str};
+
+    #[link(name = "extractor")]
+    unsafe extern "C" {
+        fn extract(input: *const c_char) -> *mut c_char;
+    }
+    
+    pub fn extract_wrapper(input: &str) -> Option<String> {
+        unsafe {
+            let input: String = input.chars()
+                .filter(|&c| c != '\0')
+                .collect();
+            let input = CString::new(input).unwrap();
+            let output = extract(input.as_ptr());
+            if output.is_null() {
+                None
+            } else {
+                let result = str::from_utf8(CStr::from_ptr(output).to_bytes()).unwrap().to_string();
+                Some(result)
+            }
+        }
+    }
+}
+
+/// Returns every recipe on the page
+/// Expects `contents` to already be decoded HTML; decompression happens in the downloader
+/// Falls back to microdata if the page has no JSON-LD schema
+#[tracing::instrument(skip(contents))]
+pub async fn extract_all(link: &str, contents: &str) -> Result<Vec<Value>, Error> {
+    let Some(schema) = c_extractor::extract_wrapper(contents) else {
+        return Ok(microdata::extract(contents).into_iter().collect());
+    };
+
+    let schema = serde_json::from_str::<Value>(schema.as_str())?;
+
+    Ok(find_recipes(&schema).into_iter().cloned().collect())
+}
+
+/// Returns the first recipe on the page
+#[tracing::instrument(skip(contents))]
+pub async fn extract(link: &str, contents: &str) -> Result<Option<Value>, Error> {
+    Ok(extract_all(link, contents).await?.into_iter().next())
+}
+
+/// Whether a node's @type is "Recipe", or an array including "Recipe" (eg ["Recipe", "NewsArticle"])
+fn is_recipe(v: &Value) -> bool {
+    match v.get("@type") {
+        Some(Value::String(v)) => v == "Recipe",
+        Some(Value::Array(v)) => v.iter().any(|v| v.as_str().is_some_and(|v| v == "Recipe")),
+        _ => false,
+    }
+}
+
+/// Whether a node is a page (eg "WebPage", "ItemPage") or site, which may have the recipe
+/// as its mainEntity
+fn is_page(v: &Value) -> bool {
+    let is_page_type = |v: &str| v == "WebSite" || v.ends_with("Page");
+    match v.get("@type") {
+        Some(Value::String(v)) => is_page_type(v),
+        Some(Value::Array(v)) => v.iter().any(|v| v.as_str().is_some_and(is_page_type)),
+        _ => false,
+    }
+}
+
+/// Finds the recipes in a schema, which can be the schema itself, elements of a top-level
+/// array, nodes in its @graph, or the mainEntity of a page
+fn find_recipes(schema: &Value) -> Vec<&Value> {
+    if is_recipe(schema) {
+        return vec![schema];
+    }
+
+    // some schemas have no @type at all, so are assumed to be the recipe
+    if schema.is_object() && schema.get("@type").is_none() && schema.get("@graph").is_none() {
+        return vec![schema];
+    }
+
+    if let Some(arr) = schema.as_array() {
+        return arr.iter().flat_map(find_recipes).collect();
+    }
+
+    if is_page(schema)
+        && let Some(main_entity) = schema.get("mainEntity")
+    {
+        let recipes: Vec<&Value> = match main_entity.as_array() {
+            Some(arr) => arr.iter().filter(|v| is_recipe(v)).collect(),
+            None => Some(main_entity).filter(|v| is_recipe(v)).into_iter().collect(),
+        };
+        if !recipes.is_empty() {
+            return recipes;
+        }
+    }
+
+    let Some(arr) = schema.get("@graph").and_then(|v| v.as_array()) else {
+        return vec![];
+    };
+
+    let recipes: Vec<&Value> = arr.iter()
+        .filter(|v| is_recipe(v))
+        .collect();
+    if !recipes.is_empty() {
+        return recipes;
+    }
+
+    // a page in the graph usually only references the recipe by @id, in which case the recipe
+    // is its own node in the graph
+    arr.iter()
+        .filter(|v| is_page(v))
+        .flat_map(find_recipes)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// A page with the schema in a JSON-LD script, which needs an @context mentioning schema.org
+    /// to be found
+    fn page(schema: &Value) -> String {
+        format!(r#"<html><head><script type="application/ld+json">{schema}</script></head><body></body></html>"#)
+    }
+
+    async fn titles(schema: Value) -> Vec<String> {
+        extract_all("https://example.com/pancakes", &page(&schema)).await
+            .unwrap()
+            .iter()
+            .map(|v| v["name"].as_str().unwrap_or_default().to_owned())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn type_arrays_including_recipe_are_recipes() {
+        let schema = json!({ "@context": "https://schema.org", "@type": ["Recipe", "NewsArticle"], "name": "Pancakes" });
+        assert_eq!(titles(schema).await, vec!["Pancakes"]);
+
+        let schema = json!({ "@context": "https://schema.org", "@graph": [
+            { "@type": ["NewsArticle"], "name": "Pancake day" },
+            { "@type": ["NewsArticle", "Recipe"], "name": "Pancakes" },
+        ]});
+        assert_eq!(titles(schema).await, vec!["Pancakes"]);
+    }
+
+    #[tokio::test]
+    async fn type_arrays_without_recipe_are_not_recipes() {
+        let schema = json!({ "@context": "https://schema.org", "@type": ["NewsArticle", "Article"], "name": "Pancake day" });
+        assert!(titles(schema).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn recipe_in_the_main_entity_of_a_page_is_found() {
+        let schema = json!({
+            "@context": "https://schema.org",
+            "@type": "WebPage",
+            "name": "Pancakes | Example Recipes",
+            "mainEntity": { "@type": "Recipe", "name": "Pancakes" },
+        });
+        assert_eq!(titles(schema).await, vec!["Pancakes"]);
+
+        // the page is often in a graph alongside other nodes
+        let schema = json!({ "@context": "https://schema.org", "@graph": [
+            { "@type": "Organization", "name": "Example Recipes" },
+            { "@type": "WebPage", "name": "Pancakes | Example Recipes", "mainEntity": { "@type": "Recipe", "name": "Pancakes" } },
+        ]});
+        assert_eq!(titles(schema).await, vec!["Pancakes"]);
+    }
+
+    #[tokio::test]
+    async fn main_entity_that_is_not_a_recipe_is_ignored() {
+        let schema = json!({
+            "@context": "https://schema.org",
+            "@type": "WebPage",
+            "mainEntity": { "@type": "Article", "name": "Pancake day" },
+        });
+        assert!(titles(schema).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn every_recipe_is_extracted_and_extract_returns_the_first() {
+        let schema = json!({ "@context": "https://schema.org", "@graph": [
+            { "@type": "WebPage", "name": "Two pancake recipes" },
+            { "@type": "Recipe", "name": "Pancakes" },
+            { "@type": "Recipe", "name": "Vegan pancakes" },
+        ]});
+        assert_eq!(titles(schema.clone()).await, vec!["Pancakes", "Vegan pancakes"]);
+
+        let first = extract("https://example.com/pancakes", &page(&schema)).await.unwrap().unwrap();
+        assert_eq!(first["name"], "Pancakes");
+    }
+}
@@ -1,4 +1,17 @@
+pub mod address;
+pub mod download;
+pub mod extractor;
 pub mod link;
+pub mod link_allowlist;
 pub mod link_blacklist;
+pub mod logging;
+pub mod metrics;
+pub mod microdata;
+pub mod parser;
 pub mod recipe;
+pub mod robots;
 
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_redis;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_server;
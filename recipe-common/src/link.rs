@@ -1,37 +1,29 @@
-use std::fmt;
+use std::{collections::{HashMap, HashSet}, time::Duration};
 
-use anyhow::Error;
-use redis::{aio::MultiplexedConnection, AsyncCommands};
+use chrono::Utc;
+use rand::Rng;
+use redis::{aio::MultiplexedConnection, AsyncCommands, RedisError};
 use serde::{Deserialize, Serialize};
+use thiserror::Error;
 use tokio::task::JoinSet;
 use url::Url;
 
-use crate::link_blacklist;
+use crate::{link_allowlist, link_blacklist};
 
-#[derive(Debug)]
-pub struct ProcessingLinkNotFoundError;
-
-impl fmt::Display for ProcessingLinkNotFoundError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Processing link not found")
-    }
-}
-
-impl std::error::Error for ProcessingLinkNotFoundError {}
-
-#[derive(Debug)]
-pub struct LinkMissingDomainError {
-    link: String
+#[derive(Debug, Error)]
+pub enum LinkError {
+    #[error("Link did not have a domain: {link}")]
+    MissingDomain { link: String },
+    #[error("Redis error: {0}")]
+    Redis(#[from] RedisError),
+    #[error("Invalid link: {0}")]
+    InvalidUrl(#[from] url::ParseError),
+    #[error("Link not found: {link}")]
+    NotFound { link: String },
+    #[error("Status is not a failed status: {}", status.to_string())]
+    NotFailedStatus { status: LinkStatus },
 }
 
-impl fmt::Display for LinkMissingDomainError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Link did not have a domain: {}", self.link)
-    }
-}
-
-impl std::error::Error for LinkMissingDomainError  {}
-
 #[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
 pub enum LinkStatus {
     Waiting,
@@ -43,16 +35,22 @@ pub enum LinkStatus {
 }
 
 impl LinkStatus {
+    pub const ALL: [LinkStatus; 6] = [
+        LinkStatus::Waiting,
+        LinkStatus::Processing,
+        LinkStatus::DownloadFailed,
+        LinkStatus::ExtractionFailed,
+        LinkStatus::ParsingFailed,
+        LinkStatus::Processed,
+    ];
+
+    /// The inverse of `to_string`, which it is derived from so the two can't disagree
     pub fn from_string(x: &str) -> Option<Self> {
-        match x {
-            "waiting" => Some(LinkStatus::Waiting),
-            "processing" => Some(LinkStatus::Processing),
-            "download_failed" => Some(LinkStatus::DownloadFailed),
-            "extraction_failed" => Some(LinkStatus::ExtractionFailed),
-            "parsing_failed" => Some(LinkStatus::ExtractionFailed),
-            "processed" => Some(LinkStatus::Processed),
-            _ => None,
-        }
+        Self::ALL.into_iter().find(|status| status.to_string() == x)
+    }
+
+    pub fn is_failed(self) -> bool {
+        matches!(self, LinkStatus::DownloadFailed | LinkStatus::ExtractionFailed | LinkStatus::ParsingFailed)
     }
 
     pub fn to_string(self) -> &'static str {
@@ -67,6 +65,11 @@ impl LinkStatus {
     }
 }
 
+const TRACKING_QUERY_PARAMETERS: [&str; 2] = ["fbclid", "gclid"];
+const TRACKING_QUERY_PARAMETER_PREFIX: &str = "utm_";
+/// Links are re-queued in batches of this many, so one transaction doesn't get too large
+const REQUEUE_BATCH_SIZE: usize = 1000;
+
 fn key_status_to_links(status: LinkStatus) -> String {
     format!("link:links_by_status:{}", status.to_string())
 }
@@ -79,10 +82,30 @@ fn key_processing_domains() -> String {
     "link:processing_domains".to_string()
 }
 
+// HASH of domain to how many of its links are processing
+fn key_domain_to_processing_count() -> String {
+    "link:processing_count_by_domain".to_string()
+}
+
+// How many links from a single domain can be processing at once
+fn key_domain_slots() -> String {
+    "link:domain_slots".to_string()
+}
+
+// Maximum number of links a domain can have waiting, if there is one
+fn key_max_domain_waiting_links() -> String {
+    "link:max_domain_waiting_links".to_string()
+}
+
 fn key_waiting_domains() -> String {
     "link:waiting_domains".to_string()
 }
 
+// ZSET of waiting domains, scored by their highest priority waiting link
+fn key_domains_by_priority() -> String {
+    "link:domains_by_priority".to_string()
+}
+
 fn key_link_to_status() -> String {
     "link:status".to_string()
 }
@@ -103,22 +126,353 @@ fn key_link_to_remaining_follows() -> String {
     "link:remaining_follows".to_string()
 }
 
+fn key_link_to_depth() -> String {
+    "link:depth".to_string()
+}
+
 fn key_link_to_content_size() -> String {
     "link:content_size".to_string()
 }
 
+// ZSET of processing links, scored by when they started processing (in milliseconds)
+fn key_processing_started_at() -> String {
+    "link:processing_started_at".to_string()
+}
+
+// HASH of processing link to when it started processing, replaced by the ZSET above
+fn key_legacy_link_to_processing_started() -> String {
+    "link:processing_started".to_string()
+}
+
+fn key_link_to_original() -> String {
+    "link:original".to_string()
+}
+
+// SET of links found on the page
+fn key_link_to_children(link: &str) -> String {
+    format!("link:children:{link}")
+}
+
+fn key_link_to_content_hash() -> String {
+    "link:content_hash".to_string()
+}
+
+// ZSET of processed links, scored by when they were processed (unix millis)
+fn key_processed_at() -> String {
+    "link:processed_at".to_string()
+}
+
+fn key_no_follow_links() -> String {
+    "link:no_follow".to_string()
+}
+
+fn is_tracking_query_parameter(key: &str) -> bool {
+    key.starts_with(TRACKING_QUERY_PARAMETER_PREFIX) || TRACKING_QUERY_PARAMETERS.contains(&key)
+}
+
+/// Normalizes a link so that trivially different versions of the same page are treated as one
+/// - lowercases the host (already done by `Url::parse` for http/https)
+/// - removes the fragment
+/// - removes tracking query parameters (utm_*, fbclid, gclid)
+/// - removes any trailing slash
+///
+/// Links that fail to parse are returned unchanged
+pub fn canonicalize(link: &str) -> String {
+    let Ok(mut url) = Url::parse(link) else {
+        return link.to_owned();
+    };
+
+    url.set_fragment(None);
+
+    // filtered on the raw query, so that the parameters that are kept aren't re-encoded (eg
+    // 'q=a+b' staying as it is rather than becoming 'q=a%2Bb')
+    let query = url.query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|parameter| !is_tracking_query_parameter(parameter.split('=').next().unwrap_or_default()))
+        .collect::<Vec<&str>>()
+        .join("&");
+    if query.is_empty() {
+        url.set_query(None);
+    } else {
+        url.set_query(Some(&query));
+    }
+
+    if url.path().len() > 1 && url.path().ends_with('/') {
+        let path = url.path().trim_end_matches('/').to_owned();
+        url.set_path(&path);
+    }
+
+    url.to_string()
+}
+
+/// Keeps a domain's entry in `key_domains_by_priority` in sync with its waiting links, so must
+/// be called whenever a domain starts or stops waiting or its waiting links change
+#[tracing::instrument(skip(redis_links))]
+async fn update_domain_priority(mut redis_links: MultiplexedConnection, domain: &str) -> Result<(), LinkError> {
+    let is_waiting: bool = redis_links.sismember(key_waiting_domains(), domain).await?;
+    let highest: Vec<(String, f32)> = redis_links.zrevrange_withscores(key_domain_to_waiting_links(domain), 0, 0).await?;
+    match highest.first() {
+        Some((_, priority)) if is_waiting => {
+            let _: () = redis_links.zadd(key_domains_by_priority(), domain, *priority).await?;
+        }
+        _ => {
+            let _: () = redis_links.zrem(key_domains_by_priority(), domain).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Domains can only be polled while they have waiting links and a free slot, so this must be
+/// called whenever either of those might have changed
+#[tracing::instrument(skip(redis_links))]
+async fn update_domain_waiting(mut redis_links: MultiplexedConnection, domain: &str) -> Result<(), LinkError> {
+    let (processing, slots): (Option<usize>, Option<usize>) = redis::pipe()
+        .hget(key_domain_to_processing_count(), domain)
+        .get(key_domain_slots())
+        .query_async(&mut redis_links)
+        .await?;
+    let has_free_slot = processing.unwrap_or(0) < slots.unwrap_or(1);
+
+    if has_free_slot && is_domain_waiting(redis_links.clone(), domain).await? {
+        let _: () = redis_links.sadd(key_waiting_domains(), domain).await?;
+    } else {
+        let _: () = redis_links.srem(key_waiting_domains(), domain).await?;
+    }
+
+    update_domain_priority(redis_links, domain).await
+}
+
+#[tracing::instrument(skip(redis_links))]
+async fn claim_domain_slot(mut redis_links: MultiplexedConnection, domain: &str) -> Result<(), LinkError> {
+    let mut pipe = redis::pipe();
+    pipe.hincr(key_domain_to_processing_count(), domain, 1)
+        .sadd(key_processing_domains(), domain);
+    pipe.exec_async(&mut redis_links).await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip(redis_links))]
+async fn release_domain_slots(mut redis_links: MultiplexedConnection, domain: &str, count: usize) -> Result<(), LinkError> {
+    let processing: i64 = redis_links.hincr(key_domain_to_processing_count(), domain, -(count as i64)).await?;
+    if processing <= 0 {
+        let mut pipe = redis::pipe();
+        pipe.hdel(key_domain_to_processing_count(), domain)
+            .srem(key_processing_domains(), domain);
+        pipe.exec_async(&mut redis_links).await?;
+    }
+    Ok(())
+}
+
+/// Sets how many links from a single domain can be processing at once (1 if never set)
+#[tracing::instrument(skip(redis_links))]
+pub async fn set_domain_slots(mut redis_links: MultiplexedConnection, slots: usize) -> Result<(), LinkError> {
+    let _: () = redis_links.set(key_domain_slots(), slots).await?;
+    // domains that were full may not be any more
+    let domains: Vec<String> = redis_links.smembers(key_processing_domains()).await?;
+    for domain in domains {
+        update_domain_waiting(redis_links.clone(), &domain).await?;
+    }
+    Ok(())
+}
+
+/// Sets the maximum number of links a domain can have waiting (no limit if None), so that links
+/// requeued outside the finder respect the same limit it uses when adding links
+#[tracing::instrument(skip(redis_links))]
+pub async fn set_max_domain_waiting_links(mut redis_links: MultiplexedConnection, max: Option<usize>) -> Result<(), LinkError> {
+    match max {
+        Some(max) => redis_links.set(key_max_domain_waiting_links(), max).await?,
+        None => redis_links.del(key_max_domain_waiting_links()).await?,
+    }
+    Ok(())
+}
+
+/// The maximum number of links a domain can have waiting, as set by
+/// `set_max_domain_waiting_links` (no limit if None)
+#[tracing::instrument(skip(redis_links))]
+pub async fn get_max_domain_waiting_links(mut redis_links: MultiplexedConnection) -> Result<Option<usize>, LinkError> {
+    Ok(redis_links.get(key_max_domain_waiting_links()).await?)
+}
+
+/// Rebuilds the domain priorities from scratch, eg for links added before they were tracked
+#[tracing::instrument(skip(redis_links))]
+pub async fn rebuild_domain_priorities(mut redis_links: MultiplexedConnection) -> Result<(), LinkError> {
+    let _: () = redis_links.del(key_domains_by_priority()).await?;
+    let domains: Vec<String> = redis_links.smembers(key_waiting_domains()).await?;
+    for domain in domains {
+        update_domain_priority(redis_links.clone(), &domain).await?;
+    }
+    Ok(())
+}
+
 #[tracing::instrument(skip(pool))]
-pub async fn reset_tasks(mut pool: MultiplexedConnection) -> Result<(), Error> {
+pub async fn reset_tasks(mut pool: MultiplexedConnection) -> Result<(), LinkError> {
     let processing: Vec<String> = pool.zrange(key_status_to_links(LinkStatus::Processing), 0, -1).await?;
     for link in processing {
         update_status(pool.clone(), &link, LinkStatus::Waiting).await?;
     }
 
+    // nothing is processing any more, so the start times from before they were kept in a ZSET
+    // are no longer needed
+    let _: () = pool.del(key_legacy_link_to_processing_started()).await?;
+
     Ok(())
 }
 
+/// Moves links that have been processing for longer than `timeout` back to waiting, eg because
+/// the worker processing them crashed
+/// Returns the number of links re-queued
+#[tracing::instrument(skip(redis_links))]
+pub async fn reset_stuck_tasks(mut redis_links: MultiplexedConnection, timeout: Duration) -> Result<usize, LinkError> {
+    let cutoff = Utc::now().timestamp_millis() - timeout.as_millis() as i64;
+    let links: Vec<String> = redis_links.zrangebyscore(key_processing_started_at(), "-inf", cutoff).await?;
+
+    let mut reset = 0;
+    for links in links.chunks(REQUEUE_BATCH_SIZE) {
+        reset += requeue(redis_links.clone(), links, LinkStatus::Processing, None, None).await?;
+    }
+
+    Ok(reset)
+}
+
+/// Moves every link with a failed status back to waiting so it is retried, highest priority
+/// first. Links on domains that already have as many waiting links as they can (see
+/// `set_max_domain_waiting_links`) are left as they are
+/// Returns the number of links re-queued
+#[tracing::instrument(skip(redis_links))]
+pub async fn requeue_failed(mut redis_links: MultiplexedConnection, status: LinkStatus) -> Result<usize, LinkError> {
+    if !status.is_failed() {
+        return Err(LinkError::NotFailedStatus { status });
+    }
+
+    let max_domain_waiting_links = get_max_domain_waiting_links(redis_links.clone()).await?;
+    let links: Vec<String> = redis_links.zrevrange(key_status_to_links(status), 0, -1).await?;
+
+    let mut requeued = 0;
+    for links in links.chunks(REQUEUE_BATCH_SIZE) {
+        requeued += requeue(redis_links.clone(), links, status, None, max_domain_waiting_links).await?;
+    }
+
+    Ok(requeued)
+}
+
+/// Moves up to `limit` links that were processed more than `ttl` ago back to waiting with the
+/// given priority, so their recipes are refreshed. Pages that haven't changed since are not
+/// re-parsed, as their content hash will match
+/// Links processed before processing times were recorded are never re-queued
+/// Returns the number of links re-queued
+#[tracing::instrument(skip(redis_links))]
+pub async fn requeue_stale(
+    mut redis_links: MultiplexedConnection, 
+    ttl: Duration, 
+    priority: f32, 
+    limit: usize,
+) -> Result<usize, LinkError> {
+    let cutoff = Utc::now().timestamp_millis() - ttl.as_millis() as i64;
+    let links: Vec<String> = redis_links.zrangebyscore_limit(key_processed_at(), "-inf", cutoff, 0, limit as isize).await?;
+    requeue(redis_links, &links, LinkStatus::Processed, Some(priority), None).await
+}
+
+/// Moves links that have `status` (which can't be waiting) back to waiting in a few pipelines,
+/// rather than the several round trips per link of `update_status`
+/// If `priority` is given, the links are re-queued with it rather than their previous priority
+/// If `max_domain_waiting_links` is given, links are skipped once their domain has that many
+/// waiting links
+/// Returns the number of links re-queued, which excludes any that have since been removed or
+/// changed status
+#[tracing::instrument(skip(redis_links, links))]
+async fn requeue(
+    mut redis_links: MultiplexedConnection, 
+    links: &[String], 
+    status: LinkStatus, 
+    priority: Option<f32>,
+    max_domain_waiting_links: Option<usize>,
+) -> Result<usize, LinkError> {
+    if links.is_empty() {
+        return Ok(0);
+    }
+
+    let mut pipe = redis::pipe();
+    for link in links {
+        pipe.hget(key_link_to_domain(), link)
+            .hget(key_link_to_priority(), link)
+            .hget(key_link_to_status(), link);
+    }
+    let details: Vec<(Option<String>, Option<f32>, Option<String>)> = pipe.query_async(&mut redis_links).await?;
+
+    // how many more links each domain can have waiting
+    let mut room = HashMap::new();
+    if let Some(max_domain_waiting_links) = max_domain_waiting_links {
+        let domains: HashSet<&String> = details.iter()
+            .filter_map(|(domain, _, _)| domain.as_ref())
+            .collect();
+        let mut pipe = redis::pipe();
+        for domain in &domains {
+            pipe.zcard(key_domain_to_waiting_links(domain));
+        }
+        let waiting: Vec<usize> = pipe.query_async(&mut redis_links).await?;
+        room = domains.into_iter()
+            .cloned()
+            .zip(waiting.into_iter().map(|waiting| max_domain_waiting_links.saturating_sub(waiting)))
+            .collect();
+    }
+
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    // how many links were requeued from each domain
+    let mut domains = HashMap::<String, usize>::new();
+    let mut requeued = 0;
+    for (link, details) in links.iter().zip(details) {
+        let (Some(domain), Some(previous_priority), Some(previous_status)) = details else {
+            continue;
+        };
+        if LinkStatus::from_string(&previous_status) != Some(status) {
+            // eg a processing start time left behind by a link that has since finished
+            if status == LinkStatus::Processing {
+                pipe.zrem(key_processing_started_at(), link);
+            }
+            continue;
+        }
+        if let Some(room) = room.get_mut(&domain) {
+            if *room == 0 {
+                continue;
+            }
+            *room -= 1;
+        }
+        let priority = priority.unwrap_or(previous_priority);
+        pipe.zrem(key_status_to_links(status), link)
+            .zadd(key_status_to_links(LinkStatus::Waiting), link, priority)
+            .hset(key_link_to_status(), link, LinkStatus::Waiting.to_string())
+            .hset(key_link_to_priority(), link, priority)
+            .zadd(key_domain_to_waiting_links(&domain), link, priority);
+        if status == LinkStatus::Processed {
+            pipe.zrem(key_processed_at(), link);
+        }
+        if status == LinkStatus::Processing {
+            pipe.zrem(key_processing_started_at(), link);
+        }
+        *domains.entry(domain).or_default() += 1;
+        requeued += 1;
+    }
+    pipe.exec_async(&mut redis_links).await?;
+
+    for (domain, count) in &domains {
+        if status == LinkStatus::Processing {
+            release_domain_slots(redis_links.clone(), domain, *count).await?;
+        }
+        update_domain_waiting(redis_links.clone(), domain).await?;
+    }
+
+    Ok(requeued)
+}
+
 /// Returns true if added
-/// Returns false if already existed or matches the blacklist
+/// Returns false if already existed, matches the blacklist, isn't on the allowlist (if there is
+/// one), or its domain's queue is full
+/// If `follow` is false, none of the links on the page are followed, regardless of its remaining
+/// follows or whether it has a complete recipe
+/// If `max_domain_waiting_links` is given and the domain already has that many waiting links,
+/// the lowest priority one is dropped to make room, unless the new link's priority is lower
 #[tracing::instrument(skip(pool))]
 pub async fn add(
     mut pool: MultiplexedConnection,
@@ -126,14 +480,41 @@ pub async fn add(
     parent: Option<&str>,
     priority: f32,
     remaining_follows: i32,
-) -> Result<bool, Error> {
-    if !link_blacklist::is_allowed(pool.clone(), link).await? || exists(pool.clone(), link).await? {
+    follow: bool,
+    max_domain_waiting_links: Option<usize>,
+) -> Result<bool, LinkError> {
+    let original = link;
+    let link = &canonicalize(original);
+
+    if !link_blacklist::is_allowed(pool.clone(), link).await? 
+        || !link_allowlist::is_allowed(pool.clone(), link).await? 
+        || exists(pool.clone(), link).await? 
+    {
         return Ok(false);
     }
 
     let url = Url::parse(link)?;
     let Some(domain) = url.domain().map(|domain| domain.to_owned()) else {
-        return Err(LinkMissingDomainError { link: link.to_owned() }.into())
+        return Err(LinkError::MissingDomain { link: link.to_owned() })
+    };
+
+    if let Some(max_domain_waiting_links) = max_domain_waiting_links {
+        let waiting: usize = pool.zcard(key_domain_to_waiting_links(&domain)).await?;
+        if waiting >= max_domain_waiting_links {
+            let lowest: Vec<(String, f32)> = pool.zrange_withscores(key_domain_to_waiting_links(&domain), 0, 0).await?;
+            match lowest.first() {
+                Some((lowest, lowest_priority)) if *lowest_priority < priority => {
+                    remove(pool.clone(), lowest).await?;
+                }
+                _ => return Ok(false),
+            }
+        }
+    }
+
+    // links added before depths were recorded count as depth 0
+    let depth = match parent {
+        Some(parent) => get_depth(pool.clone(), &canonicalize(parent)).await? + 1,
+        None => 0,
     };
 
     let mut pipe = redis::pipe();
@@ -142,81 +523,240 @@ pub async fn add(
         .hset(key_link_to_priority(), link, priority)
         .hset(key_link_to_domain(), link, &domain)
         .hset(key_link_to_remaining_follows(), link, remaining_follows)
-        .sadd(key_waiting_domains(), &domain);
+        .hset(key_link_to_depth(), link, depth)
+        .zadd(key_domain_to_waiting_links(&domain), link, priority);
 
     if let Some(parent) = parent {
         pipe.hset(key_link_to_parent(), link, parent);
+        pipe.sadd(key_link_to_children(&canonicalize(parent)), link);
     }
 
-    pipe.exec_async(&mut pool).await?;
-    let is_domain_processing: bool = pool.sismember(key_processing_domains(), &domain).await?;
-    if !is_domain_processing {
-        let _: () = pool.zadd(key_domain_to_waiting_links(&domain), link, priority).await?;
+    if original != link {
+        pipe.hset(key_link_to_original(), link, original);
+    }
+
+    // set along with everything else, so the link can't be processed before it is marked
+    if !follow {
+        pipe.sadd(key_no_follow_links(), link);
     }
 
+    pipe.exec_async(&mut pool).await?;
+
+    update_domain_waiting(pool.clone(), &domain).await?;
+
     Ok(true)
 }
 
 #[tracing::instrument(skip(redis_links))]
-pub async fn get_status(mut redis_links: MultiplexedConnection, link: &str) -> Result<LinkStatus, Error> {
-    Ok(LinkStatus::from_string(&redis_links.hget::<_, _, String>(key_link_to_status(), link).await?).unwrap())
+pub async fn get_status(mut redis_links: MultiplexedConnection, link: &str) -> Result<LinkStatus, LinkError> {
+    let status: Option<String> = redis_links.hget(key_link_to_status(), link).await?;
+    status.as_deref()
+        .and_then(LinkStatus::from_string)
+        .ok_or_else(|| LinkError::NotFound { link: link.to_owned() })
 }
 
 #[tracing::instrument(skip(redis_links))]
-pub async fn get_priority(mut redis_links: MultiplexedConnection, link: &str) -> Result<f32, Error> {
-    Ok(redis_links.hget(key_link_to_priority(), link).await?)
+pub async fn get_priority(mut redis_links: MultiplexedConnection, link: &str) -> Result<f32, LinkError> {
+    let priority: Option<f32> = redis_links.hget(key_link_to_priority(), link).await?;
+    priority.ok_or_else(|| LinkError::NotFound { link: link.to_owned() })
 }
 
 #[tracing::instrument(skip(redis_links))]
-pub async fn get_parent(mut redis_links: MultiplexedConnection, link: &str) -> Result<Option<String>, Error> {
+pub async fn get_parent(mut redis_links: MultiplexedConnection, link: &str) -> Result<Option<String>, LinkError> {
     Ok(redis_links.hget(key_link_to_parent(), link).await?)
 }
 
+/// Returns the link as it was originally submitted, before it was canonicalized
+#[tracing::instrument(skip(redis_links))]
+pub async fn get_original(mut redis_links: MultiplexedConnection, link: &str) -> Result<String, LinkError> {
+    let original: Option<String> = redis_links.hget(key_link_to_original(), link).await?;
+    Ok(original.unwrap_or(link.to_owned()))
+}
+
 #[tracing::instrument(skip(redis_links))]
-pub async fn get_domain(mut redis_links: MultiplexedConnection, link: &str) -> Result<String, Error> {
-    Ok(redis_links.hget(key_link_to_domain(), link).await?)
+pub async fn get_domain(mut redis_links: MultiplexedConnection, link: &str) -> Result<String, LinkError> {
+    let domain: Option<String> = redis_links.hget(key_link_to_domain(), link).await?;
+    domain.ok_or_else(|| LinkError::NotFound { link: link.to_owned() })
 }
 
 #[tracing::instrument(skip(redis_links))]
-pub async fn get_remaining_follows(mut redis_links: MultiplexedConnection, link: &str) -> Result<i32, Error> {
+pub async fn get_remaining_follows(mut redis_links: MultiplexedConnection, link: &str) -> Result<i32, LinkError> {
     Ok(redis_links.hget(key_link_to_remaining_follows(), link).await?)
 }
 
+/// How many links were followed from a submitted link to reach this one (0 for submitted links)
 #[tracing::instrument(skip(redis_links))]
-pub async fn set_content_size(mut redis_links: MultiplexedConnection, link: &str, content_size: usize) -> Result<(), Error> {
+pub async fn get_depth(mut redis_links: MultiplexedConnection, link: &str) -> Result<u32, LinkError> {
+    let depth: Option<u32> = redis_links.hget(key_link_to_depth(), link).await?;
+    Ok(depth.unwrap_or(0))
+}
+
+/// Links that were found on the page and added
+#[tracing::instrument(skip(redis_links))]
+pub async fn get_children(mut redis_links: MultiplexedConnection, link: &str) -> Result<Vec<String>, LinkError> {
+    Ok(redis_links.smembers(key_link_to_children(&canonicalize(link))).await?)
+}
+
+/// Walks the links discovered from `root`, breadth first, up to `max_depth` links away
+/// Stops once `max_nodes` links have been visited, so a heavily linked root can't return the
+/// whole crawl
+/// Returns each visited link with its children, starting with the root
+#[tracing::instrument(skip(redis_links))]
+pub async fn get_link_graph(
+    mut redis_links: MultiplexedConnection, 
+    root: &str, 
+    max_depth: usize, 
+    max_nodes: usize,
+) -> Result<Vec<(String, Vec<String>)>, LinkError> {
+    let mut graph = vec![];
+    let mut visited = HashSet::new();
+    let mut current = vec![canonicalize(root)];
+    visited.insert(current[0].clone());
+
+    for depth in 0..=max_depth {
+        current.truncate(max_nodes.saturating_sub(graph.len()));
+        if current.is_empty() {
+            break;
+        }
+
+        // one round trip per level rather than per link
+        let mut pipe = redis::pipe();
+        for link in &current {
+            pipe.smembers(key_link_to_children(link));
+        }
+        let children: Vec<Vec<String>> = pipe.query_async(&mut redis_links).await?;
+
+        let mut next = vec![];
+        for (link, children) in current.into_iter().zip(children) {
+            // children past max depth are listed but not expanded
+            if depth < max_depth {
+                for child in &children {
+                    if visited.insert(child.clone()) {
+                        next.push(child.clone());
+                    }
+                }
+            }
+            graph.push((link, children));
+        }
+        current = next;
+    }
+
+    Ok(graph)
+}
+
+/// The SHA-256 hash (in hex) of the page's content when recipes were last parsed from it
+#[tracing::instrument(skip(redis_links))]
+pub async fn get_content_hash(mut redis_links: MultiplexedConnection, link: &str) -> Result<Option<String>, LinkError> {
+    Ok(redis_links.hget(key_link_to_content_hash(), link).await?)
+}
+
+#[tracing::instrument(skip(redis_links))]
+pub async fn set_content_hash(mut redis_links: MultiplexedConnection, link: &str, content_hash: &str) -> Result<(), LinkError> {
+    let _: () = redis_links.hset(key_link_to_content_hash(), link, content_hash).await?;
+    Ok(())
+}
+
+#[tracing::instrument(skip(redis_links))]
+pub async fn is_no_follow(mut redis_links: MultiplexedConnection, link: &str) -> Result<bool, LinkError> {
+    Ok(redis_links.sismember(key_no_follow_links(), canonicalize(link)).await?)
+}
+
+#[tracing::instrument(skip(redis_links))]
+pub async fn set_content_size(mut redis_links: MultiplexedConnection, link: &str, content_size: usize) -> Result<(), LinkError> {
     Ok(redis_links.hset(key_link_to_content_size(), link, content_size).await?)
 }
 
 #[tracing::instrument(skip(redis_links))]
-pub async fn links_with_status(mut redis_links: MultiplexedConnection, status: LinkStatus) -> Result<usize, Error> {
+pub async fn links_with_status(mut redis_links: MultiplexedConnection, status: LinkStatus) -> Result<usize, LinkError> {
     Ok(redis_links.zcard(key_status_to_links(status)).await?)
 }
 
 #[tracing::instrument(skip(redis_links))]
-pub async fn total_content_size(mut redis_links: MultiplexedConnection) -> Result<u64, Error> {
+pub async fn total_content_size(mut redis_links: MultiplexedConnection) -> Result<u64, LinkError> {
     let sizes: Vec<String> = redis_links.hvals(key_link_to_content_size()).await?;
     Ok(sizes.iter().filter_map(|v| v.parse::<u64>().ok()).sum())
 }
 
 #[tracing::instrument(skip(redis_links))]
-pub async fn is_domain_waiting(mut redis_links: MultiplexedConnection, domain: &str) -> Result<bool, Error> {
+pub async fn is_domain_waiting(mut redis_links: MultiplexedConnection, domain: &str) -> Result<bool, LinkError> {
     Ok(redis_links.exists(key_domain_to_waiting_links(domain)).await?)
 }
 
+#[derive(Debug, Clone, Copy)]
+pub struct DomainStats {
+    /// Links waiting to be processed
+    pub waiting: usize,
+    pub is_domain_waiting: bool,
+    pub is_domain_processing: bool,
+    /// Links on the domain with any status
+    pub total: usize,
+}
+
+/// Counting the total scans every link, so is slow with a lot of links
 #[tracing::instrument(skip(redis_links))]
-pub async fn domains_in_system(mut redis_links: MultiplexedConnection) -> Result<usize, Error> {
-    let processing: usize = redis_links.scard(key_processing_domains()).await?;
-    let waiting: usize = redis_links.scard(key_waiting_domains()).await?;
-    Ok(processing + waiting)
+pub async fn get_domain_stats(mut redis_links: MultiplexedConnection, domain: &str) -> Result<DomainStats, LinkError> {
+    let waiting: usize = redis_links.zcard(key_domain_to_waiting_links(domain)).await?;
+    let is_domain_waiting: bool = redis_links.sismember(key_waiting_domains(), domain).await?;
+    let is_domain_processing: bool = redis_links.sismember(key_processing_domains(), domain).await?;
+
+    let mut total = 0;
+    let mut iter = redis_links.hscan::<_, (String, String)>(key_link_to_domain()).await?;
+    while let Some((_, link_domain)) = iter.next_item().await {
+        if link_domain == domain {
+            total += 1;
+        }
+    }
+
+    Ok(DomainStats { waiting, is_domain_waiting, is_domain_processing, total })
 }
 
 #[tracing::instrument(skip(redis_links))]
-pub async fn get_links_by_status(mut redis_links: MultiplexedConnection, status: LinkStatus) -> Result<Vec<String>, Error> {
+pub async fn domains_in_system(mut redis_links: MultiplexedConnection) -> Result<usize, LinkError> {
+    // domains with free slots can be both processing and waiting
+    let domains: Vec<String> = redis_links.sunion(&[key_processing_domains(), key_waiting_domains()]).await?;
+    Ok(domains.len())
+}
+
+#[tracing::instrument(skip(redis_links))]
+pub async fn get_links_by_status(mut redis_links: MultiplexedConnection, status: LinkStatus) -> Result<Vec<String>, LinkError> {
     Ok(redis::cmd("zrange").arg(key_status_to_links(status)).arg("0").arg("-1").query_async(&mut redis_links).await?)
 }
 
+/// Returns one page of the links with a status, highest priority first
+#[tracing::instrument(skip(redis_links))]
+pub async fn get_links_by_status_range(
+    mut redis_links: MultiplexedConnection, 
+    status: LinkStatus, 
+    offset: usize, 
+    limit: usize
+) -> Result<Vec<String>, LinkError> {
+    if limit == 0 {
+        return Ok(vec![]);
+    }
+    // out of range indexes just return nothing, so huge values are clamped rather than wrapping
+    let start = isize::try_from(offset).unwrap_or(isize::MAX);
+    let stop = isize::try_from(offset.saturating_add(limit - 1)).unwrap_or(isize::MAX);
+    Ok(redis_links.zrevrange(key_status_to_links(status), start, stop).await?)
+}
+
+/// Fetches the priority and parent of each link with a single pipeline
 #[tracing::instrument(skip(redis_links))]
-pub async fn update_status(mut redis_links: MultiplexedConnection, link: &str, status: LinkStatus) -> Result<(), Error> {
+pub async fn get_links_detailed(mut redis_links: MultiplexedConnection, links: &[String]) -> Result<Vec<(f32, Option<String>)>, LinkError> {
+    if links.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let mut pipe = redis::pipe();
+    for link in links {
+        pipe.hget(key_link_to_priority(), link)
+            .hget(key_link_to_parent(), link);
+    }
+
+    Ok(pipe.query_async(&mut redis_links).await?)
+}
+
+#[tracing::instrument(skip(redis_links))]
+pub async fn update_status(mut redis_links: MultiplexedConnection, link: &str, status: LinkStatus) -> Result<(), LinkError> {
     let previous_status = get_status(redis_links.clone(), link).await?;
     let priority = get_priority(redis_links.clone(), link).await?;
     let domain = get_domain(redis_links.clone(), link).await?;
@@ -234,35 +774,166 @@ pub async fn update_status(mut redis_links: MultiplexedConnection, link: &str, s
         pipe.zrem(key_domain_to_waiting_links(&domain), link);
     }
 
+    if status == LinkStatus::Processing {
+        pipe.zadd(key_processing_started_at(), link, Utc::now().timestamp_millis());
+    } else if previous_status == LinkStatus::Processing {
+        pipe.zrem(key_processing_started_at(), link);
+    }
+
+    if status == LinkStatus::Processed {
+        pipe.zadd(key_processed_at(), link, Utc::now().timestamp_millis());
+    } else if previous_status == LinkStatus::Processed {
+        pipe.zrem(key_processed_at(), link);
+    }
+
     pipe.exec_async(&mut redis_links).await?;
 
-    // Results of previous computation are required here so use a new pipe
-    let mut pipe = redis::pipe();
     if status == LinkStatus::Processing {
-        pipe.srem(key_waiting_domains(), domain.clone());
-        pipe.sadd(key_processing_domains(), domain.clone());
+        claim_domain_slot(redis_links.clone(), &domain).await?;
     }
 
     if previous_status == LinkStatus::Processing {
-        pipe.srem(key_processing_domains(), domain.clone());
-        if is_domain_waiting(redis_links.clone(), &domain).await? {
-            pipe.sadd(key_waiting_domains(), domain.clone());
-        }
+        release_domain_slots(redis_links.clone(), &domain, 1).await?;
+    }
+
+    update_domain_waiting(redis_links.clone(), &domain).await?;
+
+    Ok(())
+}
+
+/// Removes a link along with every index entry that refers to it
+/// Returns false if the link did not exist
+#[tracing::instrument(skip(redis_links))]
+pub async fn remove(mut redis_links: MultiplexedConnection, link: &str) -> Result<bool, LinkError> {
+    let link = &canonicalize(link);
+    if !exists(redis_links.clone(), link).await? {
+        return Ok(false);
+    }
+
+    let status = get_status(redis_links.clone(), link).await?;
+    let domain = get_domain(redis_links.clone(), link).await?;
+    let parent = get_parent(redis_links.clone(), link).await?;
+
+    let mut pipe = redis::pipe();
+    if let Some(parent) = parent {
+        pipe.srem(key_link_to_children(&canonicalize(&parent)), link);
+    }
+    pipe.zrem(key_status_to_links(status), link)
+        .zrem(key_domain_to_waiting_links(&domain), link)
+        .hdel(key_link_to_status(), link)
+        .hdel(key_link_to_priority(), link)
+        .hdel(key_link_to_domain(), link)
+        .hdel(key_link_to_parent(), link)
+        .hdel(key_link_to_remaining_follows(), link)
+        .hdel(key_link_to_depth(), link)
+        .hdel(key_link_to_content_size(), link)
+        .hdel(key_link_to_original(), link)
+        .zrem(key_processing_started_at(), link)
+        .hdel(key_link_to_content_hash(), link)
+        .zrem(key_processed_at(), link)
+        .del(key_link_to_children(link))
+        .srem(key_no_follow_links(), link);
+    pipe.exec_async(&mut redis_links).await?;
+
+    if status == LinkStatus::Processing {
+        release_domain_slots(redis_links.clone(), &domain, 1).await?;
+    }
+
+    update_domain_waiting(redis_links.clone(), &domain).await?;
+
+    Ok(true)
+}
+
+/// Sets the priority of a link, eg to bump a link that is already queued
+/// The link only gets rescored in its domain's queue if it is still waiting
+#[tracing::instrument(skip(redis_links))]
+pub async fn set_priority(mut redis_links: MultiplexedConnection, link: &str, priority: f32) -> Result<(), LinkError> {
+    let link = &canonicalize(link);
+    let status = get_status(redis_links.clone(), link).await?;
+    let domain = get_domain(redis_links.clone(), link).await?;
+
+    let mut pipe = redis::pipe();
+    pipe.hset(key_link_to_priority(), link, priority)
+        .zadd(key_status_to_links(status), link, priority);
+
+    if status == LinkStatus::Waiting {
+        pipe.zadd(key_domain_to_waiting_links(&domain), link, priority);
     }
 
     pipe.exec_async(&mut redis_links).await?;
 
+    update_domain_priority(redis_links.clone(), &domain).await?;
+
     Ok(())
 }
 
+/// Sets the priority of all of a domain's waiting links
+/// Returns the number of links updated
 #[tracing::instrument(skip(redis_links))]
-async fn exists(mut redis_links: MultiplexedConnection, link: &str) -> Result<bool, Error> {
-    Ok(redis_links.hexists(key_link_to_status(), link).await?)
+pub async fn set_domain_priority(mut redis_links: MultiplexedConnection, domain: &str, priority: f32) -> Result<usize, LinkError> {
+    let links: Vec<String> = redis_links.zrange(key_domain_to_waiting_links(domain), 0, -1).await?;
+    if links.is_empty() {
+        return Ok(0);
+    }
+
+    let mut pipe = redis::pipe();
+    for link in &links {
+        pipe.zadd(key_domain_to_waiting_links(domain), link, priority)
+            .zadd(key_status_to_links(LinkStatus::Waiting), link, priority)
+            .hset(key_link_to_priority(), link, priority);
+    }
+    pipe.exec_async(&mut redis_links).await?;
+
+    update_domain_priority(redis_links.clone(), domain).await?;
+
+    Ok(links.len())
 }
 
 #[tracing::instrument(skip(redis_links))]
-pub async fn poll_next_jobs(mut redis_links: MultiplexedConnection, count: usize) -> Result<Vec<String>, Error> {
-    let next_domains: Vec<String> = redis::cmd("SPOP").arg(key_waiting_domains()).arg(count).query_async(&mut redis_links).await?;
+async fn exists(mut redis_links: MultiplexedConnection, link: &str) -> Result<bool, LinkError> {
+    Ok(redis_links.hexists(key_link_to_status(), canonicalize(link)).await?)
+}
+
+/// Picks up to `count` domains at random, weighting each by e^priority (priorities can be
+/// negative), so a domain is e^2 (~7) times as likely to be picked as one with a priority 2
+/// lower, but every domain has some chance of being picked
+fn sample_by_priority(domains: Vec<(String, f64)>, count: usize, rng: &mut impl Rng) -> Vec<String> {
+    // weighted sampling without replacement (Efraimidis-Spirakis), taking the largest keys
+    // u^(1/weight), or equivalently ln(u)/weight, which doesn't underflow
+    let mut keyed: Vec<(f64, String)> = domains.into_iter()
+        .map(|(domain, priority)| ((1.0 - rng.gen_range(0.0..1.0_f64)).ln() * (-priority).exp(), domain))
+        .collect();
+    keyed.sort_by(|a, b| b.0.total_cmp(&a.0));
+    keyed.into_iter()
+        .take(count)
+        .map(|(_, domain)| domain)
+        .collect()
+}
+
+/// Takes the highest priority waiting link from each of up to `count` domains and marks them as
+/// processing. Domains with free slots (see `set_domain_slots`) are put back in the waiting set,
+/// so can be polled again for their next link
+/// Domains are picked uniformly at random, or if `by_priority` is set, weighted by their highest
+/// priority waiting link (see `sample_by_priority`)
+#[tracing::instrument(skip(redis_links))]
+pub async fn poll_next_jobs(mut redis_links: MultiplexedConnection, count: usize, by_priority: bool) -> Result<Vec<String>, LinkError> {
+    if count == 0 {
+        return Ok(vec![]);
+    }
+
+    let next_domains: Vec<String> = if by_priority {
+        let domains: Vec<(String, f64)> = redis_links.zrange_withscores(key_domains_by_priority(), 0, -1).await?;
+        let domains = sample_by_priority(domains, count, &mut rand::thread_rng());
+        if !domains.is_empty() {
+            let mut pipe = redis::pipe();
+            pipe.srem(key_waiting_domains(), &domains)
+                .zrem(key_domains_by_priority(), &domains);
+            pipe.exec_async(&mut redis_links).await?;
+        }
+        domains
+    } else {
+        redis::cmd("SPOP").arg(key_waiting_domains()).arg(count).query_async(&mut redis_links).await?
+    };
 
     let mut futures = JoinSet::new();
     for domain in next_domains {
@@ -294,3 +965,343 @@ pub async fn poll_next_jobs(mut redis_links: MultiplexedConnection, count: usize
     Ok(next_links)
 }
 
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::test_redis;
+
+    use super::*;
+
+    #[test]
+    fn canonicalize_removes_tracking_parameters_only() {
+        assert_eq!(canonicalize("https://Example.com/pancakes/#method"), "https://example.com/pancakes");
+        assert_eq!(canonicalize("https://example.com/?utm_source=x&fbclid=y"), "https://example.com/");
+        assert_eq!(
+            canonicalize("https://example.com/search?q=a+b&utm_medium=email&tag=caf%C3%A9&empty"),
+            "https://example.com/search?q=a+b&tag=caf%C3%A9&empty",
+        );
+        assert_eq!(canonicalize("not a link"), "not a link");
+    }
+
+    #[test]
+    fn every_status_is_read_back_as_itself() {
+        for status in LinkStatus::ALL {
+            assert_eq!(LinkStatus::from_string(status.to_string()), Some(status));
+        }
+        assert_eq!(LinkStatus::from_string("parsing_failed"), Some(LinkStatus::ParsingFailed));
+        assert_eq!(LinkStatus::from_string("Waiting"), None);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn links_without_a_domain_are_rejected() {
+        let (_guard, redis) = test_redis::connection().await;
+
+        for link in ["mailto:chef@example.com", "http://127.0.0.1/pancakes"] {
+            let err = add(redis.clone(), link, None, 0.0, 0, true, None).await.unwrap_err();
+            assert!(matches!(&err, LinkError::MissingDomain { link: missing } if missing == link), "{err}");
+            assert_eq!(err.to_string(), format!("Link did not have a domain: {link}"));
+        }
+
+        // without a scheme it isn't a URL at all
+        let err = add(redis.clone(), "example.com/pancakes", None, 0.0, 0, true, None).await.unwrap_err();
+        assert!(matches!(err, LinkError::InvalidUrl(_)), "{err}");
+
+        assert!(!exists(redis.clone(), "mailto:chef@example.com").await.unwrap());
+    }
+
+    #[test]
+    fn sample_by_priority_favours_but_does_not_starve_low_priority_domains() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let domains = vec![("high.com".to_string(), 0.0), ("low.com".to_string(), -2.0)];
+
+        let mut high_first = 0;
+        for _ in 0..10_000 {
+            let sampled = sample_by_priority(domains.clone(), 1, &mut rng);
+            assert_eq!(sampled.len(), 1);
+            if sampled[0] == "high.com" {
+                high_first += 1;
+            }
+        }
+
+        // picked with probability 1 / (1 + e^-2), about 88% of the time
+        assert!((8600..9000).contains(&high_first), "{high_first}");
+        let mut all = sample_by_priority(domains.clone(), 5, &mut rng);
+        all.sort();
+        assert_eq!(all, vec!["high.com", "low.com"]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn poll_by_priority_takes_high_priority_domains_first_and_most_often() {
+        let (_guard, redis) = test_redis::connection().await;
+
+        let mut high_first = 0;
+        for i in 0..50 {
+            let high = format!("https://high.com/{i}");
+            let low = format!("https://low.com/{i}");
+            add(redis.clone(), &high, None, 5.0, 5, true, None).await.unwrap();
+            add(redis.clone(), &low, None, 0.0, 5, true, None).await.unwrap();
+
+            let first = poll_next_jobs(redis.clone(), 1, true).await.unwrap();
+            let second = poll_next_jobs(redis.clone(), 1, true).await.unwrap();
+            if first == vec![high.clone()] {
+                high_first += 1;
+                assert_eq!(second, vec![low.clone()]);
+            } else {
+                assert_eq!((first, second), (vec![low.clone()], vec![high.clone()]));
+            }
+            update_status(redis.clone(), &high, LinkStatus::Processed).await.unwrap();
+            update_status(redis.clone(), &low, LinkStatus::Processed).await.unwrap();
+        }
+
+        // the low priority domain goes first with probability 1 / (1 + e^5), under 1%
+        assert!(high_first >= 45, "{high_first}");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn domain_slots_limit_links_processing_at_once() {
+        let (_guard, redis) = test_redis::connection().await;
+
+        set_domain_slots(redis.clone(), 2).await.unwrap();
+        for page in ["a", "b", "c"] {
+            add(redis.clone(), &format!("https://example.com/{page}"), None, 0.0, 1, true, None).await.unwrap();
+        }
+
+        // one link per domain per poll, until both slots are taken
+        assert_eq!(poll_next_jobs(redis.clone(), 10, false).await.unwrap().len(), 1);
+        let second = poll_next_jobs(redis.clone(), 10, false).await.unwrap();
+        assert_eq!(second.len(), 1);
+        assert!(poll_next_jobs(redis.clone(), 10, false).await.unwrap().is_empty());
+        assert_eq!(get_links_by_status(redis.clone(), LinkStatus::Processing).await.unwrap().len(), 2);
+
+        update_status(redis.clone(), &second[0], LinkStatus::Processed).await.unwrap();
+        assert_eq!(poll_next_jobs(redis.clone(), 10, false).await.unwrap().len(), 1);
+        assert!(poll_next_jobs(redis.clone(), 10, false).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn link_graph_is_limited_by_depth_and_nodes() {
+        let (_guard, redis) = test_redis::connection().await;
+
+        let root = "https://example.com/root";
+        add(redis.clone(), root, None, 1.0, 3, true, None).await.unwrap();
+        for child in ["a", "b"] {
+            let child = format!("https://example.com/{child}");
+            add(redis.clone(), &child, Some(root), 1.0, 2, true, None).await.unwrap();
+            add(redis.clone(), &format!("{child}/grandchild"), Some(&child), 1.0, 1, true, None).await.unwrap();
+        }
+
+        let graph = get_link_graph(redis.clone(), root, 1, 100).await.unwrap();
+        assert_eq!(graph.len(), 3);
+        assert_eq!(graph[0].0, root);
+        assert_eq!(graph[0].1.len(), 2);
+
+        assert_eq!(get_link_graph(redis.clone(), root, 2, 100).await.unwrap().len(), 5);
+        assert_eq!(get_link_graph(redis.clone(), root, 2, 2).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn remove_only_frees_domain_for_processing_links() {
+        let (_guard, redis) = test_redis::connection().await;
+
+        for page in ["a", "b", "c"] {
+            add(redis.clone(), &format!("https://example.com/{page}"), None, 0.0, 1, true, None).await.unwrap();
+        }
+        let processing = poll_next_jobs(redis.clone(), 10, false).await.unwrap();
+        assert_eq!(processing.len(), 1);
+
+        // removing a waiting link leaves the domain busy with the processing one
+        let waiting = get_links_by_status(redis.clone(), LinkStatus::Waiting).await.unwrap();
+        assert!(remove(redis.clone(), &waiting[0]).await.unwrap());
+        assert!(poll_next_jobs(redis.clone(), 10, false).await.unwrap().is_empty());
+
+        assert!(remove(redis.clone(), &processing[0]).await.unwrap());
+        assert!(!remove(redis.clone(), &processing[0]).await.unwrap());
+        assert_eq!(poll_next_jobs(redis.clone(), 10, false).await.unwrap(), vec![waiting[1].clone()]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn requeue_failed_respects_max_domain_waiting_links() {
+        let (_guard, redis) = test_redis::connection().await;
+
+        set_max_domain_waiting_links(redis.clone(), Some(2)).await.unwrap();
+        for (page, priority) in [("a", 1.0), ("b", 3.0), ("c", 2.0)] {
+            let link = format!("https://example.com/{page}");
+            add(redis.clone(), &link, None, priority, 1, true, None).await.unwrap();
+            update_status(redis.clone(), &link, LinkStatus::Processing).await.unwrap();
+            update_status(redis.clone(), &link, LinkStatus::DownloadFailed).await.unwrap();
+        }
+        add(redis.clone(), "https://example.org/d", None, 0.0, 1, true, None).await.unwrap();
+        add(redis.clone(), "https://example.com/e", None, 0.0, 1, true, None).await.unwrap();
+
+        // example.com has room for one more waiting link, which goes to the highest priority
+        assert_eq!(requeue_failed(redis.clone(), LinkStatus::DownloadFailed).await.unwrap(), 1);
+        assert_eq!(get_status(redis.clone(), "https://example.com/b").await.unwrap(), LinkStatus::Waiting);
+        assert_eq!(links_with_status(redis.clone(), LinkStatus::DownloadFailed).await.unwrap(), 2);
+
+        set_max_domain_waiting_links(redis.clone(), None).await.unwrap();
+        assert_eq!(requeue_failed(redis.clone(), LinkStatus::DownloadFailed).await.unwrap(), 2);
+        assert!(requeue_failed(redis.clone(), LinkStatus::Waiting).await.is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn reset_stuck_tasks_only_resets_old_links() {
+        let (_guard, mut redis) = test_redis::connection().await;
+
+        set_domain_slots(redis.clone(), 2).await.unwrap();
+        for page in ["stuck", "fresh"] {
+            add(redis.clone(), &format!("https://example.com/{page}"), None, 1.0, 1, true, None).await.unwrap();
+        }
+        assert_eq!(poll_next_jobs(redis.clone(), 10, false).await.unwrap().len(), 1);
+        assert_eq!(poll_next_jobs(redis.clone(), 10, false).await.unwrap().len(), 1);
+        let _: () = redis.zadd(key_processing_started_at(), "https://example.com/stuck", 0).await.unwrap();
+
+        assert_eq!(reset_stuck_tasks(redis.clone(), Duration::from_secs(60)).await.unwrap(), 1);
+        assert_eq!(get_status(redis.clone(), "https://example.com/stuck").await.unwrap(), LinkStatus::Waiting);
+        assert_eq!(get_status(redis.clone(), "https://example.com/fresh").await.unwrap(), LinkStatus::Processing);
+
+        // the stuck link's slot was freed, so it can be polled again
+        assert_eq!(poll_next_jobs(redis.clone(), 10, false).await.unwrap(), vec!["https://example.com/stuck".to_string()]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn requeue_stale_only_requeues_old_links() {
+        let (_guard, mut redis) = test_redis::connection().await;
+
+        let old = "https://example.com/old";
+        let fresh = "https://example.org/fresh";
+        for link in [old, fresh] {
+            add(redis.clone(), link, None, 1.0, 1, true, None).await.unwrap();
+            update_status(redis.clone(), link, LinkStatus::Processing).await.unwrap();
+            update_status(redis.clone(), link, LinkStatus::Processed).await.unwrap();
+        }
+        let two_days_ago = Utc::now().timestamp_millis() - 2 * 24 * 60 * 60 * 1000;
+        let _: () = redis.zadd(key_processed_at(), old, two_days_ago).await.unwrap();
+
+        let requeued = requeue_stale(redis.clone(), Duration::from_secs(24 * 60 * 60), -1.0, 100).await.unwrap();
+        assert_eq!(requeued, 1);
+        assert_eq!(get_status(redis.clone(), old).await.unwrap(), LinkStatus::Waiting);
+        assert_eq!(get_priority(redis.clone(), old).await.unwrap(), -1.0);
+        assert_eq!(get_status(redis.clone(), fresh).await.unwrap(), LinkStatus::Processed);
+
+        assert_eq!(poll_next_jobs(redis.clone(), 10, false).await.unwrap(), vec![old.to_string()]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn get_links_by_status_range_pages_by_priority() {
+        let (_guard, redis) = test_redis::connection().await;
+
+        for (page, priority) in [("a", 1.0), ("b", 3.0), ("c", 2.0)] {
+            add(redis.clone(), &format!("https://example.com/{page}"), None, priority, 1, true, None).await.unwrap();
+        }
+
+        let page = get_links_by_status_range(redis.clone(), LinkStatus::Waiting, 1, 5).await.unwrap();
+        assert_eq!(page, vec!["https://example.com/c", "https://example.com/a"]);
+        assert!(get_links_by_status_range(redis.clone(), LinkStatus::Waiting, 0, 0).await.unwrap().is_empty());
+        assert!(get_links_by_status_range(redis.clone(), LinkStatus::Waiting, usize::MAX, usize::MAX).await.unwrap().is_empty());
+        assert_eq!(get_links_by_status_range(redis.clone(), LinkStatus::Waiting, 2, usize::MAX).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn single_domain_slot_by_default() {
+        let (_guard, redis) = test_redis::connection().await;
+
+        for page in ["a", "b"] {
+            add(redis.clone(), &format!("https://example.com/{page}"), None, 0.0, 1, true, None).await.unwrap();
+        }
+
+        assert_eq!(poll_next_jobs(redis.clone(), 10, true).await.unwrap().len(), 1);
+        assert!(poll_next_jobs(redis.clone(), 10, true).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn raised_priority_is_polled_first() {
+        let (_guard, redis) = test_redis::connection().await;
+
+        for (page, priority) in [("a", 3.0), ("b", 1.0), ("c", 2.0)] {
+            add(redis.clone(), &format!("https://example.com/{page}"), None, priority, 1, true, None).await.unwrap();
+        }
+        set_priority(redis.clone(), "https://example.com/b", 5.0).await.unwrap();
+
+        assert_eq!(get_priority(redis.clone(), "https://example.com/b").await.unwrap(), 5.0);
+        assert_eq!(poll_next_jobs(redis.clone(), 1, false).await.unwrap(), vec!["https://example.com/b".to_string()]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn set_domain_priority_rescores_only_waiting_links() {
+        let (_guard, mut redis) = test_redis::connection().await;
+
+        for (page, priority) in [("a", 1.0), ("b", 2.0), ("c", 3.0)] {
+            add(redis.clone(), &format!("https://example.com/{page}"), None, priority, 1, true, None).await.unwrap();
+        }
+        add(redis.clone(), "https://example.org/d", None, 4.0, 1, true, None).await.unwrap();
+        update_status(redis.clone(), "https://example.com/c", LinkStatus::Processing).await.unwrap();
+
+        assert_eq!(set_domain_priority(redis.clone(), "example.com", 10.0).await.unwrap(), 2);
+        for link in ["https://example.com/a", "https://example.com/b"] {
+            assert_eq!(get_priority(redis.clone(), link).await.unwrap(), 10.0);
+            let score: f32 = redis.zscore(key_status_to_links(LinkStatus::Waiting), link).await.unwrap();
+            assert_eq!(score, 10.0);
+        }
+        assert_eq!(get_priority(redis.clone(), "https://example.com/c").await.unwrap(), 3.0);
+        assert_eq!(get_priority(redis.clone(), "https://example.org/d").await.unwrap(), 4.0);
+
+        assert_eq!(set_domain_priority(redis.clone(), "example.net", 10.0).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn full_domain_queue_keeps_the_highest_priority_links() {
+        let (_guard, mut redis) = test_redis::connection().await;
+
+        let max = 3;
+        for i in 0..max + 5 {
+            let link = format!("https://example.com/{i}");
+            assert!(add(redis.clone(), &link, None, i as f32, 1, true, Some(max)).await.unwrap());
+            let waiting: usize = redis.zcard(key_domain_to_waiting_links("example.com")).await.unwrap();
+            assert!(waiting <= max, "{waiting}");
+        }
+
+        let mut waiting = get_links_by_status(redis.clone(), LinkStatus::Waiting).await.unwrap();
+        waiting.sort();
+        assert_eq!(waiting, vec!["https://example.com/5", "https://example.com/6", "https://example.com/7"]);
+        assert!(!exists(redis.clone(), "https://example.com/0").await.unwrap());
+
+        // a link lower than everything already queued is rejected instead
+        assert!(!add(redis.clone(), "https://example.com/low", None, -1.0, 1, true, Some(max)).await.unwrap());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn link_graph_of_a_deep_chain_stops_at_max_depth() {
+        let (_guard, redis) = test_redis::connection().await;
+
+        add(redis.clone(), "https://example.com/0", None, 0.0, 1, true, None).await.unwrap();
+        for i in 1..20 {
+            let parent = format!("https://example.com/{}", i - 1);
+            add(redis.clone(), &format!("https://example.com/{i}"), Some(&parent), 0.0, 1, true, None).await.unwrap();
+        }
+        assert_eq!(get_depth(redis.clone(), "https://example.com/19").await.unwrap(), 19);
+
+        let graph = get_link_graph(redis.clone(), "https://example.com/0", 5, 100).await.unwrap();
+        let links: Vec<&str> = graph.iter().map(|(link, _)| link.as_str()).collect();
+        assert_eq!(links, (0..=5).map(|i| format!("https://example.com/{i}")).collect::<Vec<_>>());
+        // the deepest link lists its child without expanding it
+        assert_eq!(graph[5].1, vec!["https://example.com/6"]);
+
+        assert_eq!(get_link_graph(redis.clone(), "https://example.com/0", 100, 100).await.unwrap().len(), 20);
+    }
+}
@@ -0,0 +1,149 @@
+use std::{collections::HashSet, sync::LazyLock, time::Instant};
+
+use redis::{aio::MultiplexedConnection, AsyncCommands, RedisError};
+use tokio::sync::RwLock;
+
+use crate::link_blacklist::{is_on_domain, refresh_interval};
+
+static ALLOWLIST: LazyLock<RwLock<Option<Allowlist>>> = LazyLock::new(|| RwLock::new(None));
+
+/// When not empty, only links on these domains (or their subdomains) are crawled
+#[derive(Debug)]
+struct Allowlist {
+    domains: HashSet<String>,
+    loaded_at: Instant,
+}
+
+impl Allowlist {
+    fn is_allowed(&self, link: &str) -> bool {
+        self.domains.is_empty() || is_on_domain(&self.domains, link)
+    }
+}
+
+// SET of allowed domains
+fn key_allowlist_domains() -> String {
+    "allowlist:domains".to_string()
+}
+
+/// Returns true if added
+/// Returns false if already existed
+#[tracing::instrument(skip(pool))]
+pub async fn add(mut pool: MultiplexedConnection, domain: &str) -> Result<bool, RedisError> {
+    let added: usize = pool.sadd(key_allowlist_domains(), domain.to_lowercase()).await?;
+    invalidate().await;
+    Ok(added > 0)
+}
+
+/// Returns true if removed
+/// Returns false if it wasn't on the allowlist
+#[tracing::instrument(skip(pool))]
+pub async fn remove(mut pool: MultiplexedConnection, domain: &str) -> Result<bool, RedisError> {
+    let removed: usize = pool.srem(key_allowlist_domains(), domain.to_lowercase()).await?;
+    invalidate().await;
+    Ok(removed > 0)
+}
+
+#[tracing::instrument(skip(pool))]
+async fn load(mut pool: MultiplexedConnection) -> Result<Allowlist, RedisError> {
+    let domains: HashSet<String> = pool.smembers(key_allowlist_domains()).await?;
+    Ok(Allowlist { domains, loaded_at: Instant::now() })
+}
+
+/// Drops the cached allowlist, so the next check reloads it from Redis
+/// Only affects this process; other processes pick up changes on their next refresh
+pub async fn invalidate() {
+    *ALLOWLIST.write().await = None;
+}
+
+/// Always true if the allowlist is empty
+/// The allowlist is cached and reloaded at most every refresh interval (shared with the
+/// blacklist), so changes made by other processes can take that long to apply
+#[tracing::instrument(skip(pool))]
+pub async fn is_allowed(pool: MultiplexedConnection, link: &str) -> Result<bool, RedisError> {
+    if let Some(allowlist) = ALLOWLIST.read().await.as_ref()
+        && allowlist.loaded_at.elapsed() < refresh_interval()
+    {
+        return Ok(allowlist.is_allowed(link));
+    }
+
+    // the write lock is held while loading so that only one task reloads at a time, and any
+    // others waiting for it use what it loaded
+    let mut cached = ALLOWLIST.write().await;
+    if let Some(allowlist) = cached.as_ref()
+        && allowlist.loaded_at.elapsed() < refresh_interval()
+    {
+        return Ok(allowlist.is_allowed(link));
+    }
+
+    let allowlist = load(pool).await?;
+    let is_allowed = allowlist.is_allowed(link);
+    *cached = Some(allowlist);
+
+    Ok(is_allowed)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_redis;
+
+    use super::*;
+
+    fn allowlist(domains: &[&str]) -> Allowlist {
+        Allowlist {
+            domains: domains.iter().map(|v| v.to_string()).collect(),
+            loaded_at: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn empty_allowlist_allows_everything() {
+        assert!(allowlist(&[]).is_allowed("https://example.com/pancakes"));
+    }
+
+    #[test]
+    fn allowlist_allows_only_its_domains_and_their_subdomains() {
+        let allowlist = allowlist(&["example.com"]);
+
+        assert!(allowlist.is_allowed("https://example.com/pancakes"));
+        assert!(allowlist.is_allowed("https://www.Example.com/pancakes"));
+        assert!(!allowlist.is_allowed("https://notexample.com/pancakes"));
+        assert!(!allowlist.is_allowed("https://example.com.evil.com/pancakes"));
+        assert!(!allowlist.is_allowed("not a link"));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn adding_and_removing_domains_invalidates_the_cache() {
+        let (_guard, redis) = test_redis::connection().await;
+        invalidate().await;
+
+        assert!(is_allowed(redis.clone(), "https://other.com/").await.unwrap());
+
+        assert!(add(redis.clone(), "Example.com").await.unwrap());
+        assert!(!add(redis.clone(), "example.com").await.unwrap());
+        assert!(is_allowed(redis.clone(), "https://www.example.com/").await.unwrap());
+        assert!(!is_allowed(redis.clone(), "https://other.com/").await.unwrap());
+
+        assert!(remove(redis.clone(), "example.com").await.unwrap());
+        assert!(!remove(redis.clone(), "example.com").await.unwrap());
+        assert!(is_allowed(redis.clone(), "https://other.com/").await.unwrap());
+        invalidate().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn changes_from_other_processes_apply_once_invalidated() {
+        let (_guard, mut redis) = test_redis::connection().await;
+        invalidate().await;
+
+        assert!(is_allowed(redis.clone(), "https://other.com/").await.unwrap());
+
+        // as another process would, without invalidating this one's cache
+        let _: () = redis.sadd(key_allowlist_domains(), "example.com").await.unwrap();
+        assert!(is_allowed(redis.clone(), "https://other.com/").await.unwrap());
+
+        invalidate().await;
+        assert!(!is_allowed(redis.clone(), "https://other.com/").await.unwrap());
+        invalidate().await;
+    }
+}
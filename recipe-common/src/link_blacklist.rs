@@ -1,38 +1,262 @@
+use std::{collections::HashSet, sync::{atomic::{AtomicU64, Ordering}, LazyLock}, time::{Duration, Instant}};
+
 use anyhow::Error;
-use redis::{aio::MultiplexedConnection, AsyncCommands};
+use log::warn;
+use redis::{aio::MultiplexedConnection, AsyncCommands, RedisError};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+use url::Url;
+use utoipa::ToSchema;
+
+static REFRESH_INTERVAL_MILLIS: AtomicU64 = AtomicU64::new(60_000);
+
+static BLACKLIST: LazyLock<RwLock<Option<Blacklist>>> = LazyLock::new(|| RwLock::new(None));
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchType {
+    /// Blocks links containing the word anywhere
+    Substring,
+    /// Blocks links on the domain or any of its subdomains, eg 'example.com' blocks
+    /// 'www.example.com' but not 'notexample.com'
+    Domain,
+    /// Blocks links matching the regex
+    Regex,
+}
+
+/// The blacklist with its regexes compiled, so links can be checked without going to Redis
+#[derive(Debug)]
+struct Blacklist {
+    substrings: Vec<String>,
+    domains: HashSet<String>,
+    regexes: Vec<Regex>,
+    loaded_at: Instant,
+}
+
+impl Blacklist {
+    fn is_allowed(&self, link: &str) -> bool {
+        if self.substrings.iter().any(|word| link.contains(word.as_str())) {
+            return false;
+        }
+
+        if is_on_domain(&self.domains, link) {
+            return false;
+        }
+
+        !self.regexes.iter().any(|regex| regex.is_match(link))
+    }
+}
+
+/// Whether the link is on one of the domains or any of their subdomains
+pub(crate) fn is_on_domain(domains: &HashSet<String>, link: &str) -> bool {
+    let Some(host) = Url::parse(link).ok().and_then(|url| url.host_str().map(|v| v.to_lowercase())) else {
+        return false;
+    };
+
+    // eg www.example.com -> www.example.com, example.com, com
+    let mut domain = host.as_str();
+    loop {
+        if domains.contains(domain) {
+            return true;
+        }
+        let Some((_, parent)) = domain.split_once('.') else {
+            return false;
+        };
+        domain = parent;
+    }
+}
 
+// SET of words blocked by substring
 fn key_blacklist() -> String {
     "blacklist".to_string()
 }
 
+// SET of blocked domains
+fn key_blacklist_domains() -> String {
+    "blacklist:domains".to_string()
+}
+
+// SET of blocked regexes
+fn key_blacklist_regexes() -> String {
+    "blacklist:regexes".to_string()
+}
+
+fn key(match_type: MatchType) -> String {
+    match match_type {
+        MatchType::Substring => key_blacklist(),
+        MatchType::Domain => key_blacklist_domains(),
+        MatchType::Regex => key_blacklist_regexes(),
+    }
+}
+
 /// Returns true if added
 /// Returns false if already existed
+/// Fails if `match_type` is regex and `word` is not a valid regex
 #[tracing::instrument(skip(pool))]
-pub async fn add(mut pool: MultiplexedConnection, word: &str) -> Result<bool, Error> {
-    if exists(pool.clone(), word).await? {
+pub async fn add(mut pool: MultiplexedConnection, word: &str, match_type: MatchType) -> Result<bool, Error> {
+    if match_type == MatchType::Regex {
+        Regex::new(word)?;
+    }
+
+    let word = match match_type {
+        MatchType::Domain => word.to_lowercase(),
+        _ => word.to_owned(),
+    };
+
+    if exists(pool.clone(), &word, match_type).await? {
         return Ok(false);
     }
 
-    let _: () = pool.sadd(key_blacklist(), word).await?;
-   
+    let _: () = pool.sadd(key(match_type), &word).await?;
+
+    invalidate().await;
+
     Ok(true)
 }
 
 #[tracing::instrument(skip(pool))]
-async fn exists(mut pool: MultiplexedConnection, word: &str) -> Result<bool, Error> {
-    Ok(pool.sismember(key_blacklist(), word).await?)
+async fn exists(mut pool: MultiplexedConnection, word: &str, match_type: MatchType) -> Result<bool, Error> {
+    Ok(pool.sismember(key(match_type), word).await?)
 }
 
 #[tracing::instrument(skip(pool))]
-pub async fn is_allowed(mut pool: MultiplexedConnection, link: &str) -> Result<bool, Error> {
-    let blacklist: Vec<String> = pool.smembers(key_blacklist()).await?;
+async fn load(mut pool: MultiplexedConnection) -> Result<Blacklist, RedisError> {
+    let substrings: Vec<String> = pool.smembers(key_blacklist()).await?;
+    let domains: HashSet<String> = pool.smembers(key_blacklist_domains()).await?;
+    let regexes: Vec<String> = pool.smembers(key_blacklist_regexes()).await?;
 
-    for word in blacklist {
-        if link.contains(&word) {
-            return Ok(false);
-        }
+    let regexes = regexes.iter()
+        .filter_map(|v| match Regex::new(v) {
+            Ok(regex) => Some(regex),
+            Err(err) => {
+                warn!("Ignoring invalid blacklist regex '{v}': {err}");
+                None
+            }
+        })
+        .collect();
+
+    Ok(Blacklist { substrings, domains, regexes, loaded_at: Instant::now() })
+}
+
+/// Sets how often the cached blacklist (and allowlist) is reloaded from Redis (default 60s)
+pub fn set_refresh_interval(interval: Duration) {
+    REFRESH_INTERVAL_MILLIS.store(interval.as_millis() as u64, Ordering::Relaxed);
+}
+
+pub(crate) fn refresh_interval() -> Duration {
+    Duration::from_millis(REFRESH_INTERVAL_MILLIS.load(Ordering::Relaxed))
+}
+
+/// Drops the cached blacklist, so the next check reloads it from Redis
+/// Only affects this process; other processes pick up changes on their next refresh
+pub async fn invalidate() {
+    *BLACKLIST.write().await = None;
+}
+
+/// The blacklist is cached and reloaded at most every refresh interval, so changes made by
+/// other processes can take that long to apply
+#[tracing::instrument(skip(pool))]
+pub async fn is_allowed(pool: MultiplexedConnection, link: &str) -> Result<bool, RedisError> {
+    if let Some(blacklist) = BLACKLIST.read().await.as_ref()
+        && blacklist.loaded_at.elapsed() < refresh_interval()
+    {
+        return Ok(blacklist.is_allowed(link));
     }
 
-    Ok(true)
+    // the write lock is held while loading so that only one task reloads at a time, and any
+    // others waiting for it use what it loaded
+    let mut cached = BLACKLIST.write().await;
+    if let Some(blacklist) = cached.as_ref()
+        && blacklist.loaded_at.elapsed() < refresh_interval()
+    {
+        return Ok(blacklist.is_allowed(link));
+    }
+
+    let blacklist = load(pool).await?;
+    let is_allowed = blacklist.is_allowed(link);
+    *cached = Some(blacklist);
+
+    Ok(is_allowed)
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::test_redis;
+
+    use super::*;
+
+    fn blacklist(substrings: &[&str], regexes: &[&str]) -> Blacklist {
+        Blacklist {
+            substrings: substrings.iter().map(|v| v.to_string()).collect(),
+            domains: HashSet::new(),
+            regexes: regexes.iter().map(|v| Regex::new(v).unwrap()).collect(),
+            loaded_at: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn substrings_block_links_containing_them_anywhere() {
+        let blacklist = blacklist(&["login", "/tag/"], &[]);
+
+        assert!(!blacklist.is_allowed("https://example.com/login?next=/pancakes"));
+        assert!(!blacklist.is_allowed("https://loginexample.com/"));
+        assert!(!blacklist.is_allowed("https://example.com/tag/breakfast"));
+        assert!(blacklist.is_allowed("https://example.com/tags/breakfast"));
+        // matching is case sensitive
+        assert!(blacklist.is_allowed("https://example.com/LOGIN"));
+    }
+
+    #[test]
+    fn regexes_block_matching_links() {
+        let blacklist = blacklist(&[], &[r"/page/\d+/?$", r"^https?://[^/]*\.pinterest\."]);
+
+        assert!(!blacklist.is_allowed("https://example.com/recipes/page/2"));
+        assert!(!blacklist.is_allowed("https://www.pinterest.co.uk/pin/1"));
+        assert!(blacklist.is_allowed("https://example.com/recipes/page/two"));
+        assert!(blacklist.is_allowed("https://example.com/?ref=www.pinterest.com"));
+    }
+
+    #[test]
+    fn empty_blacklist_allows_everything() {
+        assert!(blacklist(&[], &[]).is_allowed("https://example.com/login"));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn concurrent_checks_see_added_words() {
+        let (_guard, redis) = test_redis::connection().await;
+
+        invalidate().await;
+        assert!(add(redis.clone(), "example.org", MatchType::Domain).await.unwrap());
+        assert!(!add(redis.clone(), "Example.org", MatchType::Domain).await.unwrap());
+
+        let (blocked, allowed) = tokio::join!(
+            is_allowed(redis.clone(), "https://www.example.org/pancakes"),
+            is_allowed(redis.clone(), "https://notexample.org/pancakes"),
+        );
+        assert!(!blocked.unwrap());
+        assert!(allowed.unwrap());
+        invalidate().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn cached_blacklist_is_reloaded_after_the_refresh_interval() {
+        let (_guard, mut redis) = test_redis::connection().await;
+        invalidate().await;
+        set_refresh_interval(Duration::from_secs(60));
+        assert!(is_allowed(redis.clone(), "https://example.com/pancakes").await.unwrap());
+
+        // as another process would, without invalidating this one's cache
+        let _: () = redis.sadd(key_blacklist(), "pancakes").await.unwrap();
+        assert!(is_allowed(redis.clone(), "https://example.com/pancakes").await.unwrap());
+
+        set_refresh_interval(Duration::from_millis(50));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!is_allowed(redis.clone(), "https://example.com/pancakes").await.unwrap());
+
+        set_refresh_interval(Duration::from_secs(60));
+        invalidate().await;
+    }
+}
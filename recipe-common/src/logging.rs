@@ -0,0 +1,132 @@
+use std::{fmt, str::FromStr};
+
+use tracing::Subscriber;
+use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, registry::LookupSpan, util::SubscriberInitExt, EnvFilter, Layer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable lines
+    Pretty,
+    /// One JSON object per line, for ingestion into eg Loki or ELK
+    Json,
+}
+
+#[derive(Debug)]
+pub struct UnknownLogFormatError {
+    format: String,
+}
+
+impl fmt::Display for UnknownLogFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown log format '{}' (expected 'pretty' or 'json')", self.format)
+    }
+}
+
+impl std::error::Error for UnknownLogFormatError {}
+
+impl FromStr for LogFormat {
+    type Err = UnknownLogFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(UnknownLogFormatError { format: s.to_owned() }),
+        }
+    }
+}
+
+fn layer<S, W>(filter: &str, format: LogFormat, writer: W) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Pretty => tracing_subscriber::fmt::layer()
+            .with_writer(writer)
+            .with_line_number(true)
+            .with_filter(EnvFilter::new(filter))
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_writer(writer)
+            .with_line_number(true)
+            .with_filter(EnvFilter::new(filter))
+            .boxed(),
+    }
+}
+
+/// Installs the global tracing subscriber, eg with filter 'recipe_api=trace,recipe_common=trace'
+pub fn init(filter: &str, format: LogFormat) {
+    tracing_subscriber::registry()
+        .with(layer(filter, format, std::io::stdout))
+        .init();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, sync::{Arc, Mutex}};
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn log_lines(format: LogFormat) -> Vec<String> {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::registry()
+            .with(layer("recipe_common=info", format, move || writer.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(target: "recipe_common", link = "https://example.com", "Downloaded page");
+            tracing::debug!(target: "recipe_common", "Filtered by level");
+            tracing::info!(target: "other_crate", "Filtered by target");
+        });
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        output.lines().map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn formats_are_parsed_from_their_names() {
+        assert_eq!("pretty".parse::<LogFormat>().unwrap(), LogFormat::Pretty);
+        assert_eq!("json".parse::<LogFormat>().unwrap(), LogFormat::Json);
+        assert_eq!(
+            "JSON".parse::<LogFormat>().unwrap_err().to_string(),
+            "Unknown log format 'JSON' (expected 'pretty' or 'json')",
+        );
+    }
+
+    #[test]
+    fn pretty_format_writes_plain_lines() {
+        let lines = log_lines(LogFormat::Pretty);
+
+        assert_eq!(lines.len(), 1, "{lines:?}");
+        assert!(serde_json::from_str::<serde_json::Value>(&lines[0]).is_err());
+        assert!(lines[0].contains("INFO"));
+        assert!(lines[0].contains("Downloaded page"));
+        assert!(lines[0].contains("https://example.com"));
+    }
+
+    #[test]
+    fn json_format_writes_one_object_per_line() {
+        let lines = log_lines(LogFormat::Json);
+
+        assert_eq!(lines.len(), 1, "{lines:?}");
+        let line: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["target"], "recipe_common");
+        assert_eq!(line["fields"]["message"], "Downloaded page");
+        assert_eq!(line["fields"]["link"], "https://example.com");
+        assert!(line["line_number"].is_u64());
+    }
+}
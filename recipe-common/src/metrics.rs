@@ -0,0 +1,45 @@
+use std::collections::HashMap;
+
+use anyhow::Error;
+use redis::{aio::MultiplexedConnection, AsyncCommands};
+
+/// Counters incremented by the finder as links go through the pipeline
+/// These are kept in Redis so that the API can report them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Counter {
+    Downloads,
+    Extractions,
+    Parses,
+}
+
+impl Counter {
+    pub const ALL: [Counter; 3] = [Counter::Downloads, Counter::Extractions, Counter::Parses];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Counter::Downloads => "downloads",
+            Counter::Extractions => "extractions",
+            Counter::Parses => "parses",
+        }
+    }
+}
+
+// HASH
+fn key_counters() -> String {
+    "metrics:counters".to_string()
+}
+
+#[tracing::instrument(skip(redis))]
+pub async fn increment(mut redis: MultiplexedConnection, counter: Counter) -> Result<(), Error> {
+    let _: () = redis.hincr(key_counters(), counter.as_str(), 1).await?;
+    Ok(())
+}
+
+/// Counters that have never been incremented are 0
+#[tracing::instrument(skip(redis))]
+pub async fn get_counters(mut redis: MultiplexedConnection) -> Result<HashMap<&'static str, u64>, Error> {
+    let values: HashMap<String, u64> = redis.hgetall(key_counters()).await?;
+    Ok(Counter::ALL.iter()
+        .map(|v| (v.as_str(), values.get(v.as_str()).copied().unwrap_or(0)))
+        .collect())
+}
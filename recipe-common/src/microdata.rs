@@ -0,0 +1,202 @@
+use scraper::{ElementRef, Html, Selector};
+use serde_json::{Map, Value};
+
+/// Properties that the parser expects to be arrays, even if the page only has one of them
+const ARRAY_PROPERTIES: [&str; 3] = ["recipeIngredient", "recipeInstructions", "image"];
+
+/// The value of a property that isn't an item in its own right
+/// https://html.spec.whatwg.org/multipage/microdata.html#values
+fn property_value(element: ElementRef) -> Value {
+    let attribute = match element.value().name() {
+        "meta" => element.value().attr("content"),
+        "img" | "audio" | "video" | "source" => element.value().attr("src"),
+        "a" | "link" | "area" => element.value().attr("href"),
+        "time" => element.value().attr("datetime"),
+        "data" | "meter" => element.value().attr("value"),
+        _ => element.value().attr("content"),
+    };
+
+    let text = |element: ElementRef| element.text()
+        .collect::<Vec<&str>>()
+        .join(" ")
+        .trim()
+        .to_owned();
+
+    if let Some(attribute) = attribute {
+        return Value::String(attribute.to_owned());
+    }
+
+    // eg <ol itemprop="recipeInstructions"><li>...</li><li>...</li></ol>
+    let list_items: Vec<Value> = element.select(&Selector::parse("li").unwrap())
+        .map(text)
+        .filter(|v| !v.is_empty())
+        .map(Value::String)
+        .collect();
+    if list_items.len() > 1 {
+        return Value::Array(list_items);
+    }
+
+    Value::String(text(element))
+}
+
+fn insert(properties: &mut Map<String, Value>, name: &str, value: Value) {
+    let values = match value {
+        Value::Array(values) => values,
+        value => vec![value],
+    };
+
+    for value in values {
+        match properties.get_mut(name) {
+            None => {
+                properties.insert(name.to_owned(), value);
+            }
+            Some(Value::Array(existing)) => existing.push(value),
+            Some(existing) => {
+                let first = existing.take();
+                *existing = Value::Array(vec![first, value]);
+            }
+        }
+    }
+}
+
+/// Collects the properties under `element`, stopping at nested items (which own their own
+/// properties)
+fn collect(element: ElementRef, properties: &mut Map<String, Value>) {
+    for child in element.children().filter_map(ElementRef::wrap) {
+        let is_item = child.value().attr("itemscope").is_some();
+
+        if let Some(names) = child.value().attr("itemprop") {
+            let value = if is_item {
+                Value::Object(item(child))
+            } else {
+                property_value(child)
+            };
+            for name in names.split_whitespace() {
+                insert(properties, name, value.clone());
+            }
+        }
+
+        if !is_item {
+            collect(child, properties);
+        }
+    }
+}
+
+fn item(element: ElementRef) -> Map<String, Value> {
+    let mut properties = Map::new();
+
+    // eg https://schema.org/Recipe -> Recipe
+    if let Some(item_type) = element.value().attr("itemtype").and_then(|v| v.split_whitespace().next()) {
+        let item_type = item_type.trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .unwrap_or(item_type);
+        properties.insert("@type".to_owned(), Value::String(item_type.to_owned()));
+    }
+
+    collect(element, &mut properties);
+    properties
+}
+
+/// Builds a JSON-LD style schema from the first schema.org Recipe item on the page, for older
+/// sites that use microdata (itemprop attributes) instead of JSON-LD
+pub fn extract(contents: &str) -> Option<Value> {
+    let document = Html::parse_document(contents);
+    let selector = Selector::parse("[itemscope][itemtype*='schema.org/Recipe']").unwrap();
+    let element = document.select(&selector).next()?;
+
+    let mut properties = item(element);
+
+    // older pages use the data-vocabulary.org name
+    if let Some(ingredients) = properties.remove("ingredients") {
+        insert(&mut properties, "recipeIngredient", ingredients);
+    }
+
+    for name in ARRAY_PROPERTIES {
+        if let Some(value) = properties.get_mut(name)
+            && !value.is_array()
+        {
+            *value = Value::Array(vec![value.take()]);
+        }
+    }
+
+    // the parser only reads author names from objects
+    if let Some(author) = properties.get_mut("author")
+        && let Value::String(name) = author
+    {
+        let mut object = Map::new();
+        object.insert("name".to_owned(), Value::String(name.clone()));
+        *author = Value::Object(object);
+    }
+
+    Some(Value::Object(properties))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser;
+
+    use super::*;
+
+    const MICRODATA_PAGE: &str = r#"<html><body>
+        <nav><a href="/">Home</a></nav>
+        <div itemscope itemtype="http://schema.org/Recipe">
+            <h1 itemprop="name">Grandma's Apple Pie</h1>
+            <meta itemprop="description" content="A classic double-crust pie">
+            <span itemprop="author">Jane Doe</span>
+            <img itemprop="image" src="/images/pie.jpg">
+            <time itemprop="totalTime" datetime="PT1H30M">1 hour 30 minutes</time>
+            <ul>
+                <li itemprop="recipeIngredient">6 apples</li>
+                <li itemprop="recipeIngredient">200g flour</li>
+                <li itemprop="ingredients">100g butter</li>
+            </ul>
+            <ol itemprop="recipeInstructions">
+                <li>Peel and slice the apples</li>
+                <li>Line the dish with pastry and fill it</li>
+                <li>Bake for an hour</li>
+            </ol>
+            <div itemprop="aggregateRating" itemscope itemtype="http://schema.org/AggregateRating">
+                <span itemprop="ratingValue">4.5</span> from <span itemprop="ratingCount">12</span> reviews
+            </div>
+        </div>
+    </body></html>"#;
+
+    #[test]
+    fn recipe_item_is_converted_to_a_schema() {
+        let schema = extract(MICRODATA_PAGE).unwrap();
+
+        assert_eq!(schema["@type"], "Recipe");
+        assert_eq!(schema["name"], "Grandma's Apple Pie");
+        assert_eq!(schema["recipeIngredient"], serde_json::json!(["6 apples", "200g flour", "100g butter"]));
+        assert_eq!(schema["author"], serde_json::json!({ "name": "Jane Doe" }));
+        assert_eq!(schema["image"], serde_json::json!(["/images/pie.jpg"]));
+        assert_eq!(schema["aggregateRating"]["@type"], "AggregateRating");
+        assert_eq!(schema["aggregateRating"]["ratingValue"], "4.5");
+        // properties of the nested rating aren't properties of the recipe
+        assert!(schema.get("ratingValue").is_none());
+    }
+
+    #[tokio::test]
+    async fn converted_schema_is_parsed() {
+        let schema = extract(MICRODATA_PAGE).unwrap();
+        let recipe = parser::parse("https://example.com/apple-pie".to_string(), schema).await.unwrap();
+
+        assert_eq!(recipe.title, "Grandma's Apple Pie");
+        assert_eq!(recipe.ingredients, vec!["6 apples", "200g flour", "100g butter"]);
+        assert_eq!(recipe.instructions, vec![
+            "Peel and slice the apples",
+            "Line the dish with pastry and fill it",
+            "Bake for an hour",
+        ]);
+        assert_eq!(recipe.images, vec!["https://example.com/images/pie.jpg"]);
+        assert_eq!(recipe.total_time_seconds, Some(90 * 60));
+        assert_eq!(recipe.rating, Some(4.5));
+    }
+
+    #[test]
+    fn pages_without_a_recipe_item_have_no_schema() {
+        let page = r#"<div itemscope itemtype="http://schema.org/Article"><h1 itemprop="name">News</h1></div>"#;
+        assert!(extract(page).is_none());
+    }
+}
@@ -0,0 +1,697 @@
+use std::{collections::HashSet, sync::LazyLock};
+
+use chrono::{DateTime, NaiveDateTime};
+use regex::Regex;
+use serde_json::Value;
+use url::Url;
+
+use crate::recipe::{parse_servings_range, Recipe};
+
+const NON_IMAGE_EXTENSIONS: [&str; 6] = [".html", ".htm", ".php", ".js", ".css", ".pdf"];
+
+static TAG_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+static WHITESPACE_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\s+").unwrap());
+
+// eg '1,250', '12.5' or '.5'
+static NUMBER_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\d[\d,]*(\.\d+)?|\.\d+").unwrap());
+
+#[derive(Debug, Clone, Copy)]
+enum MassUnit {
+    Grams,
+    Milligrams,
+}
+
+/// Strips HTML tags and decodes entities, since schemas often embed markup like
+/// `<a href="...">` or `&amp;` in otherwise plain text fields
+fn sanitize(text: &str) -> String {
+    let text = TAG_REGEX.replace_all(text, " ");
+    let text = html_escape::decode_html_entities(&text);
+    WHITESPACE_REGEX.replace_all(&text, " ")
+        .trim()
+        .to_owned()
+}
+
+/// Removes repeated values, keeping the first of each in its original position
+/// Schemas often list the same author or image more than once (eg once as a string and once as
+/// an object)
+fn dedup(values: Vec<String>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    values.into_iter()
+        .filter(|v| seen.insert(v.clone()))
+        .collect()
+}
+
+fn duration_to_seconds(duration: iso8601::Duration) -> Option<u64> {
+    match duration {
+        iso8601::Duration::YMDHMS { year, month, day, hour, minute, second, millisecond } => {
+            if year != 0 || month != 0 {
+                return None
+            }
+
+            // milliseconds are dropped rather than rounded
+            Some((millisecond / 1000
+                + second
+                + 60 * minute
+                + 60 * 60 * hour
+                + 60 * 60 * 24 * day) as u64)
+        }
+        iso8601::Duration::Weeks(weeks) => Some(60 * 60 * 24 * 7 * weeks as u64),
+    }
+}
+
+/// Finds the first number in some text, eg 'approximately 1,250 kcal per serving' -> 1250
+fn first_number(text: &str) -> Option<f32> {
+    NUMBER_REGEX.find(text)
+        .map(|v| v.as_str().replace(",", ""))
+        .and_then(|v| v.parse::<f32>().ok())
+}
+
+fn title(v: &Value) -> Option<String> {
+    v.get("name")
+        .and_then(|v| v.as_str())
+        .map(sanitize)
+}
+
+fn image(v: &Value) -> Vec<String> {
+    let single_image = v.get("image")
+        .and_then(|v| v.as_str())
+        .map(|v| v.to_owned())
+        .map(|v| vec![v]);
+
+    let image_array = v.get("image")
+        .and_then(|v| v.as_array())
+        .map(|v| v.iter()
+            .filter_map(|v| v.as_str())
+            .map(|v| v.to_owned())
+            .collect::<Vec<String>>()
+        );
+
+    let single_image_object = v.get("image")
+        .and_then(|v| v.get("url"))
+        .and_then(|v| v.as_str())
+        .map(|v| vec![v.to_owned()]);
+
+    let image_object_array = v.get("image")
+        .and_then(|v| v.as_array())
+        .map(|v| v.iter()
+            .filter_map(|v| v.get("url"))
+            .filter_map(|v| v.as_str())
+            .map(|v| v.to_owned())
+            .collect::<Vec<String>>()
+        );
+
+    single_image
+        .or(image_array)
+        .or(single_image_object)
+        .or(image_object_array)
+        .unwrap_or_default()
+}
+
+/// Resolves relative (eg '/img/x.jpg') and protocol-relative (eg '//cdn.example.com/x.jpg')
+/// images against the recipe's link, and drops anything that isn't an http(s) URL such as data
+/// URIs or pages
+fn normalize_images(images: Vec<String>, link: &str) -> Vec<String> {
+    let Ok(base) = Url::parse(link) else {
+        return vec![];
+    };
+
+    let images = images.iter()
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .filter_map(|v| base.join(v).ok())
+        .filter(|v| matches!(v.scheme(), "http" | "https"))
+        .filter(|v| !NON_IMAGE_EXTENSIONS.iter().any(|extension| v.path().to_lowercase().ends_with(extension)))
+        .map(|v| v.to_string())
+        .collect();
+
+    dedup(images)
+}
+
+fn authors(v: &Value, link: String) -> Vec<String> {
+    let Some(v) = v.get("author") else {
+        return vec![]
+    };
+
+    // author can be "Jane Doe", {"name": "Jane Doe"}, or an array of either
+    let authors: Vec<String> = v.as_array()
+        .cloned()
+        .unwrap_or(vec![v.clone()])
+        .iter()
+        .filter_map(|v| v.as_str()
+            .or(v.get("name").and_then(|v| v.as_str()))
+            .map(sanitize)
+        )
+        .filter(|v| !v.is_empty())
+        .collect();
+
+    if authors.is_empty() {
+        let Ok(url) = Url::parse(&link) else {
+            return vec![];
+        };
+        let Some(domain) = url.domain() else {
+            return vec![];
+        };
+        return vec![domain.to_owned()];
+    }
+
+    authors
+}
+
+fn description(v: &Value) -> Option<String> {
+    v.get("description")
+        .and_then(|v| v.as_str())
+        .map(sanitize)
+}
+
+/// Interprets a number as a Unix timestamp, in either seconds or milliseconds
+fn timestamp_to_date(timestamp: f64) -> Option<String> {
+    // in seconds this would be over 3000 years from now, so it must be milliseconds
+    let seconds = if timestamp.abs() >= 1e11 {
+        timestamp / 1000.0
+    } else {
+        timestamp
+    };
+
+    DateTime::from_timestamp(seconds as i64, 0)
+        .map(|v| v.format("%Y-%m-%d").to_string())
+}
+
+fn date(v: &Value) -> Option<String> {
+    let v = v.get("datePublished")
+        .or(v.get("dateCreated"))?;
+
+    // eg 1252267620 or 1252267620000
+    if let Some(timestamp) = v.as_f64() {
+        return timestamp_to_date(timestamp);
+    }
+
+    let mut date_str = v.as_str()?
+        .to_owned();
+
+    // converts eg 2009-09-06T20:07Z (not a valid ISO8601 apparently?)
+    // into 2009-09-06T20:07:00Z
+    // Yes this is ridiculous
+    if Regex::new(r".{0,100}T\d\d:\d\dZ").unwrap().is_match(&date_str) {
+        date_str = date_str.replace("Z", ":00Z");
+    }
+
+    // Covers almost all websites
+    let format1 = dateparser::parse(&date_str)
+        .ok()
+        .map(|v| v.date_naive());
+
+    // https://www.kingarthurbaking.com/recipes/muffaletta-recipe
+    let format2 = NaiveDateTime::parse_from_str(&date_str, "%B %d, %Y at %I:%M%p")
+        .ok()
+        .map(|v| v.date());
+
+    // https://www.food.com/recipe/authentic-german-black-bread-schwarzbrot-446730
+    let format3 = NaiveDateTime::parse_from_str(&date_str, "%B %d, %Y")
+        .ok()
+        .map(|v| v.date());
+
+    format1
+        .or(format2)
+        .or(format3)
+        .map(|v| v.format("%Y-%m-%d").to_string())
+}
+
+fn servings(v: &Value) -> Option<String> {
+    let v = v.get("recipeYield");
+
+    // recipeYield: ["1 serving", ...]
+    let array_text = v
+        .and_then(|v| v.as_array())
+        .and_then(|v| v.iter()
+            .find_map(|v| v.as_str()
+                .map(|v| v.to_owned())
+                .take_if(|v| v.parse::<i32>().is_err())
+            )
+        );
+
+    // recipeYield: ["1", ...]
+    let array_wrapped_number = v
+        .and_then(|v| v.as_array())
+        .and_then(|v| v.iter()
+            .find_map(|v| v.as_str()
+                .map(|v| v.to_owned())
+                .take_if(|v| v.parse::<i32>().is_ok())
+            )
+        );
+    
+    // recipeYield: [1, ...]
+    let array_number = v
+        .and_then(|v| v.as_array())
+        .and_then(|v| v.iter()
+            .find_map(|v| v.as_i64()
+                .map(|v| v.to_string())
+            )
+        );  
+
+    // recipeYield: "1 serving"
+    let text = v
+        .and_then(|v| v.as_str())
+        .map(|v| v.to_owned())
+        .take_if(|v| v.parse::<i32>().is_err());
+
+    // recipeYield: "1"
+    let text_wrapped_number = v
+        .and_then(|v| v.as_str())
+        .map(|v| v.to_owned())
+        .take_if(|v| v.parse::<i32>().is_ok());
+
+    // recipeYield: 1
+    let text_number = v
+        .and_then(|v| v.as_i64())
+        .map(|v| v.to_string());
+
+    // eg 'makes 12&#189; dozen'
+    array_text
+        .or(text)
+        .or(array_wrapped_number)
+        .or(text_wrapped_number)
+        .or(array_number)
+        .or(text_number)
+        .map(|v| sanitize(&v))
+}
+
+fn prep_time(v: &Value) -> Option<u64> {
+    v.get("prepTime")
+        .and_then(|v| v.as_str())
+        .and_then(|v| iso8601::duration(v).ok())
+        .and_then(duration_to_seconds)
+}
+
+fn cook_time(v: &Value) -> Option<u64> {
+    v.get("cookTime")
+        .and_then(|v| v.as_str())
+        .and_then(|v| iso8601::duration(v).ok())
+        .and_then(duration_to_seconds)
+}
+
+fn total_time(v: &Value) -> Option<u64> {
+    v.get("totalTime")
+        .and_then(|v| v.as_str())
+        .and_then(|v| iso8601::duration(v).ok())
+        .and_then(duration_to_seconds)
+}
+
+fn ingredients(v: &Value) -> Vec<String> {
+    v.get("recipeIngredient")
+        .and_then(|v| v.as_array())
+        .and_then(|v| v.iter()
+            .map(|v| v.as_str().map(sanitize))
+            .collect::<Option<Vec<String>>>()
+        )
+        .unwrap_or_default()
+}
+
+fn instructions(v: &Value) -> Vec<String> {
+    v.get("recipeInstructions")
+        .and_then(|v| v.as_array())
+        .and_then(|v| v.iter()
+            .map(|v| v.as_str()
+                .map(sanitize)
+                .or(v.get("text")
+                    .and_then(|v| v.as_str())
+                    .map(sanitize)
+                )
+            )
+            .collect::<Option<Vec<String>>>()
+        )
+        .unwrap_or_default()
+}
+
+fn rating(v: &Value) -> Option<f32> {
+    v.get("aggregateRating")
+        .and_then(|v| {
+            v.get("ratingValue")
+        })
+        .and_then(|v| v.as_str())
+        .and_then(|v| v.parse::<f32>().ok())
+}
+
+fn rating_count(v: &Value) -> Option<i32> {
+    let ratings = v.get("aggregateRating")
+        .and_then(|v| v.get("ratingCount"))
+        .and_then(|v| v.as_str())
+        .and_then(|v| v.parse::<i32>().ok());
+
+    let reviews = v.get("aggregateRating")
+        .and_then(|v| v.get("reviewCount"))
+        .and_then(|v| v.as_str())
+        .and_then(|v| v.parse::<i32>().ok());
+
+    match ratings {
+        Some(ratings) => reviews
+            .map(|v| v + ratings)
+            .or(Some(ratings)),
+        None => reviews,
+    }
+}
+
+fn keywords(v: &Value) -> Vec<String> {
+    let mut keywords = v.get("keywords")
+        .and_then(|v| v.as_str())
+        .map(|v| v.split(",")
+            .map(|v| v.trim())
+            .map(|v| v.to_owned())
+            .collect::<Vec<String>>()
+        )
+        .unwrap_or_default();
+
+    let category = v.get("recipeCategory")
+        .and_then(|v| v.as_array())
+        .and_then(|v| v.iter()
+            .map(|v| v.as_str()
+                .map(|v| v.trim())
+                .map(|v| v.to_owned())
+            )
+            .collect::<Option<Vec<String>>>()
+        )
+        .unwrap_or_default();
+    
+    let cuisine = v.get("recipeCuisine")
+        .and_then(|v| v.as_array())
+        .and_then(|v| v.iter()
+            .map(|v| v.as_str()
+                .map(|v| v.trim())
+                .map(|v| v.to_owned())
+            )
+            .collect::<Option<Vec<String>>>()
+        )
+        .unwrap_or_default();
+
+    keywords.extend(category);
+    keywords.extend(cuisine);
+
+    keywords.sort();
+    keywords.dedup();
+
+    keywords
+}
+
+fn calories(v: &Value) -> Option<f32> {
+    v.get("nutrition")
+        .and_then(|v| v.get("calories"))
+        .and_then(|v| v.as_str())
+        .and_then(first_number)
+}
+
+/// Parses a mass like '12 g', '12grams', '350 mg' or '1,200mcg' into `unit`
+/// A bare number (eg '12' or 12) is assumed to already be in `unit`
+/// Returns None for units that can't be converted to a mass, such as IU
+fn mass(v: &Value, unit: MassUnit) -> Option<f32> {
+    if let Some(v) = v.as_f64() {
+        return Some(v as f32);
+    }
+
+    let text = v.as_str()?.to_lowercase();
+    let number = NUMBER_REGEX.find(&text)?;
+    let value = number.as_str().replace(",", "").parse::<f32>().ok()?;
+
+    let suffix: String = text[number.end()..].trim_start()
+        .chars()
+        .take_while(|c| c.is_alphabetic())
+        .collect();
+    let grams = match suffix.as_str() {
+        "" => return Some(value),
+        "kg" | "kilogram" | "kilograms" => value * 1000.0,
+        "g" | "gr" | "gram" | "grams" => value,
+        "mg" | "milligram" | "milligrams" => value / 1000.0,
+        "mcg" | "µg" | "ug" | "microgram" | "micrograms" => value / 1_000_000.0,
+        _ => return None,
+    };
+
+    Some(match unit {
+        MassUnit::Grams => grams,
+        MassUnit::Milligrams => grams * 1000.0,
+    })
+}
+
+fn nutrition_mass(v: &Value, field: &str, unit: MassUnit) -> Option<f32> {
+    v.get("nutrition")
+        .and_then(|v| v.get(field))
+        .and_then(|v| mass(v, unit))
+}
+
+fn carbohydrates(v: &Value) -> Option<f32> {
+    nutrition_mass(v, "carbohydrateContent", MassUnit::Grams)
+}
+
+fn cholesterol(v: &Value) -> Option<f32> {
+    nutrition_mass(v, "cholesterolContent", MassUnit::Milligrams)
+}
+
+fn fat(v: &Value) -> Option<f32> {
+    nutrition_mass(v, "fatContent", MassUnit::Grams)
+}
+
+fn fiber(v: &Value) -> Option<f32> {
+    nutrition_mass(v, "fiberContent", MassUnit::Grams)
+}
+
+fn protein(v: &Value) -> Option<f32> {
+    nutrition_mass(v, "proteinContent", MassUnit::Grams)
+}
+
+fn saturated_fat(v: &Value) -> Option<f32> {
+    nutrition_mass(v, "saturatedFatContent", MassUnit::Grams)
+}
+
+fn sodium(v: &Value) -> Option<f32> {
+    nutrition_mass(v, "sodiumContent", MassUnit::Milligrams)
+}
+
+fn sugar(v: &Value) -> Option<f32> {
+    nutrition_mass(v, "sugarContent", MassUnit::Grams)
+}
+
+fn serving_size(v: &Value) -> Option<String> {
+    v.get("nutrition")
+        .and_then(|v| v.get("servingSize"))
+        .and_then(|v| v.as_str())
+        .map(sanitize)
+        .filter(|v| !v.is_empty())
+}
+
+/// Every recipe on a page needs its own link, so recipes after the first get a fragment
+/// eg https://example.com/roundup#recipe-2
+pub fn recipe_link(link: &str, index: usize) -> String {
+    if index == 0 {
+        link.to_owned()
+    } else {
+        format!("{link}#recipe-{}", index + 1)
+    }
+}
+
+#[tracing::instrument(skip(schema))]
+pub async fn parse(link: String, schema: Value) -> Option<Recipe> {
+    let title = title(&schema)?;
+    let description = description(&schema)?;
+
+    let ingredients = ingredients(&schema);
+    if ingredients.is_empty() {
+        return None;
+    }
+
+    let instructions = instructions(&schema);
+    if instructions.is_empty() {
+        return None;
+    }
+
+    let servings = servings(&schema);
+    let servings_range = servings.as_deref().and_then(parse_servings_range);
+
+    Some(Recipe {
+        link: link.clone(),
+        title,
+        ingredients,
+        instructions,
+        images: normalize_images(image(&schema), &link),
+        authors: dedup(authors(&schema, link.to_owned())),
+        description,
+        date: date(&schema),
+        servings,
+        servings_min: servings_range.map(|(min, _)| min),
+        servings_max: servings_range.map(|(_, max)| max),
+        prep_time_seconds: prep_time(&schema),
+        cook_time_seconds: cook_time(&schema),
+        total_time_seconds: total_time(&schema).or_else(|| Some(prep_time(&schema)? + cook_time(&schema)?)),
+        rating: rating(&schema),
+        rating_count: rating_count(&schema),
+        keywords: keywords(&schema),
+        calories: calories(&schema),
+        carbohydrates: carbohydrates(&schema),
+        cholesterol: cholesterol(&schema),
+        fat: fat(&schema),
+        fiber: fiber(&schema),
+        protein: protein(&schema),
+        saturated_fat: saturated_fat(&schema),
+        sodium: sodium(&schema),
+        sugar: sugar(&schema),
+        serving_size: serving_size(&schema),
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_strips_tags_and_decodes_entities() {
+        assert_eq!(sanitize("Salt &amp; pepper"), "Salt & pepper");
+        assert_eq!(sanitize("&frac12; cup &#8220;self-raising&#8221; flour"), "½ cup “self-raising” flour");
+        assert_eq!(sanitize("Use <a href=\"https://example.com/ghee\">ghee</a>, or&nbsp;butter"), "Use ghee , or butter");
+        assert_eq!(sanitize("  <p>Whisk\n\n the eggs</p> "), "Whisk the eggs");
+    }
+
+    #[test]
+    fn first_number_ignores_surrounding_text() {
+        assert_eq!(first_number("250 kcal"), Some(250.0));
+        assert_eq!(first_number("250 calories"), Some(250.0));
+        assert_eq!(first_number("250 kcal per serving"), Some(250.0));
+        assert_eq!(first_number("approximately 250"), Some(250.0));
+        assert_eq!(first_number("approximately 1,250 kcal per serving"), Some(1250.0));
+        assert_eq!(first_number("12.5 g"), Some(12.5));
+        assert_eq!(first_number(".5 kcal"), Some(0.5));
+        assert_eq!(first_number("no calories listed"), None);
+    }
+
+    #[test]
+    fn calories_reads_first_number_of_nutrition_calories() {
+        let recipe = serde_json::json!({ "nutrition": { "calories": "250 kcal per serving" } });
+        assert_eq!(calories(&recipe), Some(250.0));
+        assert_eq!(calories(&serde_json::json!({})), None);
+    }
+
+    #[test]
+    fn timestamp_to_date_accepts_seconds_and_milliseconds() {
+        assert_eq!(timestamp_to_date(1252267620.0), Some("2009-09-06".to_string()));
+        assert_eq!(timestamp_to_date(1252267620000.0), Some("2009-09-06".to_string()));
+        assert_eq!(timestamp_to_date(0.0), Some("1970-01-01".to_string()));
+        assert_eq!(timestamp_to_date(-86400.0), Some("1969-12-31".to_string()));
+    }
+
+    #[test]
+    fn mass_converts_units() {
+        let grams = |v: Value| mass(&v, MassUnit::Grams);
+        assert_eq!(grams(Value::from(12)), Some(12.0));
+        assert_eq!(grams(Value::from("12")), Some(12.0));
+        assert_eq!(grams(Value::from("12 g")), Some(12.0));
+        assert_eq!(grams(Value::from("12grams")), Some(12.0));
+        assert_eq!(grams(Value::from("1.5 kg")), Some(1500.0));
+        assert_eq!(grams(Value::from("350 mg")), Some(0.35));
+        assert_eq!(grams(Value::from(".5g")), Some(0.5));
+        assert_eq!(grams(Value::from("0.5 Grams")), Some(0.5));
+
+        let milligrams = |v: Value| mass(&v, MassUnit::Milligrams);
+        assert_eq!(milligrams(Value::from("1,200mcg")), Some(1.2));
+        assert_eq!(milligrams(Value::from("2 milligrams")), Some(2.0));
+        assert_eq!(milligrams(Value::from("0.25 g")), Some(250.0));
+    }
+
+    #[test]
+    fn mass_rejects_non_mass_units() {
+        assert_eq!(mass(&Value::from("400 IU"), MassUnit::Milligrams), None);
+        assert_eq!(mass(&Value::from("1 cup"), MassUnit::Grams), None);
+        assert_eq!(mass(&Value::from("none"), MassUnit::Grams), None);
+        assert_eq!(mass(&Value::Null, MassUnit::Grams), None);
+    }
+
+    fn seconds(duration: &str) -> Option<u64> {
+        duration_to_seconds(iso8601::duration(duration).unwrap())
+    }
+
+    #[test]
+    fn duration_to_seconds_handles_weeks_days_and_times() {
+        assert_eq!(seconds("P1W"), Some(7 * 24 * 60 * 60));
+        assert_eq!(seconds("PT1H30M"), Some(90 * 60));
+        assert_eq!(seconds("P0Y0M1DT2H"), Some(26 * 60 * 60));
+        // a month or year isn't a fixed length
+        assert_eq!(seconds("P1M"), None);
+        assert_eq!(seconds("P1Y"), None);
+    }
+
+    #[test]
+    fn duration_to_seconds_drops_milliseconds() {
+        assert_eq!(seconds("PT1.5S"), Some(1));
+        assert_eq!(seconds("PT10M0.999S"), Some(600));
+    }
+
+    #[test]
+    fn title_decodes_entities_and_strips_tags() {
+        let recipe = serde_json::json!({ "name": "Mac &amp; Cheese &#8211; <em>the best</em>" });
+        assert_eq!(title(&recipe), Some("Mac & Cheese – the best".to_string()));
+    }
+
+    #[test]
+    fn servings_decode_entities() {
+        assert_eq!(servings(&serde_json::json!({ "recipeYield": "makes 12&#189; dozen" })), Some("makes 12½ dozen".to_string()));
+        assert_eq!(servings(&serde_json::json!({ "recipeYield": ["4", "4 &ndash; 6 servings"] })), Some("4 – 6 servings".to_string()));
+        assert_eq!(servings(&serde_json::json!({ "recipeYield": 4 })), Some("4".to_string()));
+    }
+
+    fn authors_of(author: Value) -> Vec<String> {
+        authors(&serde_json::json!({ "author": author }), "https://www.example.com/pancakes".to_string())
+    }
+
+    #[test]
+    fn authors_are_read_from_strings_and_objects() {
+        assert_eq!(authors_of(Value::from("Jane Doe")), vec!["Jane Doe"]);
+        assert_eq!(authors_of(serde_json::json!({ "@type": "Person", "name": "Jane Doe" })), vec!["Jane Doe"]);
+    }
+
+    #[test]
+    fn authors_are_read_from_arrays_of_either() {
+        assert_eq!(
+            authors_of(serde_json::json!(["Jane Doe", { "@type": "Person", "name": "John Smith" }])),
+            vec!["Jane Doe", "John Smith"],
+        );
+    }
+
+    #[test]
+    fn authors_fall_back_to_the_domain() {
+        assert_eq!(authors_of(serde_json::json!({ "@type": "Organization", "url": "https://example.com" })), vec!["www.example.com"]);
+        assert_eq!(authors_of(serde_json::json!(["", { "name": " " }])), vec!["www.example.com"]);
+        // no author at all isn't a fallback
+        assert!(authors(&serde_json::json!({}), "https://www.example.com/pancakes".to_string()).is_empty());
+    }
+
+    fn images(images: &[&str]) -> Vec<String> {
+        normalize_images(images.iter().map(|v| v.to_string()).collect(), "https://www.example.com/recipes/pancakes")
+    }
+
+    #[test]
+    fn relative_images_are_resolved_against_the_link() {
+        assert_eq!(images(&["/img/pancakes.jpg", "stack.png"]), vec![
+            "https://www.example.com/img/pancakes.jpg",
+            "https://www.example.com/recipes/stack.png",
+        ]);
+    }
+
+    #[test]
+    fn protocol_relative_images_use_the_link_scheme() {
+        assert_eq!(images(&["//cdn.example.com/pancakes.jpg"]), vec!["https://cdn.example.com/pancakes.jpg"]);
+    }
+
+    #[test]
+    fn data_uris_and_pages_are_dropped() {
+        assert_eq!(images(&[
+            "data:image/png;base64,iVBORw0KGgo=",
+            "https://www.example.com/recipes/pancakes.html",
+            "https://www.example.com/gallery.PHP",
+            "https://www.example.com/recipes/pancakes.webp",
+            " ",
+        ]), vec!["https://www.example.com/recipes/pancakes.webp"]);
+    }
+
+    #[test]
+    fn duplicate_images_are_removed() {
+        assert_eq!(images(&["/img/pancakes.jpg", "https://www.example.com/img/pancakes.jpg"]), vec![
+            "https://www.example.com/img/pancakes.jpg",
+        ]);
+    }
+}
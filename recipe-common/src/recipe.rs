@@ -1,11 +1,14 @@
-use std::{collections::HashSet, slice::Iter};
+use std::{collections::{HashMap, HashSet}, slice::Iter};
 
 use anyhow::Error;
-use redis::{aio::MultiplexedConnection, AsyncCommands, ErrorKind, FromRedisValue, RedisError, RedisResult, Value};
-use serde::Serialize;
+use redis::{aio::MultiplexedConnection, AsyncCommands, ErrorKind, FromRedisValue, Pipeline, RedisError, RedisResult, Value};
+use serde::{Deserialize, Serialize};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
 use utoipa::ToSchema;
 
-#[derive(Serialize, Debug, Clone, ToSchema)]
+use crate::link;
+
+#[derive(Serialize, Debug, Clone, Default, ToSchema)]
 pub struct Recipe {
     pub link: String,
     pub title: String,
@@ -22,6 +25,10 @@ pub struct Recipe {
     pub cook_time_seconds: Option<u64>,
     pub total_time_seconds: Option<u64>,
     pub servings: Option<String>,
+    /// The smallest number of servings, eg 4 for '4-6 servings'
+    pub servings_min: Option<f32>,
+    /// The largest number of servings, eg 6 for '4-6 servings'
+    pub servings_max: Option<f32>,
     pub calories: Option<f32>,
     pub carbohydrates: Option<f32>,
     pub cholesterol: Option<f32>,
@@ -31,6 +38,135 @@ pub struct Recipe {
     pub saturated_fat: Option<f32>,
     pub sodium: Option<f32>,
     pub sugar: Option<f32>,
+    /// The serving the nutrition is given for, eg '1 cup (240g)'
+    pub serving_size: Option<String>,
+}
+
+/// Lightweight summary of a recipe for search results, without the large lists
+#[derive(Serialize, Debug, Clone, ToSchema)]
+pub struct RecipeCard {
+    pub id: u64,
+    pub link: String,
+    pub title: String,
+    pub image: Option<String>,
+    pub rating: Option<f32>,
+    pub total_time_seconds: Option<u64>,
+}
+
+/// A single stored field of a recipe, used to fetch only part of a recipe
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum RecipeField {
+    Link,
+    Title,
+    Description,
+    Ingredients,
+    Instructions,
+    Date,
+    Keywords,
+    Authors,
+    Images,
+    Rating,
+    RatingCount,
+    PrepTimeSeconds,
+    CookTimeSeconds,
+    TotalTimeSeconds,
+    Servings,
+    ServingsMin,
+    ServingsMax,
+    Calories,
+    Carbohydrates,
+    Cholesterol,
+    Fat,
+    Fiber,
+    Protein,
+    SaturatedFat,
+    Sodium,
+    Sugar,
+    ServingSize,
+}
+
+impl RecipeField {
+    /// Every field, in the same order as the fields of `Recipe`
+    pub const ALL: [RecipeField; 27] = [
+        RecipeField::Link,
+        RecipeField::Title,
+        RecipeField::Description,
+        RecipeField::Ingredients,
+        RecipeField::Instructions,
+        RecipeField::Date,
+        RecipeField::Keywords,
+        RecipeField::Authors,
+        RecipeField::Images,
+        RecipeField::Rating,
+        RecipeField::RatingCount,
+        RecipeField::PrepTimeSeconds,
+        RecipeField::CookTimeSeconds,
+        RecipeField::TotalTimeSeconds,
+        RecipeField::Servings,
+        RecipeField::ServingsMin,
+        RecipeField::ServingsMax,
+        RecipeField::Calories,
+        RecipeField::Carbohydrates,
+        RecipeField::Cholesterol,
+        RecipeField::Fat,
+        RecipeField::Fiber,
+        RecipeField::Protein,
+        RecipeField::SaturatedFat,
+        RecipeField::Sodium,
+        RecipeField::Sugar,
+        RecipeField::ServingSize,
+    ];
+
+    fn key(self, id: u64) -> String {
+        match self {
+            RecipeField::Link => key_recipe_link(id),
+            RecipeField::Title => key_recipe_title(id),
+            RecipeField::Description => key_recipe_description(id),
+            RecipeField::Ingredients => key_recipe_ingredients(id),
+            RecipeField::Instructions => key_recipe_instructions(id),
+            RecipeField::Date => key_recipe_date(id),
+            RecipeField::Keywords => key_recipe_keywords(id),
+            RecipeField::Authors => key_recipe_authors(id),
+            RecipeField::Images => key_recipe_images(id),
+            RecipeField::Rating => key_recipe_rating(id),
+            RecipeField::RatingCount => key_recipe_rating_count(id),
+            RecipeField::PrepTimeSeconds => key_recipe_prep_time_seconds(id),
+            RecipeField::CookTimeSeconds => key_recipe_cook_time_seconds(id),
+            RecipeField::TotalTimeSeconds => key_recipe_total_time_seconds(id),
+            RecipeField::Servings => key_recipe_servings(id),
+            RecipeField::ServingsMin => key_recipe_servings_min(id),
+            RecipeField::ServingsMax => key_recipe_servings_max(id),
+            RecipeField::Calories => key_recipe_calories(id),
+            RecipeField::Carbohydrates => key_recipe_carbohydrates(id),
+            RecipeField::Cholesterol => key_recipe_cholesterol(id),
+            RecipeField::Fat => key_recipe_fat(id),
+            RecipeField::Fiber => key_recipe_fiber(id),
+            RecipeField::Protein => key_recipe_protein(id),
+            RecipeField::SaturatedFat => key_recipe_saturated_fat(id),
+            RecipeField::Sodium => key_recipe_sodium(id),
+            RecipeField::Sugar => key_recipe_sugar(id),
+            RecipeField::ServingSize => key_recipe_serving_size(id),
+        }
+    }
+
+    fn is_list(self) -> bool {
+        matches!(self, 
+            RecipeField::Ingredients 
+            | RecipeField::Instructions 
+            | RecipeField::Keywords 
+            | RecipeField::Authors 
+            | RecipeField::Images
+        )
+    }
+
+    fn queue_get(self, pipe: &mut Pipeline, id: u64) {
+        if self.is_list() {
+            pipe.lrange(self.key(id), 0, -1);
+        } else {
+            pipe.get(self.key(id));
+        }
+    }
 }
 
 pub fn get_redis_value<T: FromRedisValue>(iter: &mut Iter<Value>, field: &str) -> Result<T, RedisError> {
@@ -40,6 +176,13 @@ pub fn get_redis_value<T: FromRedisValue>(iter: &mut Iter<Value>, field: &str) -
     }
 }
 
+pub fn get_field_value<T: FromRedisValue>(fields: &HashMap<RecipeField, Value>, field: RecipeField) -> Result<T, RedisError> {
+    match fields.get(&field).map(T::from_redis_value) {
+        Some(Ok(v)) => Ok(v),
+        _ => Err(RedisError::from((ErrorKind::TypeError, "Failed to get field", format!("{field:?}")))),
+    }
+}
+
 impl FromRedisValue for Recipe {
     fn from_redis_value(v: &Value) -> RedisResult<Self> {
         let Value::Array(ref items) = *v else {
@@ -63,6 +206,8 @@ impl FromRedisValue for Recipe {
             cook_time_seconds: get_redis_value(&mut iter, "cook_time_seconds")?,
             total_time_seconds: get_redis_value(&mut iter, "total_time_seconds")?,
             servings: get_redis_value(&mut iter, "servings")?,
+            servings_min: get_redis_value(&mut iter, "servings_min")?,
+            servings_max: get_redis_value(&mut iter, "servings_max")?,
             calories: get_redis_value(&mut iter, "calories")?,
             carbohydrates: get_redis_value(&mut iter, "carbohydrates")?,
             cholesterol: get_redis_value(&mut iter, "cholesterol")?,
@@ -72,30 +217,223 @@ impl FromRedisValue for Recipe {
             saturated_fat: get_redis_value(&mut iter, "saturated_fat")?,
             sodium: get_redis_value(&mut iter, "sodium")?,
             sugar: get_redis_value(&mut iter, "sugar")?,
+            serving_size: get_redis_value(&mut iter, "serving_size")?,
         })
     }
 }
 
+/// Parses the number of servings from a yield, returning the lower and upper ends of any range
+/// eg '6' -> (6, 6), '4-6' -> (4, 6), 'serves 4 to 6 people' -> (4, 6), '4–6' -> (4, 6)
+pub fn parse_servings_range(servings: &str) -> Option<(f32, f32)> {
+    fn number(text: &str) -> Option<(f32, &str)> {
+        let text = text.trim_start();
+        let end = text.find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(text.len());
+        let value = text[..end].parse::<f32>().ok()?;
+        Some((value, &text[end..]))
+    }
+
+    let start = servings.find(|c: char| c.is_ascii_digit())?;
+    let (min, rest) = number(&servings[start..])?;
+
+    let rest = rest.trim_start();
+    let rest = ["-", "–", "—", "to"].iter()
+        .find_map(|separator| rest.strip_prefix(separator));
+    let max = rest.and_then(number)
+        .map(|(max, _)| max)
+        .filter(|max| *max >= min)
+        .unwrap_or(min);
+
+    Some((min, max))
+}
+
 impl Recipe {
+    /// The number of servings the recipe makes, which for a range like '4-6 servings' is the
+    /// lower end
+    /// Recipes stored before the range was parsed fall back to parsing `servings`
+    pub fn servings_count(&self) -> Option<f32> {
+        self.servings_min
+            .or_else(|| parse_servings_range(self.servings.as_ref()?).map(|(min, _)| min))
+            .filter(|v| *v > 0.0)
+    }
+
+    /// Whether the instructions are long enough to be a real method rather than eg 'see video'
+    /// Recipes are rejected unless at least one instruction has `min_instruction_chars`
+    /// characters and the instructions have `min_total_instruction_chars` characters in total
+    pub fn has_plausible_instructions(&self, min_instruction_chars: Option<usize>, min_total_instruction_chars: Option<usize>) -> bool {
+        let longest = self.instructions.iter()
+            .map(|v| v.chars().count())
+            .max()
+            .unwrap_or(0);
+        let total: usize = self.instructions.iter()
+            .map(|v| v.chars().count())
+            .sum();
+
+        min_instruction_chars.is_none_or(|min| longest >= min)
+            && min_total_instruction_chars.is_none_or(|min| total >= min)
+    }
+
+    /// Fills in any fields missing from this recipe with the values from `other`, so that
+    /// partial recipes found on different pages can be combined
+    /// Returns true if any fields were filled in
+    pub fn merge(&mut self, other: &Recipe) -> bool {
+        fn fill<T: Clone>(field: &mut Option<T>, other: &Option<T>) -> bool {
+            let filled = field.is_none() && other.is_some();
+            if filled {
+                *field = other.clone();
+            }
+            filled
+        }
+
+        fn fill_list<T: Clone>(field: &mut Vec<T>, other: &[T]) -> bool {
+            let filled = field.is_empty() && !other.is_empty();
+            if filled {
+                *field = other.to_vec();
+            }
+            filled
+        }
+
+        // the servings and the range parsed from them are filled together, so they can't end up
+        // from different pages that disagree
+        let has_servings = |recipe: &Recipe| recipe.servings.is_some() || recipe.servings_min.is_some() || recipe.servings_max.is_some();
+        let fill_servings = !has_servings(self) && has_servings(other);
+        if fill_servings {
+            self.servings = other.servings.clone();
+            self.servings_min = other.servings_min;
+            self.servings_max = other.servings_max;
+        }
+
+        // non-short-circuiting so every field gets filled
+        fill_servings
+            | fill_list(&mut self.ingredients, &other.ingredients)
+            | fill_list(&mut self.instructions, &other.instructions)
+            | fill(&mut self.date, &other.date)
+            | fill_list(&mut self.keywords, &other.keywords)
+            | fill_list(&mut self.authors, &other.authors)
+            | fill_list(&mut self.images, &other.images)
+            | fill(&mut self.rating, &other.rating)
+            | fill(&mut self.rating_count, &other.rating_count)
+            | fill(&mut self.prep_time_seconds, &other.prep_time_seconds)
+            | fill(&mut self.cook_time_seconds, &other.cook_time_seconds)
+            | fill(&mut self.total_time_seconds, &other.total_time_seconds)
+            | fill(&mut self.calories, &other.calories)
+            | fill(&mut self.carbohydrates, &other.carbohydrates)
+            | fill(&mut self.cholesterol, &other.cholesterol)
+            | fill(&mut self.fat, &other.fat)
+            | fill(&mut self.fiber, &other.fiber)
+            | fill(&mut self.protein, &other.protein)
+            | fill(&mut self.saturated_fat, &other.saturated_fat)
+            | fill(&mut self.sodium, &other.sodium)
+            | fill(&mut self.sugar, &other.sugar)
+            | fill(&mut self.serving_size, &other.serving_size)
+    }
+
+    /// Overwrites the fields that change over time (the rating, and the nutrition if the recipe
+    /// has been edited) with the values from `other`, eg when the recipe's page is recrawled
+    /// Fields that `other` is missing are kept
+    /// Returns true if any fields changed
+    pub fn refresh(&mut self, other: &Recipe) -> bool {
+        fn overwrite<T: Clone + PartialEq>(field: &mut Option<T>, other: &Option<T>) -> bool {
+            let changed = other.is_some() && field != other;
+            if changed {
+                *field = other.clone();
+            }
+            changed
+        }
+
+        // non-short-circuiting so every field gets overwritten
+        overwrite(&mut self.rating, &other.rating)
+            | overwrite(&mut self.rating_count, &other.rating_count)
+            | overwrite(&mut self.calories, &other.calories)
+            | overwrite(&mut self.carbohydrates, &other.carbohydrates)
+            | overwrite(&mut self.cholesterol, &other.cholesterol)
+            | overwrite(&mut self.fat, &other.fat)
+            | overwrite(&mut self.fiber, &other.fiber)
+            | overwrite(&mut self.protein, &other.protein)
+            | overwrite(&mut self.saturated_fat, &other.saturated_fat)
+            | overwrite(&mut self.sodium, &other.sodium)
+            | overwrite(&mut self.sugar, &other.sugar)
+            | overwrite(&mut self.serving_size, &other.serving_size)
+    }
+
+    /// The fields that differ from `old`, with every other optional field left empty, so that
+    /// only the changes need to be written back
+    fn changes_from(&self, old: &Recipe) -> Recipe {
+        fn changed<T: Clone + PartialEq>(field: &Option<T>, old: &Option<T>) -> Option<T> {
+            field.clone().filter(|_| field != old)
+        }
+
+        fn changed_list<T: Clone + PartialEq>(field: &[T], old: &[T]) -> Vec<T> {
+            if field == old { vec![] } else { field.to_vec() }
+        }
+
+        Recipe {
+            link: self.link.clone(),
+            title: self.title.clone(),
+            description: self.description.clone(),
+            ingredients: changed_list(&self.ingredients, &old.ingredients),
+            instructions: changed_list(&self.instructions, &old.instructions),
+            date: changed(&self.date, &old.date),
+            keywords: changed_list(&self.keywords, &old.keywords),
+            authors: changed_list(&self.authors, &old.authors),
+            images: changed_list(&self.images, &old.images),
+            rating: changed(&self.rating, &old.rating),
+            rating_count: changed(&self.rating_count, &old.rating_count),
+            prep_time_seconds: changed(&self.prep_time_seconds, &old.prep_time_seconds),
+            cook_time_seconds: changed(&self.cook_time_seconds, &old.cook_time_seconds),
+            total_time_seconds: changed(&self.total_time_seconds, &old.total_time_seconds),
+            servings: changed(&self.servings, &old.servings),
+            servings_min: changed(&self.servings_min, &old.servings_min),
+            servings_max: changed(&self.servings_max, &old.servings_max),
+            calories: changed(&self.calories, &old.calories),
+            carbohydrates: changed(&self.carbohydrates, &old.carbohydrates),
+            cholesterol: changed(&self.cholesterol, &old.cholesterol),
+            fat: changed(&self.fat, &old.fat),
+            fiber: changed(&self.fiber, &old.fiber),
+            protein: changed(&self.protein, &old.protein),
+            saturated_fat: changed(&self.saturated_fat, &old.saturated_fat),
+            sodium: changed(&self.sodium, &old.sodium),
+            sugar: changed(&self.sugar, &old.sugar),
+            serving_size: changed(&self.serving_size, &old.serving_size),
+        }
+    }
+
+    /// The fields checked by `completeness`, and whether each one is present
+    fn completeness_fields(&self) -> [bool; 18] {
+        [
+            !self.images.is_empty(),
+            !self.authors.is_empty(),
+            self.date.is_some(),
+            self.servings.is_some(),
+            self.total_time_seconds.is_some(),
+            !self.ingredients.is_empty(),
+            self.rating.is_some(),
+            self.rating_count.is_some(),
+            !self.keywords.is_empty(),
+            self.calories.is_some(),
+            self.carbohydrates.is_some(),
+            self.cholesterol.is_some(),
+            self.fat.is_some(),
+            self.fiber.is_some(),
+            self.protein.is_some(),
+            self.saturated_fat.is_some(),
+            self.sodium.is_some(),
+            self.sugar.is_some(),
+        ]
+    }
+
+    /// Fraction (from 0 to 1) of the optional fields (images, authors, nutrition, etc) that
+    /// are present
+    pub fn completeness(&self) -> f32 {
+        let fields = self.completeness_fields();
+        let present = fields.iter()
+            .filter(|v| **v)
+            .count();
+        present as f32 / fields.len() as f32
+    }
+
     pub fn is_complete(&self) -> bool {
-        !self.images.is_empty()
-            && !self.authors.is_empty()
-            && self.date.is_some()
-            && self.servings.is_some()
-            && self.total_time_seconds.is_some()
-            && !self.ingredients.is_empty()
-            && self.rating.is_some()
-            && self.rating_count.is_some()
-            && !self.keywords.is_empty()
-            && self.calories.is_some()
-            && self.carbohydrates.is_some()
-            && self.cholesterol.is_some()
-            && self.fat.is_some()
-            && self.fiber.is_some()
-            && self.protein.is_some()
-            && self.saturated_fat.is_some()
-            && self.sodium.is_some()
-            && self.sugar.is_some()
+        self.completeness_fields().iter().all(|v| *v)
     }
 }
 
@@ -114,6 +452,32 @@ fn key_term_recipes(term: &str) -> String {
     format!("term:{term}:recipes")
 }
 
+// ZSET of every term that has been indexed, all with score 0 so they are ordered
+// lexicographically for prefix queries
+fn key_terms() -> String {
+    "terms".to_string()
+}
+
+// SET of every term containing a trigram (see `trigrams`)
+fn key_trigram_terms(trigram: &str) -> String {
+    format!("trigram:{trigram}:terms")
+}
+
+// Pattern matching every key_term_recipes key
+fn pattern_term_recipes() -> String {
+    "term:*:recipes".to_string()
+}
+
+// SET of all recipes with a tag (see `extract_tags`)
+fn key_tag_recipes(tag: &str) -> String {
+    format!("tag:{tag}:recipes")
+}
+
+// Pattern matching every key_tag_recipes key
+fn pattern_tag_recipes() -> String {
+    "tag:*:recipes".to_string()
+}
+
 // SET of all recipes associated with a title
 fn key_title_recipes(title: &str) -> String {
     format!("title:{title}:titles")
@@ -124,6 +488,11 @@ fn key_description_recipes(description: &str) -> String {
     format!("description:{description}:recipes")
 }
 
+// HASH of deduplication links (see `dedup_link`) to recipe ids
+fn key_link_recipes() -> String {
+    "links:recipes".to_string()
+}
+
 // STRING
 fn key_recipe_link(id: u64) -> String {
     format!("recipe:{id}:link")
@@ -174,6 +543,16 @@ fn key_recipe_servings(id: u64) -> String {
     format!("recipe:{id}:key_servings")
 }
 
+// STRING
+fn key_recipe_servings_min(id: u64) -> String {
+    format!("recipe:{id}:servings_min")
+}
+
+// STRING
+fn key_recipe_servings_max(id: u64) -> String {
+    format!("recipe:{id}:servings_max")
+}
+
 // STRING
 fn key_recipe_calories(id: u64) -> String {
     format!("recipe:{id}:calories")
@@ -219,11 +598,21 @@ fn key_recipe_sugar(id: u64) -> String {
     format!("recipe:{id}:sugar")
 }
 
+// STRING
+fn key_recipe_serving_size(id: u64) -> String {
+    format!("recipe:{id}:serving_size")
+}
+
 // LIST
 fn key_recipe_keywords(id: u64) -> String {
     format!("recipe:{id}:keywords")
 }
 
+// Pattern matching every key_recipe_keywords key
+fn pattern_recipe_keywords() -> String {
+    "recipe:*:keywords".to_string()
+}
+
 // LIST
 fn key_recipe_authors(id: u64) -> String {
     format!("recipe:{id}:authors")
@@ -244,21 +633,37 @@ fn key_recipe_instructions(id: u64) -> String {
     format!("recipe:{id}:instructions")
 }
 
-/// Returns true if added
-/// Returns false if already existed or matches the blacklist
-#[tracing::instrument(skip(redis_recipes))]
-pub async fn add(mut redis_recipes: MultiplexedConnection, recipe: Recipe) -> Result<bool, Error> {
-    if exists(redis_recipes.clone(), &recipe).await? {
-        return Ok(false);
-    }
-
-    let id: u64 = redis_recipes.incr(key_id(), 1).await?;
+// SET of the terms the recipe is indexed under, so they can be removed if term extraction changes
+fn key_recipe_terms(id: u64) -> String {
+    format!("recipe:{id}:terms")
+}
 
-    let mut pipe = redis::pipe();
+// SET of the tags the recipe is indexed under, so they can be removed if tagging changes
+fn key_recipe_tags(id: u64) -> String {
+    format!("recipe:{id}:tags")
+}
 
-    pipe.sadd(key_recipes(), id);
+/// The form of a recipe's link used to detect duplicates, which additionally ignores the
+/// scheme so the same page served over http and https is treated as one recipe
+/// The fragment is kept, since it distinguishes recipes that share a page
+/// eg 'http://example.com/pancakes/?utm_source=x' -> 'example.com/pancakes'
+/// eg 'https://example.com/roundup#recipe-2' -> 'example.com/roundup#recipe-2'
+fn dedup_link(link: &str) -> String {
+    let canonical = link::canonicalize(link);
+    let canonical = match canonical.split_once("://") {
+        Some((_, rest)) => rest.to_owned(),
+        None => canonical,
+    };
+    match link.split_once('#') {
+        Some((_, fragment)) => format!("{canonical}#{fragment}"),
+        None => canonical,
+    }
+}
 
+/// Queues writes of every field of the recipe, and adds the recipe to the indexes
+fn queue_set_fields(pipe: &mut Pipeline, id: u64, recipe: &Recipe) {
     pipe.set(key_recipe_link(id), &recipe.link);
+    pipe.hset(key_link_recipes(), dedup_link(&recipe.link), id);
 
     pipe.sadd(key_title_recipes(&recipe.title), id);
     pipe.set(key_recipe_title(id), &recipe.title);
@@ -266,6 +671,24 @@ pub async fn add(mut redis_recipes: MultiplexedConnection, recipe: Recipe) -> Re
     pipe.sadd(key_description_recipes(&recipe.description), id);
     pipe.set(key_recipe_description(id), &recipe.description);
 
+    queue_set_optional_fields(pipe, id, recipe);
+    queue_index_terms_and_tags(pipe, id, recipe);
+}
+
+/// Queues writes of the fields the recipe has, other than the link, title and description
+/// Lists are replaced rather than appended to
+fn queue_set_optional_fields(pipe: &mut Pipeline, id: u64, recipe: &Recipe) {
+    fn queue_set_list(pipe: &mut Pipeline, key: String, values: &[String]) {
+        if values.is_empty() {
+            return;
+        }
+        pipe.del(&key);
+        pipe.cmd("lpush").arg(key);
+        for value in values.iter().rev() {
+            pipe.arg(value);
+        }
+    }
+
     recipe.date.as_ref().map(|v| pipe.set(key_recipe_date(id), v.to_string()));
     recipe.rating.as_ref().map(|v| pipe.set(key_recipe_rating(id), v));
     recipe.rating_count.as_ref().map(|v| pipe.set(key_recipe_rating_count(id), v));
@@ -273,6 +696,8 @@ pub async fn add(mut redis_recipes: MultiplexedConnection, recipe: Recipe) -> Re
     recipe.cook_time_seconds.as_ref().map(|v| pipe.set(key_recipe_cook_time_seconds(id), v));
     recipe.total_time_seconds.as_ref().map(|v| pipe.set(key_recipe_total_time_seconds(id), v));
     recipe.servings.as_ref().map(|v| pipe.set(key_recipe_servings(id), v));
+    recipe.servings_min.as_ref().map(|v| pipe.set(key_recipe_servings_min(id), v));
+    recipe.servings_max.as_ref().map(|v| pipe.set(key_recipe_servings_max(id), v));
     recipe.calories.as_ref().map(|v| pipe.set(key_recipe_calories(id), v));
     recipe.carbohydrates.as_ref().map(|v| pipe.set(key_recipe_carbohydrates(id), v));
     recipe.cholesterol.as_ref().map(|v| pipe.set(key_recipe_cholesterol(id), v));
@@ -282,92 +707,389 @@ pub async fn add(mut redis_recipes: MultiplexedConnection, recipe: Recipe) -> Re
     recipe.saturated_fat.as_ref().map(|v| pipe.set(key_recipe_saturated_fat(id), v));
     recipe.sodium.as_ref().map(|v| pipe.set(key_recipe_sodium(id), v));
     recipe.sugar.as_ref().map(|v| pipe.set(key_recipe_sugar(id), v));
+    recipe.serving_size.as_ref().map(|v| pipe.set(key_recipe_serving_size(id), v));
 
-    if !recipe.keywords.is_empty() {
-        pipe.cmd("lpush").arg(key_recipe_keywords(id));
-        for keyword in recipe.keywords.iter().rev() {
-            pipe.arg(keyword);
-        }
+    queue_set_list(pipe, key_recipe_keywords(id), &recipe.keywords);
+    queue_set_list(pipe, key_recipe_authors(id), &recipe.authors);
+    queue_set_list(pipe, key_recipe_images(id), &recipe.images);
+    queue_set_list(pipe, key_recipe_ingredients(id), &recipe.ingredients);
+    queue_set_list(pipe, key_recipe_instructions(id), &recipe.instructions);
+}
+
+/// Adds the recipe to the indexes of its terms and tags
+fn queue_index_terms_and_tags(pipe: &mut Pipeline, id: u64, recipe: &Recipe) {
+    for term in extract_terms(recipe) {
+        queue_index_term(pipe, id, &term);
     }
 
-    if !recipe.authors.is_empty() {
-        pipe.cmd("lpush").arg(key_recipe_authors(id));
-        for author in recipe.authors.iter().rev() {
-            pipe.arg(author);
-        }
+    for tag in extract_tags(recipe) {
+        queue_index_tag(pipe, id, tag);
     }
+}
 
-    if !recipe.images.is_empty() {
-        pipe.cmd("lpush").arg(key_recipe_images(id));
-        for image in recipe.images.iter().rev() {
-            pipe.arg(image);
-        }
+fn queue_index_term(pipe: &mut Pipeline, id: u64, term: &str) {
+    pipe.sadd(key_term_recipes(term), id);
+    pipe.sadd(key_recipe_terms(id), term);
+    pipe.zadd(key_terms(), term, 0);
+    for trigram in trigrams(term) {
+        pipe.sadd(key_trigram_terms(&trigram), term);
     }
+}
 
-    if !recipe.ingredients.is_empty() {
-        pipe.cmd("lpush").arg(key_recipe_ingredients(id));
-        for ingredient in recipe.ingredients.iter().rev() {
-            pipe.arg(ingredient);
-        }
+/// Removes a term that no recipes have any more from the term and trigram indexes
+fn queue_unindex_term(pipe: &mut Pipeline, term: &str) {
+    pipe.zrem(key_terms(), term);
+    for trigram in trigrams(term) {
+        pipe.srem(key_trigram_terms(&trigram), term);
     }
+}
 
-    if !recipe.instructions.is_empty() {
-        pipe.cmd("lpush").arg(key_recipe_instructions(id));
-        for instruction in recipe.instructions.iter().rev() {
-            pipe.arg(instruction);
-        }
+fn queue_index_tag(pipe: &mut Pipeline, id: u64, tag: &str) {
+    pipe.sadd(key_tag_recipes(tag), id);
+    pipe.sadd(key_recipe_tags(id), tag);
+}
+
+/// Returns the terms and tags the recipe is currently indexed under
+/// Recipes indexed before these were recorded fall back to the terms and tags that would be
+/// extracted from them now
+async fn get_indexed_terms_and_tags(
+    mut redis_recipes: MultiplexedConnection, 
+    id: u64, 
+    recipe: &Recipe,
+) -> Result<(HashSet<String>, HashSet<String>), Error> {
+    let (terms_exist, tags_exist, terms, tags): (bool, bool, HashSet<String>, HashSet<String>) = redis::pipe()
+        .exists(key_recipe_terms(id))
+        .exists(key_recipe_tags(id))
+        .smembers(key_recipe_terms(id))
+        .smembers(key_recipe_tags(id))
+        .query_async(&mut redis_recipes)
+        .await?;
+
+    let terms = if terms_exist {
+        terms
+    } else {
+        extract_terms(recipe).into_iter().collect()
+    };
+    let tags = if tags_exist {
+        tags
+    } else {
+        extract_tags(recipe).into_iter().map(|v| v.to_owned()).collect()
+    };
+
+    Ok((terms, tags))
+}
+
+/// Returns true if added
+/// Returns false if already existed or matches the blacklist
+/// If the recipe already existed, any fields missing from the existing recipe are filled in
+#[tracing::instrument(skip(redis_recipes))]
+pub async fn add(mut redis_recipes: MultiplexedConnection, recipe: Recipe) -> Result<bool, Error> {
+    if let Some(id) = find(redis_recipes.clone(), &recipe).await? {
+        merge(redis_recipes.clone(), id, &recipe).await?;
+        return Ok(false);
     }
+
+    let id: u64 = redis_recipes.incr(key_id(), 1).await?;
+
+    // MULTI/EXEC so that readers never see a partially written recipe, and a dropped
+    // connection can't leave one behind
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+
+    pipe.sadd(key_recipes(), id);
+
+    queue_set_fields(&mut pipe, id, &recipe);
+
+    pipe.exec_async(&mut redis_recipes).await?;
     
-    for term in extract_terms(&recipe) {
-        pipe.sadd(key_term_recipes(&term), id);
+    Ok(true)
+}
+
+/// Fills in any fields of the stored recipe that are missing using `recipe`. If `recipe` is from
+/// the same page (ie the page was recrawled), the rating and nutrition are refreshed too
+/// Only the fields that changed are written, so concurrent merges into the same recipe (eg from
+/// two pages crawled at once) each keep the fields they filled in rather than overwriting the
+/// other's with the values they read beforehand
+/// Returns true if any fields were filled in or refreshed
+#[tracing::instrument(skip(redis_recipes))]
+async fn merge(mut redis_recipes: MultiplexedConnection, id: u64, recipe: &Recipe) -> Result<bool, Error> {
+    let stored = get_recipe(redis_recipes.clone(), id).await?;
+    let mut updated = stored.clone();
+    let refreshed = updated.link == recipe.link && updated.refresh(recipe);
+    let filled = updated.merge(recipe);
+    if !refreshed && !filled {
+        return Ok(false);
     }
 
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+
+    queue_set_optional_fields(&mut pipe, id, &updated.changes_from(&stored));
+    queue_index_terms_and_tags(&mut pipe, id, &updated);
+
     pipe.exec_async(&mut redis_recipes).await?;
-    
+
     Ok(true)
 }
 
+/// Removes a recipe and every index that refers to it, along with any terms that no other
+/// recipe has
+/// Recipes indexed before their terms were recorded may also be in the sets of terms they no
+/// longer have, which are only cleaned up by `reindex`
+/// Returns false if the recipe did not exist
+#[tracing::instrument(skip(redis_recipes))]
+pub async fn delete(mut redis_recipes: MultiplexedConnection, id: u64) -> Result<bool, Error> {
+    let exists: bool = redis_recipes.sismember(key_recipes(), id).await?;
+    if !exists {
+        return Ok(false);
+    }
+
+    let recipe = get_recipe(redis_recipes.clone(), id).await?;
+
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    pipe.srem(key_recipes(), id)
+        .srem(key_title_recipes(&recipe.title), id)
+        .srem(key_description_recipes(&recipe.description), id);
+
+    // another recipe may have since claimed the link, in which case its entry must be kept
+    let link = dedup_link(&recipe.link);
+    let recipe_with_link: Option<u64> = redis_recipes.hget(key_link_recipes(), &link).await?;
+    if recipe_with_link == Some(id) {
+        pipe.hdel(key_link_recipes(), &link);
+    }
+
+    let (terms, tags) = get_indexed_terms_and_tags(redis_recipes.clone(), id, &recipe).await?;
+
+    for term in &terms {
+        pipe.srem(key_term_recipes(term), id);
+    }
+
+    for tag in tags {
+        pipe.srem(key_tag_recipes(&tag), id);
+    }
+
+    for field in RecipeField::ALL {
+        pipe.del(field.key(id));
+    }
+    pipe.del(key_recipe_terms(id))
+        .del(key_recipe_tags(id));
+
+    pipe.exec_async(&mut redis_recipes).await?;
+
+    // Results of previous computation are required here so use a new pipe
+    // A recipe added with one of the terms in between would be missing from the term index
+    // until the next reindex, which is much better than the index filling up with dead terms
+    let terms: Vec<String> = terms.into_iter().collect();
+    let mut pipe = redis::pipe();
+    for term in &terms {
+        pipe.exists(key_term_recipes(term));
+    }
+    let exists: Vec<bool> = pipe.query_async(&mut redis_recipes).await?;
+
+    let mut pipe = redis::pipe();
+    for (term, _) in terms.iter().zip(exists).filter(|(_, exists)| !exists) {
+        queue_unindex_term(&mut pipe, term);
+    }
+    pipe.exec_async(&mut redis_recipes).await?;
+
+    Ok(true)
+}
+
+/// Recomputes the terms and tags of a stored recipe from its fields, and updates the indexes to
+/// match, eg after the term extraction or tagging logic changes
+/// Returns false if the recipe did not exist
+#[tracing::instrument(skip(redis_recipes))]
+pub async fn reindex_recipe(mut redis_recipes: MultiplexedConnection, id: u64) -> Result<bool, Error> {
+    let exists: bool = redis_recipes.sismember(key_recipes(), id).await?;
+    if !exists {
+        return Ok(false);
+    }
+
+    let recipe = get_recipe(redis_recipes.clone(), id).await?;
+    let (old_terms, old_tags) = get_indexed_terms_and_tags(redis_recipes.clone(), id, &recipe).await?;
+    let new_terms: HashSet<String> = extract_terms(&recipe).into_iter().collect();
+    let new_tags: HashSet<&str> = extract_tags(&recipe).into_iter().collect();
+
+    // MULTI/EXEC so that searches never see the recipe missing from its indexes
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+
+    for term in old_terms.iter().filter(|term| !new_terms.contains(*term)) {
+        pipe.srem(key_term_recipes(term), id);
+    }
+    for tag in old_tags.iter().filter(|tag| !new_tags.contains(tag.as_str())) {
+        pipe.srem(key_tag_recipes(tag), id);
+    }
+
+    pipe.del(key_recipe_terms(id))
+        .del(key_recipe_tags(id));
+    for term in &new_terms {
+        queue_index_term(&mut pipe, id, term);
+    }
+    for tag in new_tags {
+        queue_index_tag(&mut pipe, id, tag);
+    }
+
+    pipe.exec_async(&mut redis_recipes).await?;
+
+    Ok(true)
+}
+
+/// Reindexes (see `reindex_recipe`) every stored recipe, then removes any index entries they
+/// are no longer indexed under (see `prune_index`) and rebuilds the term index to match (see
+/// `rebuild_terms`)
+/// Returns the number of recipes reindexed and the number of stale index entries removed
+#[tracing::instrument(skip(redis_recipes))]
+pub async fn reindex(redis_recipes: MultiplexedConnection) -> Result<(usize, usize), Error> {
+    // SSCAN can return an id more than once
+    let mut reindexed = HashSet::new();
+    let mut cursor: u64 = 0;
+    loop {
+        let (next_cursor, ids) = scan_recipes(redis_recipes.clone(), cursor, 1000).await?;
+        for id in ids {
+            if reindexed.contains(&id) {
+                continue;
+            }
+            if reindex_recipe(redis_recipes.clone(), id).await? {
+                reindexed.insert(id);
+            }
+        }
+
+        if next_cursor == 0 {
+            break;
+        }
+        cursor = next_cursor;
+    }
+
+    // every recipe now has its terms and tags recorded
+    let pruned = prune_index(redis_recipes.clone(), &pattern_term_recipes(), key_recipe_terms).await?
+        + prune_index(redis_recipes.clone(), &pattern_tag_recipes(), key_recipe_tags).await?;
+    rebuild_terms(redis_recipes.clone()).await?;
+
+    Ok((reindexed.len(), pruned))
+}
+
+/// Removes recipes from every index set matching `pattern` (eg term:*:recipes) that they aren't
+/// recorded as being indexed under by `indexed_key` (eg `key_recipe_terms`)
+/// This cleans up after recipes that were deleted or reindexed before their terms and tags were
+/// recorded, which left them in the sets for terms and tags they no longer have, so must only
+/// run once every recipe has its terms and tags recorded
+/// Uses SCAN and SSCAN so Redis isn't blocked while walking the indexes
+/// Returns the number of entries removed
+#[tracing::instrument(skip(redis_recipes, indexed_key))]
+async fn prune_index(
+    mut redis_recipes: MultiplexedConnection, 
+    pattern: &str, 
+    indexed_key: fn(u64) -> String,
+) -> Result<usize, Error> {
+    // eg term:*:recipes -> term: and :recipes
+    let (prefix, suffix) = pattern.split_once('*').unwrap();
+
+    let mut keys = HashSet::new();
+    let mut cursor: u64 = 0;
+    loop {
+        let (next_cursor, page): (u64, Vec<String>) = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(pattern)
+            .arg("COUNT")
+            .arg(1000)
+            .query_async(&mut redis_recipes)
+            .await?;
+        keys.extend(page);
+
+        if next_cursor == 0 {
+            break;
+        }
+        cursor = next_cursor;
+    }
+
+    let mut pruned = 0;
+    for key in keys {
+        let Some(name) = key.strip_prefix(prefix).and_then(|v| v.strip_suffix(suffix)) else {
+            continue;
+        };
+
+        let mut ids = HashSet::new();
+        let mut connection = redis_recipes.clone();
+        let mut iter = connection.sscan::<_, u64>(&key).await?;
+        while let Some(id) = iter.next_item().await {
+            ids.insert(id);
+        }
+        let ids: Vec<u64> = ids.into_iter().collect();
+
+        let mut pipe = redis::pipe();
+        for id in &ids {
+            pipe.sismember(indexed_key(*id), name);
+        }
+        let indexed: Vec<bool> = pipe.query_async(&mut redis_recipes).await?;
+
+        let stale: Vec<u64> = ids.into_iter()
+            .zip(indexed)
+            .filter(|(_, indexed)| !indexed)
+            .map(|(id, _)| id)
+            .collect();
+        if !stale.is_empty() {
+            let _: () = redis_recipes.srem(&key, &stale).await?;
+            pruned += stale.len();
+        }
+    }
+
+    Ok(pruned)
+}
+
+/// Returns the next cursor and roughly `count` recipe ids, starting from `cursor` (0 to start
+/// from the beginning). Once the returned cursor is 0, every recipe that existed for the whole
+/// scan has been returned at least once (ids can be returned more than once)
+/// Uses SSCAN so Redis isn't blocked when walking every recipe
+#[tracing::instrument(skip(redis_recipes))]
+pub async fn scan_recipes(mut redis_recipes: MultiplexedConnection, cursor: u64, count: usize) -> Result<(u64, Vec<u64>), Error> {
+    Ok(redis::cmd("SSCAN")
+        .arg(key_recipes())
+        .arg(cursor)
+        .arg("COUNT")
+        .arg(count)
+        .query_async(&mut redis_recipes)
+        .await?)
+}
+
+/// Returns the total number of recipes, along with the next cursor and roughly `count` recipe
+/// ids (see `scan_recipes`), so callers can page through every recipe without the whole set
+/// being loaded at once
+#[tracing::instrument(skip(redis_recipes))]
+pub async fn list_recipes(redis_recipes: MultiplexedConnection, cursor: u64, count: usize) -> Result<(usize, u64, Vec<u64>), Error> {
+    let total = recipe_count(redis_recipes.clone()).await?;
+    let (next_cursor, ids) = scan_recipes(redis_recipes, cursor, count).await?;
+    Ok((total, next_cursor, ids))
+}
+
 #[tracing::instrument(skip(redis_recipes))]
 pub async fn recipe_count(mut redis_recipes: MultiplexedConnection) -> Result<usize, Error> {
     Ok(redis_recipes.scard(key_recipes()).await?)
 }
 
+/// Looks for an existing recipe with the same link, falling back to one with the same title
+/// and description (eg the same recipe syndicated on another site)
 #[tracing::instrument(skip(redis_recipes))]
-async fn exists(mut redis_recipes: MultiplexedConnection, recipe: &Recipe) -> Result<bool, Error> {
-    let recipes_with_titles: Vec<usize> = redis_recipes.smembers(key_title_recipes(&recipe.title)).await?;
-    let recipes_with_description: Vec<usize> = redis_recipes.smembers(key_description_recipes(&recipe.description)).await?;
+async fn find(mut redis_recipes: MultiplexedConnection, recipe: &Recipe) -> Result<Option<u64>, Error> {
+    let recipe_with_link: Option<u64> = redis_recipes.hget(key_link_recipes(), dedup_link(&recipe.link)).await?;
+    if recipe_with_link.is_some() {
+        return Ok(recipe_with_link);
+    }
 
-    Ok(recipes_with_titles.iter().any(|x| recipes_with_description.contains(x)))
+    let recipes_with_titles: Vec<u64> = redis_recipes.smembers(key_title_recipes(&recipe.title)).await?;
+    let recipes_with_description: Vec<u64> = redis_recipes.smembers(key_description_recipes(&recipe.description)).await?;
+
+    Ok(recipes_with_titles.into_iter().find(|x| recipes_with_description.contains(x)))
 }
 
 #[tracing::instrument(skip(redis_recipes))]
 pub async fn get_recipe(mut redis_recipes: MultiplexedConnection, id: u64) -> Result<Recipe, Error> {
     let mut pipe = redis::pipe();
-
-    pipe.get(key_recipe_link(id));
-    pipe.get(key_recipe_title(id));
-    pipe.get(key_recipe_description(id));
-    pipe.lrange(key_recipe_ingredients(id), 0, -1);
-    pipe.lrange(key_recipe_instructions(id), 0, -1);
-    pipe.get(key_recipe_date(id));
-    pipe.lrange(key_recipe_keywords(id), 0, -1);
-    pipe.lrange(key_recipe_authors(id), 0, -1);
-    pipe.lrange(key_recipe_images(id), 0, -1);
-    pipe.get(key_recipe_rating(id));
-    pipe.get(key_recipe_rating_count(id));
-    pipe.get(key_recipe_prep_time_seconds(id));
-    pipe.get(key_recipe_cook_time_seconds(id));
-    pipe.get(key_recipe_total_time_seconds(id));
-    pipe.get(key_recipe_servings(id));
-    pipe.get(key_recipe_calories(id));
-    pipe.get(key_recipe_carbohydrates(id));
-    pipe.get(key_recipe_cholesterol(id));
-    pipe.get(key_recipe_fat(id));
-    pipe.get(key_recipe_fiber(id));
-    pipe.get(key_recipe_protein(id));
-    pipe.get(key_recipe_saturated_fat(id));
-    pipe.get(key_recipe_sodium(id));
-    pipe.get(key_recipe_sugar(id));
+    for field in RecipeField::ALL {
+        field.queue_get(&mut pipe, id);
+    }
 
     let recipe = pipe.query_async(&mut redis_recipes)
         .await?;
@@ -375,27 +1097,1023 @@ pub async fn get_recipe(mut redis_recipes: MultiplexedConnection, id: u64) -> Re
     Ok(recipe)
 }
 
-fn split_by_space(string: &str) -> Vec<String> {
-    string.split(' ').map(|v| v.to_string()).collect()
+/// Like `get_recipe`, but fetches many recipes in a single pipeline
+#[tracing::instrument(skip(redis_recipes))]
+pub async fn get_recipes(mut redis_recipes: MultiplexedConnection, ids: &[u64]) -> Result<Vec<Recipe>, Error> {
+    let mut pipe = redis::pipe();
+    for id in ids {
+        for field in RecipeField::ALL {
+            field.queue_get(&mut pipe, *id);
+        }
+    }
+
+    let values: Vec<Value> = pipe.query_async(&mut redis_recipes).await?;
+
+    Ok(values.chunks(RecipeField::ALL.len())
+        .map(|values| Recipe::from_redis_value(&Value::Array(values.to_vec())))
+        .collect::<Result<Vec<Recipe>, RedisError>>()?)
+}
+
+/// Fetches only the requested fields of a recipe, which is much cheaper than `get_recipe`
+/// when the large lists (ingredients, instructions, etc) aren't needed
+#[tracing::instrument(skip(redis_recipes))]
+pub async fn get_recipe_fields(
+    redis_recipes: MultiplexedConnection, 
+    id: u64, 
+    fields: &[RecipeField]
+) -> Result<HashMap<RecipeField, Value>, Error> {
+    Ok(get_recipes_fields(redis_recipes, &[id], fields).await?.remove(0))
+}
+
+/// Like `get_recipe_fields`, but fetches the fields of many recipes in a single pipeline
+#[tracing::instrument(skip(redis_recipes))]
+pub async fn get_recipes_fields(
+    mut redis_recipes: MultiplexedConnection, 
+    ids: &[u64], 
+    fields: &[RecipeField]
+) -> Result<Vec<HashMap<RecipeField, Value>>, Error> {
+    if fields.is_empty() {
+        return Ok(vec![HashMap::new(); ids.len()]);
+    }
+
+    let mut pipe = redis::pipe();
+    for id in ids {
+        for field in fields {
+            field.queue_get(&mut pipe, *id);
+        }
+    }
+
+    let values: Vec<Value> = pipe.query_async(&mut redis_recipes).await?;
+
+    Ok(values.chunks(fields.len())
+        .map(|values| fields.iter().copied().zip(values.iter().cloned()).collect())
+        .collect())
+}
+
+/// Fetches the cards of many recipes in a single pipeline, in the same order as `ids`
+/// Recipes that don't exist are None
+#[tracing::instrument(skip(redis_recipes))]
+pub async fn get_recipe_cards(redis_recipes: MultiplexedConnection, ids: &[u64]) -> Result<Vec<Option<RecipeCard>>, Error> {
+    let recipes_fields = get_recipes_fields(redis_recipes, ids, &[
+        RecipeField::Link,
+        RecipeField::Title,
+        RecipeField::Images,
+        RecipeField::Rating,
+        RecipeField::TotalTimeSeconds,
+    ]).await?;
+
+    let mut cards = vec![];
+    for (id, fields) in ids.iter().zip(recipes_fields) {
+        // every recipe has a link
+        if fields.get(&RecipeField::Link) == Some(&Value::Nil) {
+            cards.push(None);
+            continue;
+        }
+
+        cards.push(Some(RecipeCard {
+            id: *id,
+            link: get_field_value(&fields, RecipeField::Link)?,
+            title: get_field_value(&fields, RecipeField::Title)?,
+            image: get_field_value::<Vec<String>>(&fields, RecipeField::Images)?.into_iter().next(),
+            rating: get_field_value(&fields, RecipeField::Rating)?,
+            total_time_seconds: get_field_value(&fields, RecipeField::TotalTimeSeconds)?,
+        }));
+    }
+
+    Ok(cards)
+}
+
+/// Counts how many recipes have each keyword (case insensitive), returning the `limit`
+/// most common keywords in descending order of recipe count
+/// Uses SCAN so Redis isn't blocked while the keyword lists are collected
+#[tracing::instrument(skip(redis_recipes))]
+pub async fn keyword_frequencies(mut redis_recipes: MultiplexedConnection, limit: usize) -> Result<Vec<(String, usize)>, Error> {
+    let mut counts = HashMap::<String, usize>::new();
+    let mut cursor: u64 = 0;
+    loop {
+        let (next_cursor, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(pattern_recipe_keywords())
+            .arg("COUNT")
+            .arg(1000)
+            .query_async(&mut redis_recipes)
+            .await?;
+
+        let mut pipe = redis::pipe();
+        for key in &keys {
+            pipe.lrange(key, 0, -1);
+        }
+        let keyword_lists: Vec<Vec<String>> = pipe.query_async(&mut redis_recipes).await?;
+
+        for keywords in keyword_lists {
+            let keywords: HashSet<String> = keywords.iter()
+                .map(|v| v.to_lowercase())
+                .collect();
+            for keyword in keywords {
+                *counts.entry(keyword).or_default() += 1;
+            }
+        }
+
+        if next_cursor == 0 {
+            break;
+        }
+        cursor = next_cursor;
+    }
+
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts.truncate(limit);
+
+    Ok(counts)
+}
+
+/// How many recipes have the term
+#[tracing::instrument(skip(redis_recipes))]
+pub async fn term_recipe_count(mut redis_recipes: MultiplexedConnection, term: &str) -> Result<usize, Error> {
+    Ok(redis_recipes.scard(key_term_recipes(term)).await?)
+}
+
+/// Returns up to `limit` terms starting with `prefix` that at least one recipe has, in
+/// alphabetical order, along with how many recipes have each term (see `term_recipe_count`)
+/// Terms can be left in the term index without recipes (see `delete`), so those are skipped
+#[tracing::instrument(skip(redis_recipes))]
+pub async fn suggest_terms(mut redis_recipes: MultiplexedConnection, prefix: &str, limit: usize) -> Result<Vec<(String, usize)>, Error> {
+    let prefix = prefix.trim().to_lowercase();
+    let min = format!("[{prefix}");
+    // 0xff never appears in UTF-8, so sorts after every term starting with the prefix
+    let mut max = format!("[{prefix}").into_bytes();
+    max.push(0xff);
+
+    let mut suggestions = vec![];
+    let mut offset = 0;
+    while suggestions.len() < limit {
+        let terms: Vec<String> = redis_recipes.zrangebylex_limit(key_terms(), &min, &max, offset, limit as isize).await?;
+        if terms.is_empty() {
+            break;
+        }
+        offset += terms.len() as isize;
+
+        // spawned together so the multiplexed connection pipelines them
+        let counts: Vec<_> = terms.iter()
+            .map(|term| {
+                let redis_recipes = redis_recipes.clone();
+                let term = term.clone();
+                tokio::spawn(async move { term_recipe_count(redis_recipes, &term).await })
+            })
+            .collect();
+        for (term, count) in terms.into_iter().zip(counts) {
+            let count = count.await??;
+            if count > 0 {
+                suggestions.push((term, count));
+            }
+        }
+    }
+    suggestions.truncate(limit);
+
+    Ok(suggestions)
+}
+
+/// Adds every term that has recipes to the term and trigram indexes (for recipes indexed before
+/// they existed), and removes every term that no longer has any recipes
+/// Uses SCAN and ZSCAN so Redis isn't blocked while the terms are collected
+/// Returns the number of terms in the index
+#[tracing::instrument(skip(redis_recipes))]
+async fn rebuild_terms(mut redis_recipes: MultiplexedConnection) -> Result<usize, Error> {
+    let mut cursor: u64 = 0;
+    loop {
+        let (next_cursor, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(pattern_term_recipes())
+            .arg("COUNT")
+            .arg(1000)
+            .query_async(&mut redis_recipes)
+            .await?;
+
+        let mut pipe = redis::pipe();
+        for key in &keys {
+            // eg term:pancake:recipes -> pancake
+            if let Some(term) = key.strip_prefix("term:").and_then(|v| v.strip_suffix(":recipes")) {
+                pipe.zadd(key_terms(), term, 0);
+                for trigram in trigrams(term) {
+                    pipe.sadd(key_trigram_terms(&trigram), term);
+                }
+            }
+        }
+        pipe.exec_async(&mut redis_recipes).await?;
+
+        if next_cursor == 0 {
+            break;
+        }
+        cursor = next_cursor;
+    }
+
+    let mut terms = HashSet::new();
+    let mut connection = redis_recipes.clone();
+    let mut iter = connection.zscan::<_, (String, f64)>(key_terms()).await?;
+    while let Some((term, _)) = iter.next_item().await {
+        terms.insert(term);
+    }
+    let terms: Vec<String> = terms.into_iter().collect();
+
+    for terms in terms.chunks(1000) {
+        let mut pipe = redis::pipe();
+        for term in terms {
+            pipe.exists(key_term_recipes(term));
+        }
+        let exists: Vec<bool> = pipe.query_async(&mut redis_recipes).await?;
+
+        let mut pipe = redis::pipe();
+        for (term, _) in terms.iter().zip(exists).filter(|(_, exists)| !exists) {
+            queue_unindex_term(&mut pipe, term);
+        }
+        pipe.exec_async(&mut redis_recipes).await?;
+    }
+
+    Ok(redis_recipes.zcard(key_terms()).await?)
+}
+
+/// How similar (see `similar_terms`) an indexed term must be to an unmatched query term to be
+/// searched for instead
+const FUZZY_MIN_SIMILARITY: f64 = 0.5;
+const FUZZY_MAX_CANDIDATES: usize = 5;
+
+/// Common English words that match almost every recipe, so aren't worth indexing
+const STOP_WORDS: [&str; 33] = [
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "from", "if", "in", "into", "is", "it", 
+    "its", "of", "on", "or", "so", "than", "that", "the", "then", "this", "to", "until", "up", "was", "with",
+    "you", "your",
+];
+
+/// Lowercases a term and strips any surrounding punctuation, returning None if nothing is
+/// left or it's a stop word
+/// eg 'Chicken,' -> 'chicken', '(optional)' -> 'optional', 'The' -> None
+pub fn normalize_term(term: &str) -> Option<String> {
+    let term = term.trim_matches(|c: char| !c.is_alphanumeric())
+        .to_lowercase();
+
+    if term.is_empty() || STOP_WORDS.contains(&term.as_str()) {
+        return None;
+    }
+
+    Some(term)
+}
+
+fn split_into_terms(string: &str) -> Vec<String> {
+    string.split_whitespace()
+        .filter_map(normalize_term)
+        .collect()
 }
 
 pub fn extract_terms(recipe: &Recipe) -> Vec<String> {
     let mut terms = vec![];
-    terms.append(&mut split_by_space(&recipe.title));
-    terms.append(&mut split_by_space(&recipe.description));
+    terms.append(&mut split_into_terms(&recipe.title));
+    terms.append(&mut split_into_terms(&recipe.description));
     for keyword in &recipe.keywords {
-        terms.append(&mut split_by_space(keyword));
+        terms.append(&mut split_into_terms(keyword));
     }
     for ingredient in &recipe.ingredients {
-        terms.append(&mut split_by_space(ingredient));
+        terms.append(&mut split_into_terms(ingredient));
     }
     for instruction in &recipe.instructions {
-        terms.append(&mut split_by_space(instruction));
+        terms.append(&mut split_into_terms(instruction));
     }
     terms
 }
 
+/// Dietary tags and the phrases that indicate them. Phrases are matched as whole words after
+/// normalizing with `tag_text`, so hyphenated variants (eg 'dairy-free') are covered by the
+/// spaced version
+const TAG_SYNONYMS: [(&str, &[&str]); 7] = [
+    ("vegetarian", &["vegetarian"]),
+    ("vegan", &["vegan", "plant based"]),
+    ("gluten free", &["gluten free", "glutenfree", "coeliac", "celiac"]),
+    ("dairy free", &["dairy free", "lactose free", "non dairy"]),
+    ("keto", &["keto", "ketogenic"]),
+    ("paleo", &["paleo"]),
+    ("nut free", &["nut free"]),
+];
+
+/// Lowercases, strips accents and replaces punctuation with spaces, padding with a space either
+/// side so phrases can be matched as whole words
+/// eg 'Dairy-Free, Keto' -> ' dairy free keto ', 'Crème fraîche' -> ' creme fraiche '
+fn tag_text(string: &str) -> String {
+    let words: String = string.to_lowercase()
+        .nfd()
+        .filter(|c| !is_combining_mark(*c))
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect();
+    format!(" {} ", words.split_whitespace().collect::<Vec<&str>>().join(" "))
+}
+
+/// Maps a tag or any of its synonyms to the tag it's stored as
+/// eg 'Ketogenic' -> 'keto', 'dairy-free' -> 'dairy free'
+pub fn normalize_tag(tag: &str) -> Option<&'static str> {
+    let text = tag_text(tag);
+    TAG_SYNONYMS.iter()
+        .find(|(tag, synonyms)| tag_text(tag) == text || synonyms.iter().any(|synonym| tag_text(synonym) == text))
+        .map(|(tag, _)| *tag)
+}
+
+/// Ingredients that make a recipe not vegetarian (worcestershire sauce and fish sauce usually
+/// contain anchovies)
+const MEAT_INGREDIENTS: [&str; 56] = [
+    "anchovies", "anchovy", "bacon", "beef", "brisket", "calamari", "chicken", "chorizo", "clam", "cod",
+    "crab", "duck", "fish", "geese", "gelatin", "gelatine", "goose", "guanciale", "haddock", "halibut", "ham",
+    "herring", "lamb", "lard", "lobster", "mackerel", "meat", "mince", "mussel", "mutton", "octopus",
+    "oyster", "pancetta", "pepperoni", "pheasant", "pork", "prawn", "prosciutto", "quail", "rabbit", "salami",
+    "salmon", "sardine", "sausage", "scallop", "shrimp", "squid", "steak", "suet", "trout", "tuna", "turkey",
+    "veal", "venison", "worcester", "worcestershire",
+];
+
+/// Ingredients that make a vegetarian recipe not vegan
+const ANIMAL_PRODUCT_INGREDIENTS: [&str; 28] = [
+    "brie", "butter", "buttermilk", "camembert", "cheddar", "cheese", "cream", "creme", "custard", "egg",
+    "feta", "ghee", "gouda", "gruyere", "halloumi", "honey", "mascarpone", "mayo", "mayonnaise", "milk",
+    "mozzarella", "paneer", "parmesan", "pecorino", "ricotta", "whey", "yoghurt", "yogurt",
+];
+
+/// Whether any word of any ingredient is (the plural of) one of the terms
+fn ingredients_contain(ingredients: &[HashSet<&str>], terms: &[&str]) -> bool {
+    terms.iter().any(|term| ingredients.iter().any(|words| {
+        words.contains(term) || words.contains(format!("{term}s").as_str()) || words.contains(format!("{term}es").as_str())
+    }))
+}
+
+/// Infers vegetarian and vegan tags from the ingredients. This is deliberately conservative:
+/// anything that looks like an animal product rules the tag out, even if it's eg 'coconut milk'
+fn extract_ingredient_tags(recipe: &Recipe) -> Vec<&'static str> {
+    if recipe.ingredients.is_empty() {
+        return vec![];
+    }
+
+    let texts: Vec<String> = recipe.ingredients.iter()
+        .map(|ingredient| tag_text(ingredient))
+        .collect();
+    let ingredients: Vec<HashSet<&str>> = texts.iter()
+        .map(|text| text.split_whitespace().collect())
+        .collect();
+
+    if ingredients_contain(&ingredients, &MEAT_INGREDIENTS) {
+        return vec![];
+    }
+
+    if ingredients_contain(&ingredients, &ANIMAL_PRODUCT_INGREDIENTS) {
+        return vec!["vegetarian"];
+    }
+
+    vec!["vegetarian", "vegan"]
+}
+
+/// Finds dietary tags mentioned in the recipe's keywords, title or description, plus any
+/// that can be inferred from the ingredients
+/// Matches whole words only, so eg 'peanut free' does not count as 'nut free'
+pub fn extract_tags(recipe: &Recipe) -> Vec<&'static str> {
+    let mut texts = vec![tag_text(&recipe.title), tag_text(&recipe.description)];
+    for keyword in &recipe.keywords {
+        texts.push(tag_text(keyword));
+    }
+
+    let mut tags: Vec<&'static str> = TAG_SYNONYMS.iter()
+        .filter(|(_, synonyms)| synonyms.iter()
+            .map(|synonym| tag_text(synonym))
+            .any(|synonym| texts.iter().any(|text| text.contains(&synonym))))
+        .map(|(tag, _)| *tag)
+        .collect();
+
+    for tag in extract_ingredient_tags(recipe) {
+        if !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+
+    tags
+}
+
+/// Returns the recipes that have every one of the tags
+#[tracing::instrument(skip(redis_recipes))]
+pub async fn get_recipes_by_tags(mut redis_recipes: MultiplexedConnection, tags: &[&str]) -> Result<HashSet<usize>, Error> {
+    let keys: Vec<String> = tags.iter()
+        .map(|tag| key_tag_recipes(tag))
+        .collect();
+    Ok(redis_recipes.sinter(keys).await?)
+}
+
 pub async fn get_recipes_by_term(mut redis_recipes: MultiplexedConnection, term: &str) -> HashSet<usize> {
     redis_recipes.smembers(key_term_recipes(term)).await.unwrap_or(HashSet::new())
 }
 
+/// Every 3 character substring of the term, padded so that the start and end of the term count
+/// for more (eg 'egg' -> '  e', ' eg', 'egg', 'gg ')
+fn trigrams(term: &str) -> HashSet<String> {
+    let chars: Vec<char> = format!("  {term} ").chars().collect();
+    chars.windows(3)
+        .map(|v| v.iter().collect())
+        .collect()
+}
+
+/// Returns up to `FUZZY_MAX_CANDIDATES` indexed terms that share enough trigrams with `term` to
+/// probably be a typo or different form of it (eg 'tomatos' -> 'tomato'), along with their
+/// similarity (the Jaccard index of the trigram sets, from 0 to 1)
+#[tracing::instrument(skip(redis_recipes))]
+async fn similar_terms(mut redis_recipes: MultiplexedConnection, term: &str) -> Result<Vec<(String, f64)>, Error> {
+    let term_trigrams = trigrams(term);
+
+    let mut pipe = redis::pipe();
+    for trigram in &term_trigrams {
+        pipe.smembers(key_trigram_terms(trigram));
+    }
+    let candidate_sets: Vec<Vec<String>> = pipe.query_async(&mut redis_recipes).await?;
+
+    let mut shared = HashMap::<String, usize>::new();
+    for candidate in candidate_sets.into_iter().flatten() {
+        *shared.entry(candidate).or_default() += 1;
+    }
+
+    let mut similar: Vec<(String, f64)> = shared.into_iter()
+        .map(|(candidate, shared)| {
+            let union = term_trigrams.len() + trigrams(&candidate).len() - shared;
+            (candidate, shared as f64 / union as f64)
+        })
+        .filter(|(_, similarity)| *similarity >= FUZZY_MIN_SIMILARITY)
+        .collect();
+    similar.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    similar.truncate(FUZZY_MAX_CANDIDATES);
+
+    Ok(similar)
+}
+
+/// Returns every recipe matching at least one of the terms, ranked by the summed inverse
+/// document frequency of the terms each recipe matches, so rare terms (eg 'gochujang')
+/// count for much more than common ones (eg 'and'). Ties are broken by id
+/// Terms that no recipe has are matched to similar terms instead (see `similar_terms`), whose
+/// matches are weighted by similarity and always ranked below recipes with the same exact score
+#[tracing::instrument(skip(redis_recipes))]
+pub async fn rank_recipes_by_terms(redis_recipes: MultiplexedConnection, terms: &[String]) -> Vec<usize> {
+    // the query needs to be normalized in the same way as the indexed terms
+    let terms: HashSet<String> = terms.iter()
+        .flat_map(|term| split_into_terms(term))
+        .collect();
+    let recipe_count = recipe_count(redis_recipes.clone()).await.unwrap_or(0).max(1) as f64;
+
+    let mut scores = HashMap::<usize, (f64, f64)>::new();
+    for term in terms {
+        let ids = get_recipes_by_term(redis_recipes.clone(), &term).await;
+        if !ids.is_empty() {
+            // the term's set already holds every recipe containing it, so its size is the document frequency
+            let idf = (1.0 + recipe_count / ids.len() as f64).ln();
+            for id in ids {
+                scores.entry(id).or_default().0 += idf;
+            }
+            continue;
+        }
+
+        for (similar_term, similarity) in similar_terms(redis_recipes.clone(), &term).await.unwrap_or_default() {
+            let ids = get_recipes_by_term(redis_recipes.clone(), &similar_term).await;
+            if ids.is_empty() {
+                continue;
+            }
+            let idf = (1.0 + recipe_count / ids.len() as f64).ln();
+            for id in ids {
+                scores.entry(id).or_default().1 += idf * similarity;
+            }
+        }
+    }
+
+    let mut ranked: Vec<(usize, (f64, f64))> = scores.into_iter().collect();
+    ranked.sort_by(|a, b| b.1.0.total_cmp(&a.1.0)
+        .then_with(|| b.1.1.total_cmp(&a.1.1))
+        .then_with(|| a.0.cmp(&b.0)));
+    ranked.into_iter()
+        .map(|(id, _)| id)
+        .collect()
+}
+
+/// Returns the total number of recipes matching the terms, and one page of the ranked results
+#[tracing::instrument(skip(redis_recipes))]
+pub async fn get_recipes_by_terms(
+    redis_recipes: MultiplexedConnection, 
+    terms: &[String], 
+    offset: usize, 
+    limit: usize
+) -> (usize, Vec<usize>) {
+    let ranked = rank_recipes_by_terms(redis_recipes, terms).await;
+    let total = ranked.len();
+    let page = ranked.into_iter()
+        .skip(offset)
+        .take(limit)
+        .collect();
+    (total, page)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::test_redis;
+
+    use super::*;
+
+    fn with_ingredients(ingredients: &[&str]) -> Recipe {
+        Recipe {
+            ingredients: ingredients.iter().map(|v| v.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn refresh_overwrites_rating_and_nutrition() {
+        let mut stored = Recipe {
+            title: "Pancakes".to_string(),
+            rating: Some(4.0),
+            rating_count: Some(10),
+            calories: Some(200.0),
+            protein: Some(5.0),
+            ..Default::default()
+        };
+        let fresh = Recipe {
+            title: "Better pancakes".to_string(),
+            rating: Some(4.5),
+            rating_count: Some(12),
+            calories: Some(250.0),
+            ..Default::default()
+        };
+
+        assert!(stored.refresh(&fresh));
+        assert_eq!(stored.title, "Pancakes");
+        assert_eq!((stored.rating, stored.rating_count), (Some(4.5), Some(12)));
+        assert_eq!((stored.calories, stored.protein), (Some(250.0), Some(5.0)));
+        assert!(!stored.refresh(&fresh));
+    }
+
+    #[test]
+    fn has_plausible_instructions_checks_longest_and_total() {
+        let recipe = Recipe { instructions: vec!["Whisk".to_string(), "Fry until golden".to_string()], ..Default::default() };
+        assert!(recipe.has_plausible_instructions(None, None));
+        assert!(recipe.has_plausible_instructions(Some(16), Some(21)));
+        assert!(!recipe.has_plausible_instructions(Some(17), None));
+        assert!(!recipe.has_plausible_instructions(None, Some(22)));
+        assert!(!Recipe::default().has_plausible_instructions(Some(1), None));
+    }
+
+    #[test]
+    fn merge_fills_servings_from_one_source() {
+        let mut stored = Recipe { servings_max: Some(6.0), ..Default::default() };
+        let other = Recipe { servings: Some("4".to_string()), servings_min: Some(4.0), servings_max: Some(4.0), ..Default::default() };
+        assert!(!stored.merge(&other));
+        assert_eq!((stored.servings, stored.servings_min, stored.servings_max), (None, None, Some(6.0)));
+
+        let mut stored = Recipe::default();
+        assert!(stored.merge(&other));
+        assert_eq!((stored.servings.as_deref(), stored.servings_min, stored.servings_max), (Some("4"), Some(4.0), Some(4.0)));
+    }
+
+    #[test]
+    fn parse_servings_range_handles_ranges_and_words() {
+        assert_eq!(parse_servings_range("6"), Some((6.0, 6.0)));
+        assert_eq!(parse_servings_range("4-6"), Some((4.0, 6.0)));
+        assert_eq!(parse_servings_range("serves 4 to 6 people"), Some((4.0, 6.0)));
+        assert_eq!(parse_servings_range("4–6 servings"), Some((4.0, 6.0)));
+        assert_eq!(parse_servings_range("1.5 loaves"), Some((1.5, 1.5)));
+        // a backwards range is treated as a single number
+        assert_eq!(parse_servings_range("6-4"), Some((6.0, 6.0)));
+        assert_eq!(parse_servings_range("a few"), None);
+    }
+
+    #[test]
+    fn changes_from_keeps_only_changed_fields() {
+        let old = Recipe {
+            ingredients: vec!["2 eggs".to_string()],
+            rating: Some(4.0),
+            calories: Some(200.0),
+            ..Default::default()
+        };
+        let mut updated = old.clone();
+        updated.merge(&Recipe {
+            images: vec!["pancakes.jpg".to_string()],
+            rating: Some(5.0),
+            protein: Some(5.0),
+            ..Default::default()
+        });
+
+        let changes = updated.changes_from(&old);
+        assert!(changes.ingredients.is_empty());
+        assert_eq!(changes.images, vec!["pancakes.jpg"]);
+        assert_eq!((changes.rating, changes.calories, changes.protein), (None, None, Some(5.0)));
+    }
+
+    #[test]
+    fn normalize_term_strips_punctuation_and_stop_words() {
+        assert_eq!(normalize_term("Chicken,"), Some("chicken".to_string()));
+        assert_eq!(normalize_term("(optional)"), Some("optional".to_string()));
+        assert_eq!(normalize_term("self-raising"), Some("self-raising".to_string()));
+        assert_eq!(normalize_term("Crème"), Some("crème".to_string()));
+        assert_eq!(normalize_term("The"), None);
+        assert_eq!(normalize_term("--"), None);
+        assert_eq!(normalize_term(""), None);
+    }
+
+    #[test]
+    fn extract_tags_infers_vegan_and_vegetarian() {
+        assert_eq!(extract_tags(&with_ingredients(&["2 cups flour", "1 tsp salt"])), vec!["vegetarian", "vegan"]);
+        assert_eq!(extract_tags(&with_ingredients(&["2 cups flour", "2 eggs"])), vec!["vegetarian"]);
+        assert!(extract_tags(&with_ingredients(&[])).is_empty());
+    }
+
+    #[test]
+    fn extract_tags_rules_out_cheese_and_meat() {
+        assert_eq!(extract_tags(&with_ingredients(&["2 cups mozzarella", "1 tomato"])), vec!["vegetarian"]);
+        assert!(extract_tags(&with_ingredients(&["4 veal cutlets"])).is_empty());
+        assert!(extract_tags(&with_ingredients(&["1 tbsp Worcestershire sauce"])).is_empty());
+        assert!(extract_tags(&with_ingredients(&["2 tbsp fish sauce"])).is_empty());
+    }
+
+    #[test]
+    fn extract_tags_strips_accents() {
+        assert_eq!(extract_tags(&with_ingredients(&["200ml crème fraîche"])), vec!["vegetarian"]);
+        assert_eq!(extract_tags(&with_ingredients(&["100g Gruyère"])), vec!["vegetarian"]);
+    }
+
+    #[test]
+    fn extract_tags_matches_whole_words() {
+        let recipe = Recipe {
+            title: "Peanut-free Dairy-Free cookies".to_string(),
+            keywords: vec!["Ketogenic".to_string()],
+            ..with_ingredients(&["1 egg"])
+        };
+        assert_eq!(extract_tags(&recipe), vec!["dairy free", "keto", "vegetarian"]);
+    }
+
+    #[test]
+    fn completeness_is_the_fraction_of_optional_fields_present() {
+        let empty = Recipe::default();
+        assert_eq!(empty.completeness(), 0.0);
+        assert!(!empty.is_complete());
+
+        let partial = Recipe {
+            images: vec!["a.jpg".to_string()],
+            ingredients: vec!["2 eggs".to_string()],
+            rating: Some(4.5),
+            ..Default::default()
+        };
+        assert_eq!(partial.completeness(), 3.0 / 18.0);
+        assert!(!partial.is_complete());
+
+        let full = Recipe {
+            images: vec!["a.jpg".to_string()],
+            authors: vec!["Someone".to_string()],
+            date: Some("2024-01-01".to_string()),
+            servings: Some("4".to_string()),
+            total_time_seconds: Some(600),
+            ingredients: vec!["2 eggs".to_string()],
+            rating: Some(4.5),
+            rating_count: Some(10),
+            keywords: vec!["breakfast".to_string()],
+            calories: Some(200.0),
+            carbohydrates: Some(30.0),
+            cholesterol: Some(50.0),
+            fat: Some(5.0),
+            fiber: Some(1.0),
+            protein: Some(6.0),
+            saturated_fat: Some(2.0),
+            sodium: Some(300.0),
+            sugar: Some(4.0),
+            ..Default::default()
+        };
+        assert_eq!(full.completeness(), 1.0);
+        assert!(full.is_complete());
+    }
+
+    #[test]
+    fn trigrams_pad_the_start_and_end() {
+        let expected: HashSet<String> = ["  e", " eg", "egg", "gg "].iter().map(|v| v.to_string()).collect();
+        assert_eq!(trigrams("egg"), expected);
+        assert_eq!(trigrams("a").len(), 2);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn similar_terms_finds_misspellings() {
+        let (_guard, redis) = test_redis::connection().await;
+
+        let recipe = Recipe {
+            link: "https://example.com/salsa".to_string(),
+            title: "Salsa".to_string(),
+            ingredients: vec!["4 tomatoes".to_string(), "1 tomato".to_string(), "1 onion".to_string()],
+            ..Default::default()
+        };
+        add(redis.clone(), recipe).await.unwrap();
+
+        let similar = similar_terms(redis.clone(), "tomatos").await.unwrap();
+        assert_eq!(similar.first().map(|v| v.0.as_str()), Some("tomato"));
+        assert!(similar.iter().all(|(_, similarity)| *similarity >= FUZZY_MIN_SIMILARITY && *similarity < 1.0));
+        assert!(!similar.iter().any(|(term, _)| term == "onion"));
+
+        assert!(similar_terms(redis.clone(), "zucchini").await.unwrap().is_empty());
+        assert_eq!(rank_recipes_by_terms(redis.clone(), &["tomatos".to_string()]).await, vec![1]);
+    }
+
+    fn pancakes() -> Recipe {
+        Recipe {
+            link: "https://example.com/pancakes".to_string(),
+            title: "Pancakes".to_string(),
+            ingredients: vec!["2 eggs".to_string(), "100g flour".to_string()],
+            instructions: vec!["Whisk and fry".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn concurrent_merges_keep_every_filled_field() {
+        let (_guard, redis) = test_redis::connection().await;
+
+        let partial = Recipe { ingredients: vec![], ..pancakes() };
+        assert!(add(redis.clone(), partial).await.unwrap());
+
+        // the same recipe found on two other pages, each with fields the other is missing
+        let with_ingredients = Recipe {
+            link: "https://example.com/print/pancakes".to_string(),
+            images: vec!["pancakes.jpg".to_string()],
+            calories: Some(200.0),
+            ..pancakes()
+        };
+        let with_authors = Recipe {
+            link: "https://example.com/amp/pancakes".to_string(),
+            ingredients: vec![],
+            authors: vec!["Alice".to_string()],
+            rating: Some(4.5),
+            ..pancakes()
+        };
+        let (first, second) = tokio::join!(
+            merge(redis.clone(), 1, &with_ingredients),
+            merge(redis.clone(), 1, &with_authors),
+        );
+        assert!(first.unwrap() && second.unwrap());
+
+        let merged = get_recipe(redis.clone(), 1).await.unwrap();
+        assert_eq!(merged.link, pancakes().link);
+        assert_eq!(merged.ingredients, pancakes().ingredients);
+        assert_eq!(merged.images, vec!["pancakes.jpg"]);
+        assert_eq!(merged.authors, vec!["Alice"]);
+        assert_eq!((merged.calories, merged.rating), (Some(200.0), Some(4.5)));
+        assert!(get_recipes_by_term(redis.clone(), "flour").await.contains(&1));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn get_recipe_cards_marks_missing_recipes() {
+        let (_guard, redis) = test_redis::connection().await;
+
+        let recipe = Recipe { images: vec!["a.jpg".to_string(), "b.jpg".to_string()], rating: Some(4.5), ..pancakes() };
+        add(redis.clone(), recipe).await.unwrap();
+
+        let cards = get_recipe_cards(redis.clone(), &[1, 2]).await.unwrap();
+        assert_eq!(cards.len(), 2);
+        let card = cards[0].as_ref().unwrap();
+        assert_eq!((card.id, card.title.as_str(), card.image.as_deref()), (1, "Pancakes", Some("a.jpg")));
+        assert_eq!((card.rating, card.total_time_seconds), (Some(4.5), None));
+        assert!(cards[1].is_none());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn reindex_removes_stale_legacy_entries() {
+        let (_guard, mut redis) = test_redis::connection().await;
+
+        assert!(add(redis.clone(), pancakes()).await.unwrap());
+        let id = 1;
+        let term = extract_terms(&pancakes()).remove(0);
+
+        // a recipe indexed before its terms and tags were recorded, under a term it no longer
+        // has, and a recipe that was deleted before then
+        let _: () = redis.del(&[key_recipe_terms(id), key_recipe_tags(id)]).await.unwrap();
+        let _: () = redis.sadd(key_term_recipes("waffle"), id).await.unwrap();
+        let _: () = redis.sadd(key_term_recipes(&term), 42).await.unwrap();
+        let _: () = redis.sadd(key_tag_recipes("vegan"), id).await.unwrap();
+
+        assert_eq!(reindex(redis.clone()).await.unwrap(), (1, 3));
+        assert!(get_recipes_by_term(redis.clone(), "waffle").await.is_empty());
+        assert_eq!(get_recipes_by_term(redis.clone(), &term).await, HashSet::from([id as usize]));
+        assert!(get_recipes_by_tags(redis.clone(), &["vegan"]).await.unwrap().is_empty());
+        assert_eq!(get_recipes_by_tags(redis.clone(), &["vegetarian"]).await.unwrap(), HashSet::from([id as usize]));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn suggest_terms_by_prefix_with_counts() {
+        let (_guard, mut redis) = test_redis::connection().await;
+
+        let recipes = [
+            ("Tomato soup", "https://example.com/soup"),
+            ("Tomato salad", "https://example.com/salad"),
+            ("Tofu stir fry", "https://example.com/tofu"),
+        ];
+        for (title, link) in recipes {
+            let recipe = Recipe { title: title.to_string(), link: link.to_string(), ..pancakes() };
+            add(redis.clone(), recipe).await.unwrap();
+        }
+        // a term left behind by a deleted recipe
+        let _: () = redis.zadd(key_terms(), "tomatillo", 0).await.unwrap();
+
+        assert_eq!(term_recipe_count(redis.clone(), "tomato").await.unwrap(), 2);
+        assert_eq!(term_recipe_count(redis.clone(), "tomatillo").await.unwrap(), 0);
+
+        let suggestions = suggest_terms(redis.clone(), " TO", 10).await.unwrap();
+        assert_eq!(suggestions, vec![("tofu".to_string(), 1), ("tomato".to_string(), 2)]);
+        assert_eq!(suggest_terms(redis.clone(), "to", 1).await.unwrap(), vec![("tofu".to_string(), 1)]);
+        assert!(suggest_terms(redis.clone(), "xyz", 10).await.unwrap().is_empty());
+
+        reindex(redis.clone()).await.unwrap();
+        let score: Option<f64> = redis.zscore(key_terms(), "tomatillo").await.unwrap();
+        assert!(score.is_none());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn delete_removes_recipe_and_unused_terms() {
+        let (_guard, mut redis) = test_redis::connection().await;
+
+        let waffles = Recipe { title: "Waffles".to_string(), link: "https://example.com/waffles".to_string(), ..pancakes() };
+        add(redis.clone(), pancakes()).await.unwrap();
+        add(redis.clone(), waffles).await.unwrap();
+        assert_eq!(recipe_count(redis.clone()).await.unwrap(), 2);
+
+        assert!(delete(redis.clone(), 1).await.unwrap());
+        assert!(!delete(redis.clone(), 1).await.unwrap());
+        assert_eq!(recipe_count(redis.clone()).await.unwrap(), 1);
+
+        // 'pancake' was only used by the deleted recipe, but 'flour' is still used by waffles
+        let pancake = extract_terms(&pancakes()).remove(0);
+        assert!(get_recipes_by_term(redis.clone(), &pancake).await.is_empty());
+        assert!(get_recipes_by_term(redis.clone(), "flour").await.contains(&2));
+        let pancake_score: Option<f64> = redis.zscore(key_terms(), &pancake).await.unwrap();
+        let flour_score: Option<f64> = redis.zscore(key_terms(), "flour").await.unwrap();
+        assert_eq!((pancake_score, flour_score), (None, Some(0.0)));
+        for trigram in trigrams(&pancake) {
+            let is_member: bool = redis.sismember(key_trigram_terms(&trigram), &pancake).await.unwrap();
+            assert!(!is_member);
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn list_recipes_pages_through_every_recipe() {
+        let (_guard, mut redis) = test_redis::connection().await;
+
+        for i in 0..5 {
+            let recipe = Recipe { title: format!("Pancakes {i}"), link: format!("https://example.com/{i}"), ..pancakes() };
+            add(redis.clone(), recipe).await.unwrap();
+        }
+        // enough ids that Redis can't return them all in a single page
+        let _: () = redis.sadd(key_recipes(), (6..=600).collect::<Vec<u64>>()).await.unwrap();
+
+        let mut ids = vec![];
+        let mut cursor = 0;
+        let mut pages = 0;
+        loop {
+            let (total, next_cursor, page) = list_recipes(redis.clone(), cursor, 100).await.unwrap();
+            assert_eq!(total, 600);
+            ids.extend(page);
+            pages += 1;
+            if next_cursor == 0 {
+                break;
+            }
+            cursor = next_cursor;
+        }
+
+        ids.sort();
+        assert_eq!(ids, (1..=600).collect::<Vec<u64>>());
+        assert!(pages > 1);
+    }
+
+    /// Adds a recipe for each title, with ids in the same order starting from 1
+    async fn add_titled(redis: MultiplexedConnection, titles: &[&str]) {
+        for (i, title) in titles.iter().enumerate() {
+            let recipe = Recipe { title: title.to_string(), link: format!("https://example.com/{i}"), ..Default::default() };
+            add(redis.clone(), recipe).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn rare_terms_rank_above_common_terms() {
+        let (_guard, redis) = test_redis::connection().await;
+        add_titled(redis.clone(), &["Sugar flour", "Sugar flour biscuits", "Sugar flour shortbread", "Gochujang flour"]).await;
+
+        let ranked = rank_recipes_by_terms(redis.clone(), &["sugar flour gochujang".to_string()]).await;
+        assert_eq!(ranked, vec![4, 1, 2, 3]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn recipes_matching_more_terms_rank_higher() {
+        let (_guard, redis) = test_redis::connection().await;
+        add_titled(redis.clone(), &["Tomato soup", "Basil pasta", "Garlic bread"]).await;
+
+        let terms = ["tomato basil pasta".to_string()];
+        assert_eq!(get_recipes_by_terms(redis.clone(), &terms, 0, 10).await, (2, vec![2, 1]));
+        assert_eq!(get_recipes_by_terms(redis.clone(), &terms, 1, 1).await, (2, vec![1]));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn get_recipe_fields_fetches_only_the_requested_fields() {
+        let (_guard, redis) = test_redis::connection().await;
+        add(redis.clone(), Recipe { rating: Some(4.5), ..pancakes() }).await.unwrap();
+
+        let fields = get_recipe_fields(redis.clone(), 1, &[RecipeField::Title, RecipeField::Rating]).await.unwrap();
+        assert_eq!(fields.keys().copied().collect::<HashSet<_>>(), HashSet::from([RecipeField::Title, RecipeField::Rating]));
+        assert_eq!(get_field_value::<String>(&fields, RecipeField::Title).unwrap(), "Pancakes");
+        assert_eq!(get_field_value::<Option<f32>>(&fields, RecipeField::Rating).unwrap(), Some(4.5));
+
+        assert!(get_recipe_fields(redis.clone(), 1, &[]).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn dedup_link_ignores_scheme_and_keeps_fragment() {
+        assert_eq!(dedup_link("http://example.com/pancakes/?utm_source=x"), dedup_link("https://example.com/pancakes"));
+        assert_eq!(dedup_link("https://example.com/roundup#recipe-2"), "example.com/roundup#recipe-2");
+        assert_ne!(dedup_link("https://example.com/roundup#recipe-1"), dedup_link("https://example.com/roundup#recipe-2"));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn recipes_with_the_same_canonical_link_are_deduplicated() {
+        let (_guard, redis) = test_redis::connection().await;
+
+        assert!(add(redis.clone(), pancakes()).await.unwrap());
+        let same_page = Recipe { link: "http://example.com/pancakes/?utm_source=x".to_string(), title: "Fluffy pancakes".to_string(), ..pancakes() };
+        assert!(!add(redis.clone(), same_page).await.unwrap());
+        assert_eq!(recipe_count(redis.clone()).await.unwrap(), 1);
+
+        // two recipes on one page, told apart by their fragments
+        let first = Recipe { link: "https://example.com/roundup#recipe-1".to_string(), title: "Crepes".to_string(), ..pancakes() };
+        let second = Recipe { link: "https://example.com/roundup#recipe-2".to_string(), title: "Waffles".to_string(), ..pancakes() };
+        assert!(add(redis.clone(), first).await.unwrap());
+        assert!(add(redis.clone(), second).await.unwrap());
+        assert_eq!(recipe_count(redis.clone()).await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn scan_recipes_returns_every_id_exactly_once() {
+        let (_guard, mut redis) = test_redis::connection().await;
+        // more than Redis keeps in a compact set, which would be returned in a single page
+        let _: () = redis.sadd(key_recipes(), (1..=600).collect::<Vec<u64>>()).await.unwrap();
+
+        let mut ids = vec![];
+        let mut cursor = 0;
+        let mut pages = 0;
+        loop {
+            let (next_cursor, page) = scan_recipes(redis.clone(), cursor, 50).await.unwrap();
+            ids.extend(page);
+            pages += 1;
+            if next_cursor == 0 {
+                break;
+            }
+            cursor = next_cursor;
+        }
+
+        ids.sort();
+        assert_eq!(ids, (1..=600).collect::<Vec<u64>>());
+        assert!(pages > 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn get_recipes_round_trips_every_field() {
+        let (_guard, redis) = test_redis::connection().await;
+        let recipe = Recipe {
+            description: "Light and fluffy".to_string(),
+            date: Some("2024-01-01".to_string()),
+            keywords: vec!["breakfast".to_string()],
+            authors: vec!["Alice".to_string()],
+            images: vec!["pancakes.jpg".to_string()],
+            rating: Some(4.5),
+            rating_count: Some(12),
+            prep_time_seconds: Some(300),
+            cook_time_seconds: Some(600),
+            total_time_seconds: Some(900),
+            servings: Some("4-6".to_string()),
+            servings_min: Some(4.0),
+            servings_max: Some(6.0),
+            calories: Some(200.0),
+            carbohydrates: Some(30.0),
+            cholesterol: Some(0.05),
+            fat: Some(8.0),
+            fiber: Some(1.5),
+            protein: Some(5.0),
+            saturated_fat: Some(2.0),
+            sodium: Some(0.3),
+            sugar: Some(6.0),
+            serving_size: Some("2 pancakes".to_string()),
+            ..pancakes()
+        };
+        add(redis.clone(), recipe.clone()).await.unwrap();
+
+        let recipes = get_recipes(redis.clone(), &[1]).await.unwrap();
+        assert_eq!(recipes.len(), 1);
+        assert_eq!(serde_json::to_value(&recipes[0]).unwrap(), serde_json::to_value(&recipe).unwrap());
+    }
+}
@@ -0,0 +1,225 @@
+use std::time::Duration;
+
+use reqwest::{Client, Method};
+use url::Url;
+
+use crate::download::{headers, read_limited, HeaderConfig};
+
+/// Google ignores anything after the first 500KiB, so there's no point reading more
+const MAX_ROBOTS_SIZE: usize = 512 * 1024;
+
+/// The rules from a robots.txt that apply to us. An empty set of rules allows everything,
+/// which is also what we use when a site has no robots.txt.
+#[derive(Debug, Clone, Default)]
+pub struct RobotsRules {
+    allow: Vec<String>,
+    disallow: Vec<String>,
+    pub crawl_delay: Option<Duration>,
+}
+
+#[derive(Debug, Default)]
+struct Group {
+    user_agents: Vec<String>,
+    allow: Vec<String>,
+    disallow: Vec<String>,
+    crawl_delay: Option<Duration>,
+}
+
+/// Whether a robots.txt pattern matches a path. Patterns are prefixes, except that '*'
+/// matches any sequence of characters and a trailing '$' anchors the end of the path.
+fn matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut remaining) = path.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    for (i, part) in parts.iter().enumerate() {
+        // the last part of an anchored pattern must match the end of the path
+        if anchored && i == parts.len() - 1 {
+            return remaining.ends_with(part);
+        }
+        match remaining.find(part) {
+            Some(index) => remaining = &remaining[index + part.len()..],
+            None => return false,
+        }
+    }
+
+    !anchored || remaining.is_empty()
+}
+
+/// The product token of a user agent, lowercased, which is what robots.txt groups are matched
+/// on, eg 'examplebot' for 'ExampleBot/1.0 (+https://example.com/bot)'
+fn product_token(user_agent: &str) -> String {
+    user_agent.trim()
+        .split(|c: char| !(c.is_ascii_alphabetic() || c == '_' || c == '-'))
+        .next()
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+impl RobotsRules {
+    /// Parses a robots.txt, keeping the group that names our user agent if there is one,
+    /// or the '*' group otherwise
+    pub fn parse(contents: &str, user_agent: &str) -> Self {
+        let token = product_token(user_agent);
+        let mut groups: Vec<Group> = vec![];
+        let mut in_user_agents = false;
+
+        for line in contents.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let key = key.trim().to_lowercase();
+            let value = value.trim();
+
+            if key == "user-agent" {
+                // consecutive user-agent lines share the same group
+                if !in_user_agents {
+                    groups.push(Group::default());
+                }
+                in_user_agents = true;
+                groups.last_mut().unwrap().user_agents.push(value.to_lowercase());
+                continue;
+            }
+
+            in_user_agents = false;
+            let Some(group) = groups.last_mut() else {
+                continue;
+            };
+
+            match key.as_str() {
+                // an empty disallow means everything is allowed
+                "allow" if !value.is_empty() => group.allow.push(value.to_owned()),
+                "disallow" if !value.is_empty() => group.disallow.push(value.to_owned()),
+                "crawl-delay" => group.crawl_delay = value.parse::<f64>()
+                    .ok()
+                    .filter(|v| v.is_finite() && *v >= 0.0)
+                    .map(Duration::from_secs_f64),
+                _ => (),
+            }
+        }
+
+        let specific = groups.iter()
+            .position(|group| group.user_agents.iter().any(|v| !token.is_empty() && product_token(v) == token));
+        let wildcard = groups.iter()
+            .position(|group| group.user_agents.iter().any(|v| v == "*"));
+
+        match specific.or(wildcard) {
+            Some(index) => {
+                let group = groups.swap_remove(index);
+                RobotsRules { allow: group.allow, disallow: group.disallow, crawl_delay: group.crawl_delay }
+            }
+            None => RobotsRules::default(),
+        }
+    }
+
+    /// The most specific (longest) matching rule wins, with allow winning ties
+    pub fn is_allowed(&self, path: &str) -> bool {
+        let longest = |patterns: &[String]| patterns.iter()
+            .filter(|pattern| matches(pattern, path))
+            .map(|pattern| pattern.len())
+            .max();
+
+        match (longest(&self.allow), longest(&self.disallow)) {
+            (_, None) => true,
+            (None, Some(_)) => false,
+            (Some(allow), Some(disallow)) => allow >= disallow,
+        }
+    }
+
+    /// Whether a link's path and query (eg /recipes/pancakes?page=2) are allowed
+    pub fn is_link_allowed(&self, url: &Url) -> bool {
+        match url.query() {
+            Some(query) => self.is_allowed(&format!("{}?{}", url.path(), query)),
+            None => self.is_allowed(url.path()),
+        }
+    }
+}
+
+/// Fetches the robots.txt for a link's site. A missing or unreachable robots.txt allows
+/// everything.
+#[tracing::instrument(skip(client, header_config))]
+pub async fn fetch(client: &Client, header_config: &HeaderConfig, url: &Url) -> RobotsRules {
+    let Ok(robots_url) = url.join("/robots.txt") else {
+        return RobotsRules::default();
+    };
+
+    let response = client.request(Method::GET, robots_url)
+        .headers(headers(header_config))
+        .send()
+        .await;
+
+    let contents = match response {
+        Ok(response) if response.status().is_success() => read_limited(response, MAX_ROBOTS_SIZE).await.ok(),
+        _ => None,
+    };
+
+    contents.map(|contents| RobotsRules::parse(&String::from_utf8_lossy(&contents), &header_config.user_agent()))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROBOTS: &str = "
+        User-agent: *
+        Disallow: /private/
+        Allow: /private/recipes/
+        Disallow: /*.pdf$
+        Crawl-delay: 2
+
+        # bot is a substring of our product token, so shouldn't match
+        User-agent: bot
+        Disallow: /
+
+        User-agent: OtherBot
+        User-agent: ExampleBot/2.0
+        Disallow: /search
+        Crawl-delay: 0.5
+    ";
+
+    #[test]
+    fn parse_matches_product_token() {
+        let rules = RobotsRules::parse(ROBOTS, "ExampleBot/1.0 (+https://example.com/bot)");
+        assert_eq!(rules.crawl_delay, Some(Duration::from_millis(500)));
+        assert!(!rules.is_allowed("/search?q=soup"));
+        assert!(rules.is_allowed("/private/"));
+
+        let rules = RobotsRules::parse(ROBOTS, "Prototype recipe search engine indexer");
+        assert_eq!(rules.crawl_delay, Some(Duration::from_secs(2)));
+        assert!(rules.is_allowed("/search"));
+    }
+
+    #[test]
+    fn parse_without_matching_group_allows_everything() {
+        let rules = RobotsRules::parse("User-agent: OtherBot\nDisallow: /", "ExampleBot");
+        assert!(rules.is_allowed("/"));
+        assert!(rules.crawl_delay.is_none());
+    }
+
+    #[test]
+    fn is_allowed_prefers_longest_match() {
+        let rules = RobotsRules::parse(ROBOTS, "ExampleBot-Old");
+        assert!(!rules.is_allowed("/private/notes"));
+        assert!(rules.is_allowed("/private/recipes/pancakes"));
+        assert!(!rules.is_allowed("/files/pancakes.pdf"));
+        assert!(rules.is_allowed("/files/pancakes.pdf?download=1"));
+        assert!(rules.is_allowed("/recipes/pancakes"));
+    }
+
+    #[test]
+    fn is_link_allowed_includes_query() {
+        let rules = RobotsRules::parse("User-agent: *\nDisallow: /*?print=", "ExampleBot");
+        assert!(rules.is_link_allowed(&Url::parse("https://example.com/pancakes").unwrap()));
+        assert!(!rules.is_link_allowed(&Url::parse("https://example.com/pancakes?print=1").unwrap()));
+    }
+}
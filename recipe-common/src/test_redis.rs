@@ -0,0 +1,27 @@
+use std::sync::LazyLock;
+
+use redis::aio::MultiplexedConnection;
+use tokio::sync::{Mutex, MutexGuard};
+
+/// Tests that need Redis share one database, so only one can use it at a time
+static LOCK: LazyLock<Mutex<()>> = LazyLock::new(|| Mutex::new(()));
+
+/// A connection to an empty database given by REDIS_TEST_URL (eg redis://127.0.0.1/15), which
+/// is flushed first, so must not be used for anything else
+/// Tests using this should be marked #[ignore] so they are reported as ignored rather than
+/// passing when there is no database, and run with `cargo test -- --include-ignored`
+/// Panics if REDIS_TEST_URL isn't set
+pub async fn connection() -> (MutexGuard<'static, ()>, MultiplexedConnection) {
+    let url = std::env::var("REDIS_TEST_URL")
+        .expect("REDIS_TEST_URL must be set to run tests that need Redis");
+
+    let guard = LOCK.lock().await;
+    let mut redis = redis::Client::open(url)
+        .unwrap()
+        .get_multiplexed_tokio_connection()
+        .await
+        .unwrap();
+    let _: () = redis::cmd("FLUSHDB").query_async(&mut redis).await.unwrap();
+
+    (guard, redis)
+}
@@ -0,0 +1,83 @@
+use std::sync::{Arc, Mutex};
+
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener};
+
+/// A local HTTP server for tests, which runs until the test ends
+pub struct TestServer {
+    /// eg http://127.0.0.1:1234/
+    pub url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl TestServer {
+    /// The head (request line and headers) of every request received so far, in order
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+/// A raw HTTP response with a Content-Length, which closes the connection afterwards so every
+/// request is made on a new one
+pub fn response(status: &str, headers: &[(&str, &str)], body: &[u8]) -> Vec<u8> {
+    let mut response = format!("HTTP/1.1 {status}\r\n");
+    for (name, value) in headers {
+        response += &format!("{name}: {value}\r\n");
+    }
+    response += &format!("Content-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+
+    let mut response = response.into_bytes();
+    response.extend_from_slice(body);
+    response
+}
+
+/// Answers each request with `respond(path)`, where `respond` returns a raw HTTP response (see
+/// `response`), or None to never respond
+pub async fn serve<F>(respond: F) -> TestServer
+where
+    F: Fn(&str) -> Option<Vec<u8>> + Send + Sync + 'static
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let requests = Arc::new(Mutex::new(vec![]));
+    let respond = Arc::new(respond);
+
+    let server_requests = requests.clone();
+    tokio::spawn(async move {
+        loop {
+            let Ok((mut stream, _)) = listener.accept().await else {
+                return;
+            };
+            let requests = server_requests.clone();
+            let respond = respond.clone();
+            tokio::spawn(async move {
+                let mut head = vec![];
+                let mut buffer = [0; 4096];
+                while !head.windows(4).any(|v| v == b"\r\n\r\n") {
+                    match stream.read(&mut buffer).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => head.extend_from_slice(&buffer[..n]),
+                    }
+                }
+
+                let head = String::from_utf8_lossy(&head).into_owned();
+                let path = head.split_whitespace().nth(1).unwrap_or_default().to_owned();
+                requests.lock().unwrap().push(head);
+
+                match respond(&path) {
+                    Some(response) => {
+                        let _ = stream.write_all(&response).await;
+                    }
+                    // hold the connection open without answering until the test ends
+                    None => std::future::pending().await,
+                }
+            });
+        }
+    });
+
+    TestServer { url, requests }
+}
+
+/// Answers every request with the same response
+pub async fn serve_response(response: Vec<u8>) -> TestServer {
+    serve(move |_| Some(response.clone())).await
+}
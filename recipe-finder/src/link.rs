@@ -1,31 +1,114 @@
-use std::{sync::Arc, time::Duration};
+use std::{error::Error as _, sync::Arc, time::Duration};
 
 use anyhow::Error;
+use follower::LinkRewrite;
 use log::{debug, info, trace, warn};
-use recipe_common::{link::{self, LinkMissingDomainError, LinkStatus}, recipe::{self, Recipe}};
+use recipe_common::{address::AddressGuard, download::{ContentTooLargeError, HeaderConfig}, extractor, link::{self, LinkError, LinkStatus}, metrics::{self, Counter}, parser, recipe::{self, Recipe}};
 use redis::aio::MultiplexedConnection;
 use reqwest::{Certificate, Client, ClientBuilder, Proxy};
 use serde_json::Value;
-use tokio::{sync::Semaphore, time::interval};
+use sha2::{Digest, Sha256};
+use tokio::{sync::{OwnedSemaphorePermit, Semaphore}, time::{interval, timeout}};
 
 pub mod downloader;
-pub mod extractor;
 pub mod follower;
-pub mod parser;
 
-#[tracing::instrument(skip(redis_links, client))]
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Only follow links on the same site as the page they were found on
+    pub same_domain_only: bool,
+    /// Pages larger than this (in bytes) are not searched for links to follow
+    pub max_follow_content_size: usize,
+    /// Downloads larger than this (in bytes) are aborted and fail
+    pub max_content_size: usize,
+    /// Recipes are rejected unless at least one instruction has this many characters
+    pub min_instruction_chars: Option<usize>,
+    /// Recipes are rejected unless their instructions have this many characters in total
+    pub min_total_instruction_chars: Option<usize>,
+    /// Recipes with at least this fraction of their optional fields present (see
+    /// `Recipe::completeness`) count as complete, so their links are followed further and with a
+    /// higher priority
+    pub min_completeness: f32,
+    /// Links on pages this many follows away from a submitted link are never followed, however
+    /// complete the recipes along the way are
+    pub max_depth: u32,
+    /// Minimum time between requests to the same domain (a random extra delay is added on top,
+    /// and it is lengthened for domains that start failing or rate limiting us)
+    pub request_interval: Duration,
+    /// Maximum number of links processed at once
+    pub max_concurrent_tasks: usize,
+    /// Maximum number of requests in flight at once across all domains
+    pub max_concurrent_downloads: Option<usize>,
+    /// User agent, language and contact details sent with every request
+    pub headers: HeaderConfig,
+    /// Refuses links that resolve to local or private addresses
+    pub address_guard: AddressGuard,
+    /// Maximum time to spend processing a single link, across all stages
+    pub process_timeout: Duration,
+    /// How many times to retry a download after a 429/503 response before giving up. Retries
+    /// also stop once waiting for them would take more than half of `process_timeout`
+    pub max_retries: usize,
+    /// Maximum time a single request can take before it fails
+    pub download_timeout: Duration,
+    /// Maximum number of links a domain can have waiting, so a single site can't crowd out
+    /// everything else
+    pub max_domain_waiting_links: Option<usize>,
+    /// How many downloads from a single domain can happen at once. Each one still waits for
+    /// the request interval before freeing its slot
+    pub domain_download_slots: usize,
+    /// How long to wait for in-flight links to finish processing when shutting down
+    pub shutdown_timeout: Duration,
+    /// Poll domains with higher priority waiting links more often, rather than uniformly at random
+    pub prioritize_domains: bool,
+    /// Applied in order to every followed link, eg to turn print pages into the original page
+    pub link_rewrites: Vec<LinkRewrite>,
+}
+
+/// Metrics are only for reporting, so failing to record one shouldn't fail the link
+async fn increment_metric(redis_links: MultiplexedConnection, counter: Counter) {
+    if let Err(err) = metrics::increment(redis_links, counter).await {
+        warn!("Error incrementing {} metric: {} (source: {:?})", counter.as_str(), err, err.source());
+    }
+}
+
+#[derive(Debug)]
+pub struct Downloaded {
+    contents: String,
+    /// Only stored once recipes have been parsed from the contents, so a page that failed to
+    /// parse is retried in full next time even if it hasn't changed
+    content_hash: String,
+}
+
+/// Returns None if the content is identical to when the link was last successfully parsed, in
+/// which case there is nothing new to extract and the link is marked as processed
+#[tracing::instrument(skip(redis_links, config, client))]
 pub async fn process_download(
     redis_links: MultiplexedConnection, 
+    config: &Config,
     client: Client, 
     link: String
-) -> Result<String, Error> {
-    match downloader::download(redis_links.clone(), client, link.clone()).await {
+) -> Result<Option<Downloaded>, Error> {
+    let downloaded = match downloader::download(redis_links.clone(), config, client, link.clone()).await {
         Err(err) => {
             link::update_status(redis_links.clone(), &link, LinkStatus::DownloadFailed).await?;
+            if let Some(err) = err.downcast_ref::<ContentTooLargeError>() {
+                link::set_content_size(redis_links.clone(), &link, err.size).await?;
+            }
             return Err(err)
         },
-        Ok(downloaded) => Ok(downloaded),
+        Ok(downloaded) => downloaded,
+    };
+
+    increment_metric(redis_links.clone(), Counter::Downloads).await;
+
+    let content_hash = format!("{:x}", Sha256::digest(downloaded.as_bytes()));
+    let previous_content_hash = link::get_content_hash(redis_links.clone(), &link).await?;
+    if previous_content_hash.as_ref() == Some(&content_hash) {
+        link::update_status(redis_links.clone(), &link, LinkStatus::Processed).await?;
+        return Ok(None);
     }
+
+    Ok(Some(Downloaded { contents: downloaded, content_hash }))
 }
 
 #[tracing::instrument(skip(redis_links, contents))]
@@ -33,8 +116,8 @@ pub async fn process_extract(
     redis_links: MultiplexedConnection, 
     contents: String,
     link: String
-) -> Result<Option<Value>, Error> {
-    let extracted = extractor::extract(&link, &contents).await;
+) -> Result<Vec<Value>, Error> {
+    let extracted = extractor::extract_all(&link, &contents).await;
 
     if let Err(err) = extracted {
         link::update_status(redis_links.clone(), &link, LinkStatus::ExtractionFailed).await?;
@@ -44,49 +127,91 @@ pub async fn process_extract(
 
     let extracted = extracted.unwrap();
 
-    if extracted.is_none() {
+    if extracted.is_empty() {
         link::update_status(redis_links.clone(), &link, LinkStatus::ExtractionFailed).await?;
         link::set_content_size(redis_links.clone(), &link, contents.len()).await?;
-        return Ok(None);
+        return Ok(vec![]);
     }
 
+    increment_metric(redis_links.clone(), Counter::Extractions).await;
+
     Ok(extracted)
 }
 
-#[tracing::instrument(skip(redis_links, redis_recipes, schema))]
+/// Parses every recipe found on the page, storing each as a separate recipe
+/// Returns the first recipe that was parsed successfully
+#[tracing::instrument(skip(redis_links, redis_recipes, config, schemas))]
 pub async fn process_parse(
     redis_links: MultiplexedConnection, 
     redis_recipes: MultiplexedConnection, 
-    schema: Value,
+    config: &Config,
+    schemas: Vec<Value>,
+    content_hash: String,
     link: String
 ) -> Result<Option<Recipe>, Error> {
+    let mut parsed_recipes = vec![];
+    for (i, schema) in schemas.into_iter().enumerate() {
+        let recipe_link = parser::recipe_link(&link, i);
+
+        let Some(parsed) = parser::parse(recipe_link.clone(), schema).await else {
+            trace!("Failed to parse recipe from {recipe_link}");
+            continue;
+        };
+
+        if !parsed.has_plausible_instructions(config.min_instruction_chars, config.min_total_instruction_chars) {
+            trace!("Rejected recipe from {recipe_link} because its instructions are too short");
+            continue;
+        }
 
-    let parsed = parser::parse(link.to_string(), schema).await;
+        parsed_recipes.push(parsed);
+    }
 
-    let Some(parsed) = parsed else {
-        trace!("Failed to parse recipe from {link}");
+    if parsed_recipes.is_empty() {
         link::update_status(redis_links.clone(), &link, LinkStatus::ParsingFailed).await?;
         return Ok(None);
-    };
+    }
 
+    // the content hash is only stored once every recipe has been added, otherwise a page whose
+    // recipes failed to be stored would be skipped as unchanged when it is retried
+    for parsed in &parsed_recipes {
+        if let Err(err) = recipe::add(redis_recipes.clone(), parsed.clone()).await {
+            link::update_status(redis_links.clone(), &link, LinkStatus::ParsingFailed).await?;
+            return Err(err);
+        }
+    }
 
     link::update_status(redis_links.clone(), &link, LinkStatus::Processed).await?;
-    recipe::add(redis_recipes, parsed.clone()).await?;
+    link::set_content_hash(redis_links.clone(), &link, &content_hash).await?;
+    increment_metric(redis_links.clone(), Counter::Parses).await;
 
-    trace!("Parsed recipe from {}", link);
+    trace!("Parsed {} recipes from {}", parsed_recipes.len(), link);
 
-    Ok(Some(parsed))
+    Ok(parsed_recipes.into_iter().next())
 }
 
-#[tracing::instrument(skip(redis_links, contents, recipe))]
+#[tracing::instrument(skip(redis_links, config, contents, recipe))]
 pub async fn process_follow(
     redis_links: MultiplexedConnection, 
+    config: &Config,
     contents: String,
     recipe: Option<Recipe>,
     link: String
 ) -> Result<(), Error> {
     let recipe_exists = recipe.as_ref().is_some_and(|recipe| !recipe.ingredients.is_empty());
-    let recipe_is_complete = recipe.as_ref().is_some_and(|recipe| recipe.is_complete());
+    let recipe_is_complete = recipe.as_ref().is_some_and(|recipe| recipe.completeness() >= config.min_completeness);
+
+    // Submitted as download only
+    if link::is_no_follow(redis_links.clone(), &link).await? {
+        trace!("Skipped follow for no-follow link {link}");
+        return Ok(())
+    }
+
+    // Depth is a hard limit, unlike remaining follows, which are topped up by pages with recipes
+    let depth = link::get_depth(redis_links.clone(), &link).await?;
+    if depth >= config.max_depth {
+        trace!("Terminated follow for {link} at depth {depth}");
+        return Ok(())
+    }
 
     // Remaining follows
     let remaining_follows = link::get_remaining_follows(redis_links.clone(), &link).await?;
@@ -104,8 +229,14 @@ pub async fn process_follow(
         -2.0
     };
 
+    // Parsing huge pages for links can take a very long time
+    if contents.len() > config.max_follow_content_size {
+        warn!("Skipped following {link} because its content is too large ({} bytes)", contents.len());
+        return Ok(())
+    }
+
     // Following logic finally
-    let new_links = follower::follow(contents, link.to_string()).await;
+    let new_links = follower::follow(contents, link.to_string(), config.same_domain_only, &config.link_rewrites).await;
 
     let mut added_links = vec![];
     for new_link in &new_links {
@@ -114,13 +245,11 @@ pub async fn process_follow(
         } else {
             remaining_follows - 1
         };
-        let added = match link::add(redis_links.clone(), new_link, Some(&link), new_priority, new_remaining_follows).await {
+        let added = match link::add(redis_links.clone(), new_link, Some(&link), new_priority, new_remaining_follows, true, config.max_domain_waiting_links).await {
             Ok(ok) => ok,
             // don't return if the link is missing a domain
-            Err(err) => match err.downcast_ref::<LinkMissingDomainError>() {
-                Some(_) => false,
-                None => return Err(err),
-            }
+            Err(LinkError::MissingDomain { .. }) => false,
+            Err(err) => return Err(err.into()),
         };
         if added {
             added_links.push(new_link) 
@@ -132,23 +261,24 @@ pub async fn process_follow(
     Ok(())
 }
 
-#[tracing::instrument(skip(redis_links, redis_recipes, client, semaphore))]
-pub async fn process(
+#[tracing::instrument(skip(redis_links, redis_recipes, config, client))]
+async fn process_stages(
     redis_links: MultiplexedConnection, 
     redis_recipes: MultiplexedConnection, 
+    config: Arc<Config>,
     client: Client, 
-    semaphore: Arc<Semaphore>, 
     link: String
 ) {
-    let _permit = semaphore.acquire().await.unwrap();
-
     // Download
-    let downloaded = process_download(redis_links.clone(), client, link.clone()).await;
+    let downloaded = process_download(redis_links.clone(), &config, client, link.clone()).await;
     if let Err(err) = downloaded {
         debug!("Error downloading {}: {} (source: {:?})", &link, err, err.source());
         return;
     }
-    let downloaded = downloaded.unwrap();
+    let Some(Downloaded { contents: downloaded, content_hash }) = downloaded.unwrap() else {
+        trace!("Skipped {link} because its content is unchanged since it was last parsed");
+        return;
+    };
 
     // Extract
     let extracted = process_extract(redis_links.clone(), downloaded.clone(), link.clone()).await;
@@ -159,36 +289,63 @@ pub async fn process(
     let extracted = extracted.unwrap();
 
     // Parse
-    // (can't use map due to async closures being unstable)
-    let parsed = match extracted {
-        Some(extracted) => {
-            let parsed = process_parse(redis_links.clone(), redis_recipes, extracted, link.clone()).await;
-            if let Err(err) = parsed  {
-                warn!("Error parsing {}: {} (source: {:?})", &link, err, err.source());
-                return;
-            }
-            parsed.unwrap()
+    let parsed = if extracted.is_empty() {
+        None
+    } else {
+        let parsed = process_parse(redis_links.clone(), redis_recipes, &config, extracted, content_hash, link.clone()).await;
+        if let Err(err) = parsed  {
+            warn!("Error parsing {}: {} (source: {:?})", &link, err, err.source());
+            return;
         }
-        None => None
+        parsed.unwrap()
     };
 
     // Follow
-    let followed = process_follow(redis_links.clone(), downloaded, parsed, link.clone()).await;
+    let followed = process_follow(redis_links.clone(), &config, downloaded, parsed, link.clone()).await;
     if let Err(err) = followed  {
         warn!("Error following {}: {} (source: {:?})", &link, err, err.source());
         return;
     }
 }
 
+#[tracing::instrument(skip(redis_links, redis_recipes, config, client, _permit))]
+pub async fn process(
+    redis_links: MultiplexedConnection, 
+    redis_recipes: MultiplexedConnection, 
+    config: Arc<Config>,
+    client: Client, 
+    _permit: OwnedSemaphorePermit, 
+    link: String
+) {
+    let stages = process_stages(redis_links.clone(), redis_recipes, config.clone(), client, link.clone());
+    if timeout(config.process_timeout, stages).await.is_ok() {
+        return;
+    }
+
+    warn!("Timed out processing {link}");
+
+    // The link may have already been given a final status by a stage that completed
+    let status = link::get_status(redis_links.clone(), &link).await;
+    if let Ok(LinkStatus::Processing) = status
+        && let Err(err) = link::update_status(redis_links.clone(), &link, LinkStatus::DownloadFailed).await
+    {
+        warn!("Error updating status of timed out link {}: {} (source: {:?})", &link, err, err.source());
+    }
+}
+
+/// Processes links until `shutdown` resolves, then waits for in-flight links to finish (up to
+/// the shutdown timeout) and puts any that didn't back in the queue
 pub async fn run(
     redis_links: MultiplexedConnection, 
     redis_recipes: MultiplexedConnection, 
+    config: Config,
     proxy: String, 
-    certificates: Vec<Certificate>
+    certificates: Vec<Certificate>,
+    shutdown: impl Future<Output = ()>,
 ) {
     info!("Started processor");
 
-    let mut builder = ClientBuilder::new()
+    let mut builder = client_builder(&config)
         .proxy(Proxy::https(proxy).unwrap());
     
     for certificate in certificates {
@@ -196,25 +353,378 @@ pub async fn run(
     }
     
     let client = builder.build().unwrap();
-    let semaphore = Arc::new(Semaphore::new(4096));
+    let config = Arc::new(config);
+    let semaphore = Arc::new(Semaphore::new(config.max_concurrent_tasks));
     let mut interval = interval(Duration::from_millis(500));
 
+    tokio::pin!(shutdown);
+
     loop {
-        interval.tick().await;
+        tokio::select! {
+            _ = &mut shutdown => break,
+            _ = interval.tick() => (),
+        }
         
         if semaphore.available_permits() == 0 {
             continue;
         }
 
-        let links_result = link::poll_next_jobs(redis_links.clone(), semaphore.available_permits()).await;
+        let links_result = link::poll_next_jobs(redis_links.clone(), semaphore.available_permits(), config.prioritize_domains).await;
         if let Err(err) = links_result {
             warn!("Error while getting next job: {} (source: {:?})", err, err.source());
             continue;
         }
 
         for link in links_result.unwrap() {
-            tokio::spawn(process(redis_links.clone(), redis_recipes.clone(), client.clone(), semaphore.clone(), link));
+            // permits are acquired before spawning so that shutdown can wait for them
+            let permit = semaphore.clone().acquire_owned().await.unwrap();
+            tokio::spawn(process(redis_links.clone(), redis_recipes.clone(), config.clone(), client.clone(), permit, link));
         }
     }
+
+    let in_flight = config.max_concurrent_tasks - semaphore.available_permits();
+    info!("Shutting down, waiting for {in_flight} links to finish processing");
+
+    if timeout(config.shutdown_timeout, semaphore.acquire_many(config.max_concurrent_tasks as u32)).await.is_err() {
+        warn!("Timed out waiting for links to finish processing");
+    }
+
+    if let Err(err) = link::reset_tasks(redis_links.clone()).await {
+        warn!("Error while resetting unfinished links: {} (source: {:?})", err, err.source());
+    }
+
+    info!("Stopped processor");
 }
 
+/// The client settings apart from the proxy and its certificates
+/// Decompression must stay enabled for every encoding `download::headers` advertises in
+/// Accept-Encoding, as reqwest only decodes the ones it is built with
+fn client_builder(config: &Config) -> ClientBuilder {
+    ClientBuilder::new()
+        .gzip(true)
+        .brotli(true)
+        .deflate(true)
+        .timeout(config.download_timeout)
+        .dns_resolver(Arc::new(config.address_guard.clone()))
+        .redirect(config.address_guard.redirect_policy(Some("https")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use recipe_common::{download::DEFAULT_MAX_CONTENT_SIZE, test_redis, test_server::{self, response, TestServer}};
+
+    use super::*;
+
+    pub(super) fn config() -> Config {
+        Config {
+            same_domain_only: false,
+            max_follow_content_size: DEFAULT_MAX_CONTENT_SIZE,
+            max_content_size: DEFAULT_MAX_CONTENT_SIZE,
+            min_instruction_chars: None,
+            min_total_instruction_chars: None,
+            min_completeness: 1.0,
+            max_depth: 10,
+            request_interval: Duration::ZERO,
+            max_concurrent_tasks: 1,
+            max_concurrent_downloads: None,
+            headers: HeaderConfig::default(),
+            address_guard: AddressGuard::default().with_exempt_host("localhost"),
+            process_timeout: Duration::from_secs(300),
+            max_retries: 0,
+            download_timeout: Duration::from_secs(30),
+            max_domain_waiting_links: None,
+            domain_download_slots: 1,
+            shutdown_timeout: Duration::from_secs(30),
+            prioritize_domains: false,
+            link_rewrites: LinkRewrite::defaults(),
+        }
+    }
+
+    const PAGE: &str = r#"<html><body><a href="https://example.com/child">Child</a></body></html>"#;
+
+    const RECIPE_PAGE: &str = r#"<html><head><script type="application/ld+json">{
+        "@context": "https://schema.org",
+        "@type": "Recipe",
+        "name": "Pancakes",
+        "description": "Fluffy pancakes",
+        "recipeIngredient": ["2 eggs", "100g flour", "300ml milk"],
+        "recipeInstructions": [{"@type": "HowToStep", "text": "Whisk everything together, then fry in a hot pan"}]
+    }</script></head><body><a href="/waffles">Waffles</a></body></html>"#;
+
+    const TWO_RECIPE_PAGE: &str = r#"<html><head><script type="application/ld+json">{
+        "@context": "https://schema.org",
+        "@graph": [
+            {
+                "@type": "Recipe",
+                "name": "Pancakes",
+                "description": "Fluffy pancakes",
+                "recipeIngredient": ["2 eggs", "100g flour", "300ml milk"],
+                "recipeInstructions": [{"@type": "HowToStep", "text": "Whisk everything together, then fry in a hot pan"}]
+            },
+            {
+                "@type": "Recipe",
+                "name": "Vegan pancakes",
+                "description": "Fluffy pancakes without eggs or milk",
+                "recipeIngredient": ["1 banana", "100g flour", "300ml oat milk"],
+                "recipeInstructions": [{"@type": "HowToStep", "text": "Mash the banana, whisk in everything else, then fry in a hot pan"}]
+            }
+        ]
+    }</script></head><body></body></html>"#;
+
+    fn client() -> Client {
+        Client::builder().no_proxy().build().unwrap()
+    }
+
+    /// Links can't be added with an IP address, so the test server is requested through localhost
+    fn local_link(server: &TestServer, path: &str) -> String {
+        format!("{}{path}", server.url.replace("127.0.0.1", "localhost"))
+    }
+
+    /// Serves `page` at every path apart from robots.txt, which doesn't exist
+    async fn serve_page(page: &'static str) -> TestServer {
+        test_server::serve(move |path| Some(match path {
+            "/robots.txt" => response("404 Not Found", &[], b""),
+            _ => response("200 OK", &[("Content-Type", "text/html")], page.as_bytes()),
+        })).await
+    }
+
+    async fn parse_downloaded(redis: MultiplexedConnection, link: &str) -> Result<Option<Recipe>, Error> {
+        let downloaded = process_download(redis.clone(), &config(), client(), link.to_string()).await.unwrap().unwrap();
+        let schemas = process_extract(redis.clone(), downloaded.contents, link.to_string()).await.unwrap();
+        process_parse(redis.clone(), redis.clone(), &config(), schemas, downloaded.content_hash, link.to_string()).await
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn unchanged_content_is_not_parsed_again() {
+        let (_guard, redis) = test_redis::connection().await;
+        let server = serve_page(RECIPE_PAGE).await;
+        let link = local_link(&server, "pancakes");
+        link::add(redis.clone(), &link, None, 0.0, 5, true, None).await.unwrap();
+
+        assert!(parse_downloaded(redis.clone(), &link).await.unwrap().is_some());
+        assert!(link::get_content_hash(redis.clone(), &link).await.unwrap().is_some());
+
+        assert!(process_download(redis.clone(), &config(), client(), link.clone()).await.unwrap().is_none());
+        assert_eq!(link::get_status(redis.clone(), &link).await.unwrap(), LinkStatus::Processed);
+
+        // as if the page has changed since it was parsed
+        link::set_content_hash(redis.clone(), &link, "outdated").await.unwrap();
+        assert!(process_download(redis.clone(), &config(), client(), link.clone()).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn every_recipe_on_a_page_is_stored_separately() {
+        let (_guard, redis) = test_redis::connection().await;
+        let server = serve_page(TWO_RECIPE_PAGE).await;
+        let link = local_link(&server, "pancakes");
+        link::add(redis.clone(), &link, None, 0.0, 5, true, None).await.unwrap();
+
+        let first = parse_downloaded(redis.clone(), &link).await.unwrap().unwrap();
+        assert_eq!(first.title, "Pancakes");
+
+        let (total, _, ids) = recipe::list_recipes(redis.clone(), 0, 100).await.unwrap();
+        assert_eq!(total, 2);
+        let mut recipes: Vec<(String, String)> = recipe::get_recipes(redis.clone(), &ids).await.unwrap()
+            .into_iter()
+            .map(|v| (v.link, v.title))
+            .collect();
+        recipes.sort();
+        assert_eq!(recipes, vec![
+            (link.clone(), "Pancakes".to_string()),
+            (format!("{link}#recipe-2"), "Vegan pancakes".to_string()),
+        ]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn page_goes_through_every_stage() {
+        let (_guard, redis) = test_redis::connection().await;
+        let server = serve_page(RECIPE_PAGE).await;
+        let link = local_link(&server, "pancakes");
+        link::add(redis.clone(), &link, None, 0.0, 5, true, None).await.unwrap();
+        link::update_status(redis.clone(), &link, LinkStatus::Processing).await.unwrap();
+        let permit = Arc::new(Semaphore::new(1)).acquire_owned().await.unwrap();
+
+        process(redis.clone(), redis.clone(), Arc::new(config()), client(), permit, link.clone()).await;
+
+        // download, extract and parse
+        assert_eq!(link::get_status(redis.clone(), &link).await.unwrap(), LinkStatus::Processed);
+        assert!(link::get_content_hash(redis.clone(), &link).await.unwrap().is_some());
+        let (total, _, ids) = recipe::list_recipes(redis.clone(), 0, 100).await.unwrap();
+        assert_eq!(total, 1);
+        let recipe = recipe::get_recipe(redis.clone(), ids[0]).await.unwrap();
+        assert_eq!((recipe.link.as_str(), recipe.title.as_str()), (link.as_str(), "Pancakes"));
+
+        // follow, with the priority given to links from pages with incomplete recipes
+        let child = local_link(&server, "waffles");
+        assert_eq!(link::get_status(redis.clone(), &child).await.unwrap(), LinkStatus::Waiting);
+        assert_eq!(link::get_links_detailed(redis.clone(), &[child]).await.unwrap(), vec![(-1.0, Some(link))]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn content_hash_is_not_stored_when_recipes_fail_to_be_added() {
+        let (_guard, mut redis) = test_redis::connection().await;
+        let server = serve_page(RECIPE_PAGE).await;
+        let link = local_link(&server, "pancakes");
+        link::add(redis.clone(), &link, None, 0.0, 5, true, None).await.unwrap();
+
+        // recipe ids can't be incremented, so adding any recipe fails
+        let _: () = redis::cmd("SET").arg("static:id").arg("not a number").query_async(&mut redis).await.unwrap();
+
+        assert!(parse_downloaded(redis.clone(), &link).await.is_err());
+        assert_eq!(link::get_content_hash(redis.clone(), &link).await.unwrap(), None);
+        assert_eq!(link::get_status(redis.clone(), &link).await.unwrap(), LinkStatus::ParsingFailed);
+        assert!(process_download(redis.clone(), &config(), client(), link.clone()).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn no_follow_link_never_enqueues_children() {
+        let (_guard, redis) = test_redis::connection().await;
+
+        let parent = "https://example.com/parent";
+        link::add(redis.clone(), parent, None, 0.0, 5, false, None).await.unwrap();
+        process_follow(redis.clone(), &config(), PAGE.to_string(), None, parent.to_string()).await.unwrap();
+
+        assert!(matches!(
+            link::get_status(redis.clone(), "https://example.com/child").await,
+            Err(LinkError::NotFound { .. }),
+        ));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn follow_link_enqueues_children() {
+        let (_guard, redis) = test_redis::connection().await;
+
+        let parent = "https://example.com/parent";
+        link::add(redis.clone(), parent, None, 0.0, 5, true, None).await.unwrap();
+        process_follow(redis.clone(), &config(), PAGE.to_string(), None, parent.to_string()).await.unwrap();
+
+        assert_eq!(link::get_status(redis.clone(), "https://example.com/child").await.unwrap(), LinkStatus::Waiting);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn oversized_page_is_not_followed() {
+        let (_guard, redis) = test_redis::connection().await;
+        let config = Config { max_follow_content_size: PAGE.len(), ..config() };
+
+        let parent = "https://example.com/parent";
+        link::add(redis.clone(), parent, None, 0.0, 5, true, None).await.unwrap();
+        let page = format!("{PAGE}{}", " ".repeat(1_000_000));
+        let started = Instant::now();
+        process_follow(redis.clone(), &config, page, None, parent.to_string()).await.unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(matches!(
+            link::get_status(redis.clone(), "https://example.com/child").await,
+            Err(LinkError::NotFound { .. }),
+        ));
+
+        // a page right at the limit is still followed
+        process_follow(redis.clone(), &config, PAGE.to_string(), None, parent.to_string()).await.unwrap();
+        assert_eq!(link::get_status(redis.clone(), "https://example.com/child").await.unwrap(), LinkStatus::Waiting);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn chain_of_complete_recipes_stops_at_max_depth() {
+        let (_guard, redis) = test_redis::connection().await;
+        // every recipe counts as complete, so remaining follows never run out
+        let config = Config { max_depth: 3, min_completeness: 0.0, ..config() };
+        let recipe = Recipe { ingredients: vec!["100g flour".to_string()], ..Recipe::default() };
+
+        link::add(redis.clone(), "https://example.com/0", None, 0.0, 1, true, None).await.unwrap();
+        // each page links to the next, so the chain only ends if following stops
+        let mut followed = 0;
+        while link::get_status(redis.clone(), &format!("https://example.com/{followed}")).await.is_ok() {
+            let page = format!(r#"<html><body><a href="https://example.com/{}">Next</a></body></html>"#, followed + 1);
+            process_follow(redis.clone(), &config, page, Some(recipe.clone()), format!("https://example.com/{followed}")).await.unwrap();
+            followed += 1;
+        }
+
+        assert_eq!(followed, 4);
+        assert_eq!(link::get_depth(redis.clone(), "https://example.com/3").await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn shutdown_drains_links_and_requeues_unfinished_ones() {
+        let (_guard, redis) = test_redis::connection().await;
+        let server = test_server::serve(|path| match path {
+            "/robots.txt" => Some(response("404 Not Found", &[], b"")),
+            "/slow" => None,
+            _ => Some(response("200 OK", &[("Content-Type", "text/html")], TWO_RECIPE_PAGE.as_bytes())),
+        }).await;
+        let fast = local_link(&server, "fast");
+        let slow = local_link(&server, "slow");
+        link::set_domain_slots(redis.clone(), 2).await.unwrap();
+        link::add(redis.clone(), &fast, None, 1.0, 0, true, None).await.unwrap();
+        link::add(redis.clone(), &slow, None, 0.0, 0, true, None).await.unwrap();
+        let config = Config { max_concurrent_tasks: 2, shutdown_timeout: Duration::from_millis(500), ..config() };
+
+        // long enough for both links to be polled, which happens once per domain every 500ms
+        let shutdown = tokio::time::sleep(Duration::from_millis(1200));
+        run(redis.clone(), redis.clone(), config, "http://127.0.0.1:1".to_string(), vec![], shutdown).await;
+
+        assert_eq!(link::get_status(redis.clone(), &fast).await.unwrap(), LinkStatus::Processed);
+        assert_eq!(link::get_status(redis.clone(), &slow).await.unwrap(), LinkStatus::Waiting);
+        assert_eq!(link::links_with_status(redis.clone(), LinkStatus::Processing).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn failed_links_are_processed_again_once_requeued() {
+        let (_guard, redis) = test_redis::connection().await;
+        let server = test_server::serve(|path| Some(match path {
+            "/missing" | "/robots.txt" => response("404 Not Found", &[], b""),
+            _ => response("200 OK", &[("Content-Type", "text/html")], PAGE.as_bytes()),
+        })).await;
+        let missing = local_link(&server, "missing");
+        let no_recipe = local_link(&server, "no-recipe");
+        link::set_domain_slots(redis.clone(), 2).await.unwrap();
+        for link in [&missing, &no_recipe] {
+            link::add(redis.clone(), link, None, 0.0, 0, true, None).await.unwrap();
+            link::update_status(redis.clone(), link, LinkStatus::Processing).await.unwrap();
+            let permit = Arc::new(Semaphore::new(1)).acquire_owned().await.unwrap();
+            process(redis.clone(), redis.clone(), Arc::new(config()), client(), permit, link.clone()).await;
+        }
+        assert_eq!(link::get_status(redis.clone(), &missing).await.unwrap(), LinkStatus::DownloadFailed);
+        assert_eq!(link::get_status(redis.clone(), &no_recipe).await.unwrap(), LinkStatus::ExtractionFailed);
+
+        assert_eq!(link::requeue_failed(redis.clone(), LinkStatus::DownloadFailed).await.unwrap(), 1);
+        assert_eq!(link::requeue_failed(redis.clone(), LinkStatus::ExtractionFailed).await.unwrap(), 1);
+
+        let mut polled = link::poll_next_jobs(redis.clone(), 10, false).await.unwrap();
+        polled.extend(link::poll_next_jobs(redis.clone(), 10, false).await.unwrap());
+        polled.sort();
+        assert_eq!(polled, vec![missing, no_recipe]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn link_that_times_out_is_marked_as_download_failed() {
+        let (_guard, redis) = test_redis::connection().await;
+        let server = test_server::serve(|path| match path {
+            "/robots.txt" => Some(response("404 Not Found", &[], b"")),
+            _ => None,
+        }).await;
+        let link = local_link(&server, "never-responds");
+        link::add(redis.clone(), &link, None, 0.0, 0, true, None).await.unwrap();
+        link::update_status(redis.clone(), &link, LinkStatus::Processing).await.unwrap();
+        let config = Config { process_timeout: Duration::from_millis(500), ..config() };
+        let permit = Arc::new(Semaphore::new(1)).acquire_owned().await.unwrap();
+
+        process(redis.clone(), redis.clone(), Arc::new(config), client(), permit, link.clone()).await;
+
+        assert_eq!(link::get_status(redis.clone(), &link).await.unwrap(), LinkStatus::DownloadFailed);
+        // robots.txt may already be cached from another test on localhost
+        assert!(server.requests().last().is_some_and(|v| v.starts_with("GET /never-responds ")));
+    }
+}
@@ -1,71 +1,469 @@
-use std::{collections::HashMap, sync::{Arc, LazyLock}, time::{Duration, Instant}};
+use std::{collections::HashMap, fmt, sync::{Arc, LazyLock, OnceLock}, time::{Duration, Instant}};
 
 use anyhow::Error;
-use recipe_common::link;
+use recipe_common::{download::{decode, headers, read_limited}, link, robots::{self, RobotsRules}};
 use redis::aio::MultiplexedConnection;
-use reqwest::{header::HeaderMap, Client, Method};
+use chrono::{DateTime, Utc};
+use log::debug;
+use reqwest::{header::{CONTENT_TYPE, RETRY_AFTER}, Client, Method, Response, StatusCode};
 use tokio::{sync::{Mutex, Semaphore}, time::sleep};
+use url::Url;
 
 use crate::UnexpectedStatusCodeErr;
 
-const REQUEST_INTERVAL_FOR_ONE_SITE: Duration = Duration::from_millis(4000);
+use super::Config;
+
+#[cfg(not(test))]
 const ADDITIONAL_REQUEST_INTERVAL_MAX_MILLIS: i32 = 4000;
+// tests all download from localhost, so would otherwise wait for each other's random delays
+#[cfg(test)]
+const ADDITIONAL_REQUEST_INTERVAL_MAX_MILLIS: i32 = 0;
+const MAX_RETRY_AFTER_SECS: u64 = 120;
+const MAX_BACKOFF_MULTIPLIER: f64 = 16.0;
+const SUCCESSES_BEFORE_SPEEDUP: u32 = 10;
+/// How long a domain's robots.txt is used before it is fetched again, so that changes to it
+/// are picked up by a long running finder
+const ROBOTS_TTL: Duration = Duration::from_secs(24 * 60 * 60);
 
 static SEMAPHORES: LazyLock<Mutex<HashMap<String, Arc<Semaphore>>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+static ROBOTS: LazyLock<Mutex<HashMap<String, (Instant, RobotsRules)>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+// the config doesn't change, so this is created with the configured limit on first use
+static DOWNLOADS: OnceLock<Semaphore> = OnceLock::new();
+static BACKOFFS: LazyLock<Mutex<HashMap<String, Backoff>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Scales a domain's request interval so that domains which start failing or rate limiting us
+/// are crawled more slowly, then sped back up once they have been succeeding for a while
+#[derive(Debug, Clone, Copy)]
+struct Backoff {
+    multiplier: f64,
+    successes: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff { multiplier: 1.0, successes: 0 }
+    }
+}
+
+impl Backoff {
+    fn on_failure(&mut self) {
+        self.multiplier = (self.multiplier * 2.0).min(MAX_BACKOFF_MULTIPLIER);
+        self.successes = 0;
+    }
+
+    fn on_success(&mut self) {
+        self.successes += 1;
+        if self.successes >= SUCCESSES_BEFORE_SPEEDUP {
+            self.multiplier = (self.multiplier / 2.0).max(1.0);
+            self.successes = 0;
+        }
+    }
+
+    fn apply(&self, interval: Duration) -> Duration {
+        interval.mul_f64(self.multiplier)
+    }
+}
 
-pub fn headers() -> HeaderMap {
-    let mut headers = HeaderMap::new();
-    headers.insert("Accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8".parse().unwrap());
-    headers.insert("Accept-Language", "en-GB,en;q=0.5".parse().unwrap());
-    headers.insert("Cache-Control", "no-cache".parse().unwrap());
-    headers.insert("Pragma", "no-cache".parse().unwrap());
-    headers.insert("Priority", "u=0, i".parse().unwrap());
-    headers.insert("Sec-Ch-Ua", "\"Brave\";v=\"131\", \"Chromium\";v=\"131\", \"Not_A Brand\";v=\"24\"".parse().unwrap());
-    headers.insert("Sec-Ch-Ua-Mobile", "?0".parse().unwrap());
-    headers.insert("Sec-Ch-Ua-Platform", "Linux".parse().unwrap());
-    headers.insert("Sec-Fetch-Dest", "document".parse().unwrap());
-    headers.insert("Sec-Fetch-Mode", "navigate".parse().unwrap());
-    headers.insert("Sec-Fetch-Site", "none".parse().unwrap());
-    headers.insert("Sec-Fetch-User", "?1".parse().unwrap());
-    headers.insert("Sec-Gpc", "1".parse().unwrap());
-    headers.insert("Upgrade-Insecure-Requests", "1".parse().unwrap());
-    headers.insert("User-Agent", "Prototype recipe search engine indexer".parse().unwrap());
-    headers
+#[derive(Debug)]
+pub struct DisallowedErr {
+    link: String
+}
+
+impl fmt::Display for DisallowedErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Link is disallowed by robots.txt: {}", self.link)
+    }
+}
+
+impl std::error::Error for DisallowedErr {}
+
+#[derive(Debug)]
+pub struct UnsupportedContentTypeError {
+    link: String,
+    content_type: String,
+}
+
+impl fmt::Display for UnsupportedContentTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unsupported content type '{}': {}", self.content_type, self.link)
+    }
+}
+
+impl std::error::Error for UnsupportedContentTypeError {}
+
+/// PDFs, images, JSON, etc can't contain recipes we know how to extract, so there's no point
+/// reading them. Responses without a Content-Type are assumed to be HTML
+fn check_content_type(response: &Response, job: &str) -> Result<(), UnsupportedContentTypeError> {
+    let Some(content_type) = response.headers().get(CONTENT_TYPE) else {
+        return Ok(());
+    };
+    let content_type = String::from_utf8_lossy(content_type.as_bytes()).to_string();
+    // eg 'text/html; charset=UTF-8'
+    let mime_type = content_type.split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    if mime_type == "text/html" || mime_type == "application/xhtml+xml" {
+        Ok(())
+    } else {
+        Err(UnsupportedContentTypeError { link: job.to_owned(), content_type })
+    }
 }
 
-#[tracing::instrument(skip(redis_links, client))]
-pub async fn download(redis_links: MultiplexedConnection, client: Client, job: String) -> Result<String, Error> {
+/// How long a 429/503 response asks us to wait before retrying. Retry-After can be either a
+/// number of seconds or an HTTP date (eg 'Wed, 21 Oct 2015 07:28:00 GMT'). Falls back to the
+/// request interval if it's missing or invalid, and is capped so one site can't stall a task
+/// indefinitely.
+fn retry_after(config: &Config, response: &Response) -> Duration {
+    let value = response.headers()
+        .get(RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim());
+
+    let retry_after = value.and_then(|value| match value.parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => DateTime::parse_from_rfc2822(value)
+            .ok()
+            .map(|date| (date.with_timezone(&Utc) - Utc::now()).to_std().unwrap_or_default()),
+    });
+
+    retry_after.unwrap_or(config.request_interval)
+        .min(Duration::from_secs(MAX_RETRY_AFTER_SECS))
+}
+
+async fn record_backoff(domain: &str, failed: bool) {
+    let mut backoffs = BACKOFFS.lock().await;
+    let backoff = backoffs.entry(domain.to_owned()).or_default();
+    if failed {
+        backoff.on_failure();
+    } else {
+        backoff.on_success();
+    }
+}
+
+/// Sends a single request for the page
+/// Connection errors, 429s and server errors count towards the domain's backoff
+#[tracing::instrument(skip(config, client))]
+async fn request(config: &Config, client: &Client, domain: &str, original: &str) -> Result<Response, Error> {
+    match client.request(Method::GET, original)
+        .headers(headers(&config.headers))
+        .send()
+        .await
+    {
+        Err(err) => {
+            record_backoff(domain, true).await;
+            Err(err.into())
+        }
+        Ok(response) => {
+            let status = response.status();
+            record_backoff(domain, status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()).await;
+            Ok(response)
+        }
+    }
+}
+
+/// Reads the page from a final (ie not retried) response
+async fn read(config: &Config, response: Response, job: &str) -> Result<String, Error> {
+    if !response.status().is_success() {
+        return Err(Box::new(UnexpectedStatusCodeErr(response.status())).into());
+    }
+
+    check_content_type(&response, job)?;
+
+    decode(&read_limited(response, config.max_content_size).await?, config.max_content_size)
+}
+
+#[tracing::instrument(skip(redis_links, config, client))]
+pub async fn download(redis_links: MultiplexedConnection, config: &Config, client: Client, job: String) -> Result<String, Error> {
     let domain = link::get_domain(redis_links.clone(), &job).await?;
 
     let semaphore = SEMAPHORES.lock()
         .await
-        .entry(domain)
-        .or_insert(Arc::new(Semaphore::new(1)))
+        .entry(domain.clone())
+        .or_insert(Arc::new(Semaphore::new(config.domain_download_slots)))
         .clone();
 
-    let _permit = semaphore.acquire()
+    let mut permit = semaphore.clone()
+        .acquire_owned()
         .await
         .unwrap();
 
     let start_time = Instant::now();
+    let mut request_time = start_time;
 
-    let response = client.request(Method::GET, job)
-        .headers(headers())
-        .send()
-        .await?;
+    // the job is the canonical link, but the page should be requested exactly as it was found
+    let original = link::get_original(redis_links.clone(), &job).await?;
+    let url = Url::parse(&original)?;
 
-    if !response.status().is_success() {
-        return Err(Box::new(UnexpectedStatusCodeErr(response.status())).into());
+    // https requests go through the proxy, so are never passed to the client's resolver
+    config.address_guard.check(&url, url.scheme() == "https").await?;
+
+    // the domain's permit is held, so robots.txt is usually only fetched once per domain
+    let cached_robots = ROBOTS.lock()
+        .await
+        .get(&domain)
+        .filter(|(fetched, _)| fetched.elapsed() < ROBOTS_TTL)
+        .map(|(_, robots)| robots.clone());
+    let robots = match cached_robots {
+        Some(robots) => robots,
+        None => {
+            let robots = robots::fetch(&client, &config.headers, &url).await;
+            ROBOTS.lock()
+                .await
+                .insert(domain.clone(), (Instant::now(), robots.clone()));
+            robots
+        }
+    };
+
+    if !robots.is_link_allowed(&url) {
+        return Err(DisallowedErr { link: job }.into());
     }
 
-    let content = response.text().await?;
+    // retry after 429/503 responses, releasing the domain's permit while waiting so the wait
+    // doesn't hold up the domain's other links, and giving up once the retries would take up
+    // more than half of the time the link has to be processed
+    let mut retries = 0;
+    let content = loop {
+        let download_permit = match config.max_concurrent_downloads {
+            Some(max_concurrent_downloads) => Some(DOWNLOADS.get_or_init(|| Semaphore::new(max_concurrent_downloads))
+                .acquire()
+                .await
+                .unwrap()),
+            None => None,
+        };
 
-    let elapsed_time = Instant::now() - start_time;
-    let request_interval = REQUEST_INTERVAL_FOR_ONE_SITE + Duration::from_millis((rand::random::<f64>() * ADDITIONAL_REQUEST_INTERVAL_MAX_MILLIS as f64) as u64);
+        let response = match request(config, &client, &domain, &original).await {
+            Err(err) => break Err(err),
+            Ok(response) => response,
+        };
+
+        let status = response.status();
+        let delay = retry_after(config, &response);
+        let should_retry = matches!(status, StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE)
+            && retries < config.max_retries
+            && start_time.elapsed() + delay < config.process_timeout / 2;
+        if !should_retry {
+            break read(config, response, &job).await;
+        }
+
+        retries += 1;
+        debug!("Got status code '{status}' for {job}, retrying in {delay:?} ({retries}/{})", config.max_retries);
+        drop(download_permit);
+        drop(permit);
+        sleep(delay).await;
+        permit = semaphore.clone()
+            .acquire_owned()
+            .await
+            .unwrap();
+        request_time = Instant::now();
+    };
+
+    // wait even if the download failed, so a failing domain isn't hit again immediately
+    let backoff = BACKOFFS.lock()
+        .await
+        .get(&domain)
+        .copied()
+        .unwrap_or_default();
+    let elapsed_time = Instant::now() - request_time;
+    let base_request_interval = robots.crawl_delay
+        .map_or(config.request_interval, |crawl_delay| crawl_delay.max(config.request_interval));
+    let request_interval = backoff.apply(base_request_interval) + Duration::from_millis((rand::random::<f64>() * ADDITIONAL_REQUEST_INTERVAL_MAX_MILLIS as f64) as u64);
     if elapsed_time < request_interval {
         sleep(request_interval - elapsed_time).await;
     }
 
-    Ok(content)
+    // the domain's permit is only released once the interval has passed
+    drop(permit);
+
+    content
 }
 
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use flate2::{write::{GzEncoder, ZlibEncoder}, Compression};
+    use recipe_common::{test_redis, test_server::{self, response, serve_response}};
+
+    use crate::link::{client_builder, tests::config};
+
+    use super::*;
+
+    async fn check(content_type: Option<&str>) -> Result<(), UnsupportedContentTypeError> {
+        let headers: Vec<(&str, &str)> = content_type.iter().map(|v| ("Content-Type", *v)).collect();
+        let server = serve_response(response("200 OK", &headers, b"hi")).await;
+        let client = Client::builder().no_proxy().build().unwrap();
+        let response = client.get(&server.url).send().await.unwrap();
+        check_content_type(&response, &server.url)
+    }
+
+    #[test]
+    fn backoff_doubles_on_each_failure_up_to_the_cap() {
+        let mut backoff = Backoff::default();
+        let interval = Duration::from_secs(1);
+        assert_eq!(backoff.apply(interval), interval);
+
+        backoff.on_failure();
+        assert_eq!(backoff.apply(interval), Duration::from_secs(2));
+        backoff.on_failure();
+        assert_eq!(backoff.apply(interval), Duration::from_secs(4));
+
+        for _ in 0..10 {
+            backoff.on_failure();
+        }
+        assert_eq!(backoff.apply(interval), interval.mul_f64(MAX_BACKOFF_MULTIPLIER));
+    }
+
+    #[test]
+    fn backoff_halves_after_enough_successes_down_to_no_backoff() {
+        let mut backoff = Backoff::default();
+        backoff.on_failure();
+        backoff.on_failure();
+
+        for _ in 0..SUCCESSES_BEFORE_SPEEDUP - 1 {
+            backoff.on_success();
+        }
+        assert_eq!(backoff.multiplier, 4.0);
+        backoff.on_success();
+        assert_eq!(backoff.multiplier, 2.0);
+
+        for _ in 0..SUCCESSES_BEFORE_SPEEDUP * 5 {
+            backoff.on_success();
+        }
+        assert_eq!(backoff.multiplier, 1.0);
+    }
+
+    #[test]
+    fn backoff_failure_resets_the_success_count() {
+        let mut backoff = Backoff::default();
+        backoff.on_failure();
+
+        for _ in 0..SUCCESSES_BEFORE_SPEEDUP - 1 {
+            backoff.on_success();
+        }
+        backoff.on_failure();
+        assert_eq!(backoff.multiplier, 4.0);
+        assert_eq!(backoff.successes, 0);
+
+        backoff.on_success();
+        assert_eq!(backoff.multiplier, 4.0);
+    }
+
+    #[tokio::test]
+    async fn html_content_types_are_allowed() {
+        assert!(check(Some("text/html")).await.is_ok());
+        assert!(check(Some("text/html; charset=UTF-8")).await.is_ok());
+        assert!(check(Some("Application/XHTML+XML")).await.is_ok());
+        assert!(check(None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn other_content_types_are_rejected() {
+        let err = check(Some("application/pdf")).await.unwrap_err();
+        assert_eq!(err.content_type, "application/pdf");
+
+        assert!(check(Some("image/jpeg")).await.is_err());
+        assert!(check(Some("application/json")).await.is_err());
+    }
+
+    const PAGE: &str = "<html><body>Pancakes</body></html>";
+
+    /// Requests a page served with the given Content-Encoding as the finder would, with its
+    /// headers and client settings
+    async fn download_encoded(encoding: &str, body: Vec<u8>) -> (String, String) {
+        let server = serve_response(response("200 OK", &[("Content-Type", "text/html"), ("Content-Encoding", encoding)], &body)).await;
+        let config = config();
+        let client = client_builder(&config).no_proxy().build().unwrap();
+
+        let response = request(&config, &client, "encoding.test", &server.url).await.unwrap();
+        let contents = read(&config, response, &server.url).await.unwrap();
+        (contents, server.requests()[0].to_lowercase())
+    }
+
+    #[tokio::test]
+    async fn gzip_encoded_pages_are_decoded() {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(PAGE.as_bytes()).unwrap();
+
+        let (contents, request) = download_encoded("gzip", encoder.finish().unwrap()).await;
+        assert_eq!(contents, PAGE);
+        assert!(request.contains("accept-encoding: gzip, deflate, br"), "{request}");
+    }
+
+    #[tokio::test]
+    async fn deflate_encoded_pages_are_decoded() {
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(PAGE.as_bytes()).unwrap();
+
+        let (contents, _) = download_encoded("deflate", encoder.finish().unwrap()).await;
+        assert_eq!(contents, PAGE);
+    }
+
+    #[tokio::test]
+    async fn brotli_encoded_pages_are_decoded() {
+        let mut encoder = brotli::CompressorWriter::new(vec![], 4096, 11, 22);
+        encoder.write_all(PAGE.as_bytes()).unwrap();
+
+        let (contents, _) = download_encoded("br", encoder.into_inner()).await;
+        assert_eq!(contents, PAGE);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn rate_limited_downloads_are_retried_after_the_requested_delay() {
+        let (_guard, redis) = test_redis::connection().await;
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let server_attempts = attempts.clone();
+        let server = test_server::serve(move |path| Some(match path {
+            "/robots.txt" => response("404 Not Found", &[], b""),
+            _ if server_attempts.fetch_add(1, Ordering::SeqCst) == 0 => response("429 Too Many Requests", &[("Retry-After", "1")], b""),
+            _ => response("200 OK", &[("Content-Type", "text/html")], PAGE.as_bytes()),
+        })).await;
+        let link = format!("{}retried", server.url.replace("127.0.0.1", "localhost"));
+        link::add(redis.clone(), &link, None, 0.0, 0, true, None).await.unwrap();
+        let config = Config { max_retries: 1, ..config() };
+        let client = client_builder(&config).no_proxy().build().unwrap();
+
+        let start = Instant::now();
+        let contents = download(redis.clone(), &config, client, link).await.unwrap();
+
+        assert_eq!(contents, PAGE);
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        assert!(start.elapsed() >= Duration::from_secs(1), "retried after {:?}", start.elapsed());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Redis, see test_redis"]
+    async fn concurrent_downloads_stay_within_the_limit() {
+        let (_guard, redis) = test_redis::connection().await;
+        // pages never respond, so every download that gets through holds its permit
+        let server = test_server::serve(|path| match path {
+            "/robots.txt" => Some(response("404 Not Found", &[], b"")),
+            _ => None,
+        }).await;
+        // a domain of its own, so other tests' downloads from localhost don't share its slots
+        let address = server.url.trim_start_matches("http://").trim_end_matches('/').parse().unwrap();
+        let config = Config {
+            max_concurrent_downloads: Some(2),
+            domain_download_slots: 5,
+            address_guard: config().address_guard.with_exempt_host("downloads.test"),
+            ..config()
+        };
+        let client = client_builder(&config).no_proxy().resolve("downloads.test", address).build().unwrap();
+
+        let mut downloads = tokio::task::JoinSet::new();
+        for i in 0..5 {
+            let link = format!("http://downloads.test:{}/{i}", address.port());
+            link::add(redis.clone(), &link, None, 0.0, 0, true, None).await.unwrap();
+            let (redis, config, client) = (redis.clone(), config.clone(), client.clone());
+            downloads.spawn(async move { download(redis, &config, client, link).await });
+        }
+        sleep(Duration::from_secs(1)).await;
+
+        let pages = server.requests().iter().filter(|v| !v.contains("/robots.txt")).count();
+        assert_eq!(pages, 2);
+    }
+}
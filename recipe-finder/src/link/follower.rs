@@ -1,48 +1,278 @@
-use std::sync::LazyLock;
+use std::{fmt, str::FromStr, sync::LazyLock};
 
-use regex::{Regex, RegexBuilder};
+use recipe_common::link::canonicalize;
+use regex::Regex;
+use scraper::{Html, Selector};
 use url::Url;
 
-static LINK_ELEMENT_REGEX: LazyLock<Regex> = LazyLock::new(|| 
-    RegexBuilder::new(r#"<a.{0,2000}?>"#)
-        .build()
-        .unwrap()
+static LINK_SELECTOR: LazyLock<Selector> = LazyLock::new(|| 
+    Selector::parse("a[href]").unwrap()
 );
 
-static HREF_REGEX: LazyLock<Regex> = LazyLock::new(|| 
-    RegexBuilder::new(r#"href\s?=\s?"([^"]{0,500})""#)
-        .build()
-        .unwrap()
+static NOSCRIPT_SELECTOR: LazyLock<Selector> = LazyLock::new(|| 
+    Selector::parse("noscript").unwrap()
 );
 
-#[tracing::instrument(skip(contents))]
-pub async fn follow(contents: String, link: String) -> Vec<String> {
+/// Print and AMP versions of recipe pages usually have poor schemas, while the original pages
+/// are generally fine, so followed links are rewritten to the original
+/// eg https://example.com/pancakes/wprm_print -> https://example.com/pancakes
+const DEFAULT_LINK_REWRITES: [&str; 4] = [
+    "/wprm_print=>",
+    "/print/?$=>",
+    "/amp/?$=>",
+    "([?&])print=(?:yes|true|1)(?:&|$)=>$1",
+];
+
+#[derive(Debug)]
+pub struct InvalidLinkRewriteError {
+    rewrite: String,
+}
+
+impl fmt::Display for InvalidLinkRewriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid link rewrite '{}' (expected 'regex=>replacement')", self.rewrite)
+    }
+}
+
+impl std::error::Error for InvalidLinkRewriteError {}
+
+/// Replaces every match of `pattern` in a followed link with `replacement`, which can refer to
+/// capture groups (eg '$1')
+#[derive(Debug, Clone)]
+pub struct LinkRewrite {
+    pattern: Regex,
+    replacement: String,
+}
+
+impl LinkRewrite {
+    pub fn defaults() -> Vec<LinkRewrite> {
+        DEFAULT_LINK_REWRITES.iter()
+            .map(|v| v.parse().unwrap())
+            .collect()
+    }
+
+    fn apply(&self, link: &str) -> String {
+        self.pattern.replace_all(link, self.replacement.as_str()).into_owned()
+    }
+}
+
+/// Parses 'regex=>replacement', eg '/amp/?$=>'
+impl FromStr for LinkRewrite {
+    type Err = InvalidLinkRewriteError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || InvalidLinkRewriteError { rewrite: s.to_owned() };
+        let (pattern, replacement) = s.rsplit_once("=>").ok_or_else(err)?;
+        let pattern = Regex::new(pattern).map_err(|_| err())?;
+        Ok(LinkRewrite { pattern, replacement: replacement.to_owned() })
+    }
+}
+
+/// Whether both hosts have the same registrable domain according to the public suffix list, so
+/// subdomains are the same site (eg www.bbc.co.uk and food.bbc.co.uk) but different registrations
+/// under a shared suffix aren't (eg bbc.co.uk and itv.co.uk)
+fn is_same_site(host: &str, other_host: &str) -> bool {
+    match (psl::domain_str(host), psl::domain_str(other_host)) {
+        (Some(domain), Some(other_domain)) => domain.eq_ignore_ascii_case(other_domain),
+        _ => false,
+    }
+}
+
+/// The href of every link on the page, including those in <noscript> blocks, which the parser
+/// keeps as plain text (as a browser with scripts enabled would) so are parsed separately
+fn hrefs(document: &Html) -> Vec<String> {
+    let mut hrefs: Vec<String> = document.select(&LINK_SELECTOR)
+        .filter_map(|element| element.value().attr("href"))
+        .map(|v| v.to_owned())
+        .collect();
+
+    for noscript in document.select(&NOSCRIPT_SELECTOR) {
+        let fragment = Html::parse_fragment(&noscript.text().collect::<String>());
+        hrefs.extend(fragment.select(&LINK_SELECTOR)
+            .filter_map(|element| element.value().attr("href"))
+            .map(|v| v.to_owned()));
+    }
+
+    hrefs
+}
+
+#[tracing::instrument(skip(contents, rewrites))]
+pub async fn follow(contents: String, link: String, same_domain_only: bool, rewrites: &[LinkRewrite]) -> Vec<String> {
     let url = Url::parse(&link)
         .expect("How did you screw this up");
-    let domain = "https://".to_string() + url.domain()
-        .expect("How did you screw this up");
+    // links in HTML comments are never selected, as the parser keeps comments as comment nodes
+    let document = Html::parse_document(&contents);
+
+    hrefs(&document).iter()
+        .map(|v| v.trim())
 
-    LINK_ELEMENT_REGEX.captures_iter(&contents)
-        .map(|captures| captures.get(0).unwrap().as_str())
-        .filter_map(|element| HREF_REGEX.captures(element))
-            .map(|captures| captures.get(1).unwrap().as_str())
+        // resolve relative links against the page they were found on
+        // (eg '/category/stupid_recipes' -> bbc.co.uk/category/stupid_recipes,
+        // '../dinner' on bbc.co.uk/recipes/lunch/ -> bbc.co.uk/recipes/dinner)
+        // absolute links are left as they are
+        .filter_map(|v| url.join(v).ok())
+        .map(|v| v.to_string())
 
-        // fix relative links (eg '/category/stupid_recipes' -> bbc.co.uk/category/stupid_recipes)
-        .map(|v| if v.chars().next().is_some_and(|v| v == '/') { 
-                domain.clone() + v 
-            } else { 
-                v.to_string() 
-            })
-        .filter(|new_link| Url::parse(new_link).is_ok())
+        // links without a domain (eg to an IP address) can't be the same site, so are dropped
+        .filter(|new_link| !same_domain_only || Url::parse(new_link)
+            .ok()
+            .and_then(|v| v.domain().map(|v| v.to_owned()))
+            .is_some_and(|v| url.domain().is_some_and(|domain| is_same_site(&v, domain)))
+        )
 
         // eg, bruh.com/some-recipe might have links to bruh.com/some-recipe/comments#36
         .filter(|new_link| !new_link.starts_with(&link))
 
-        // hardcoded fix. often, recipes have www.domain.com/your_shitty_recipe/wprm_print pages
-        // these pages have shit schemas. but the original pages are generally fine
-        .map(|v| v.replace("/wprm_print", ""))
+        .map(|v| rewrites.iter().fold(v, |v, rewrite| rewrite.apply(&v)))
 
-        .map(|v| v.to_owned())
+        .map(|v| canonicalize(&v))
         .collect()
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "https://example.com/recipes/index";
+
+    async fn links_in(hrefs: &[&str], rewrites: &[LinkRewrite]) -> Vec<String> {
+        let contents = hrefs.iter()
+            .map(|v| format!("<a href=\"{v}\">link</a>"))
+            .collect::<String>();
+        follow(contents, SOURCE.to_string(), false, rewrites).await
+    }
+
+    #[tokio::test]
+    async fn default_rewrites_collapse_print_and_amp_variants() {
+        let links = links_in(&[
+            "https://example.com/pancakes/wprm_print",
+            "https://example.com/pancakes/print/",
+            "https://example.com/pancakes/amp",
+            "https://example.com/pancakes?print=yes",
+            "https://example.com/pancakes/?print=1&utm_source=feed",
+        ], &LinkRewrite::defaults()).await;
+
+        assert_eq!(links, vec!["https://example.com/pancakes"; 5]);
+    }
+
+    #[tokio::test]
+    async fn default_rewrites_keep_other_query_parameters() {
+        let links = links_in(&["https://example.com/pancakes?print=true&page=2"], &LinkRewrite::defaults()).await;
+
+        assert_eq!(links, vec!["https://example.com/pancakes?page=2"]);
+    }
+
+    #[tokio::test]
+    async fn configured_rewrites_are_applied_in_order() {
+        let rewrites = vec![
+            "/printrecipe/(\\d+)$=>/recipe/$1".parse().unwrap(),
+            "/recipe/=>/recipes/".parse().unwrap(),
+        ];
+        let links = links_in(&["https://example.com/printrecipe/42"], &rewrites).await;
+
+        assert_eq!(links, vec!["https://example.com/recipes/42"]);
+    }
+
+    #[tokio::test]
+    async fn relative_links_are_joined_against_the_source() {
+        let links = links_in(&[
+            "/abs",
+            "../rel",
+            "rel",
+            "https://other.com/absolute",
+        ], &[]).await;
+
+        assert_eq!(links, vec![
+            "https://example.com/abs",
+            "https://example.com/rel",
+            "https://example.com/recipes/rel",
+            "https://other.com/absolute",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn joined_links_keep_the_source_scheme_and_port() {
+        let contents = "<a href=\"/pancakes\">link</a><a href=\"//cdn.example.com/waffles\">link</a>".to_string();
+        let links = follow(contents, "http://localhost:8080/recipes/index".to_string(), false, &[]).await;
+
+        assert_eq!(links, vec!["http://localhost:8080/pancakes", "http://cdn.example.com/waffles"]);
+    }
+
+    #[tokio::test]
+    async fn anchors_with_long_attributes_are_found() {
+        let long_attribute = "x".repeat(3000);
+        let contents = format!(
+            "<a data-tracking=\"{long_attribute}\" title=\"1 > 0\" href=\"/pancakes\">Pancakes</a>"
+        );
+        let links = follow(contents, SOURCE.to_string(), false, &[]).await;
+
+        assert_eq!(links, vec!["https://example.com/pancakes"]);
+    }
+
+    #[tokio::test]
+    async fn same_domain_only_keeps_relative_links_and_subdomains() {
+        let contents = [
+            "/pancakes",
+            "../waffles",
+            "https://www.example.com/crepes",
+            "https://food.example.com/porridge",
+            "https://other.com/toast",
+            "https://example.com.evil.com/bacon",
+            "mailto:chef@example.com",
+        ].iter()
+            .map(|v| format!("<a href=\"{v}\">link</a>"))
+            .collect::<String>();
+        let links = follow(contents, SOURCE.to_string(), true, &[]).await;
+
+        assert_eq!(links, vec![
+            "https://example.com/pancakes",
+            "https://example.com/waffles",
+            "https://www.example.com/crepes",
+            "https://food.example.com/porridge",
+        ]);
+    }
+
+    #[test]
+    fn sites_under_a_shared_public_suffix_are_different() {
+        assert!(is_same_site("www.bbc.co.uk", "food.bbc.co.uk"));
+        assert!(!is_same_site("bbc.co.uk", "itv.co.uk"));
+        assert!(!is_same_site("alice.github.io", "bob.github.io"));
+        assert!(!is_same_site("co.uk", "bbc.co.uk"));
+    }
+
+    #[tokio::test]
+    async fn same_domain_only_drops_every_link_from_a_source_without_a_domain() {
+        let contents = "<a href=\"/pancakes\">link</a><a href=\"https://example.com/\">link</a>".to_string();
+        let links = follow(contents, "http://127.0.0.1/recipes".to_string(), true, &[]).await;
+
+        assert!(links.is_empty());
+    }
+
+    #[tokio::test]
+    async fn links_in_comments_are_ignored_and_links_in_noscript_are_followed() {
+        let contents = r#"<html><head>
+            <noscript><a href="/from-head-noscript">link</a></noscript>
+        </head><body>
+            <!-- <a href="/commented-out">link</a> -->
+            <a href="/visible">link</a>
+            <!--
+                <div><a href="https://example.com/old-menu">link</a></div>
+            -->
+            <noscript><img src="/pixel.gif"><a href="/from-body-noscript">link</a></noscript>
+        </body></html>"#.to_string();
+        let links = follow(contents, SOURCE.to_string(), false, &[]).await;
+
+        assert_eq!(links, vec![
+            "https://example.com/visible",
+            "https://example.com/from-head-noscript",
+            "https://example.com/from-body-noscript",
+        ]);
+    }
+
+    #[test]
+    fn rewrite_without_separator_is_invalid() {
+        assert!("/amp/?$".parse::<LinkRewrite>().is_err());
+        assert!("(unclosed=>".parse::<LinkRewrite>().is_err());
+    }
+}
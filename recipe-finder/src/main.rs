@@ -1,19 +1,31 @@
 use std::fmt;
+use std::net::IpAddr;
 use std::time::Duration;
 use std::{error::Error, fs::File};
 use std::io::Read;
 
-use clap::Parser;
+use clap::error::ErrorKind;
+use clap::{builder::RangedU64ValueParser, CommandFactory, Parser};
+use link::follower::LinkRewrite;
 use log::info;
+use recipe_common::address::AddressGuard;
+use recipe_common::download::{self, HeaderConfig};
+use recipe_common::logging::{self, LogFormat};
 use reqwest::{Certificate, StatusCode};
 use sqlx::mysql::MySqlPoolOptions;
-use tracing_subscriber::util::SubscriberInitExt;
-use tracing_subscriber::Layer;
-use tracing_subscriber::{layer::SubscriberExt, EnvFilter};
+use tokio::signal::{self, unix::SignalKind};
+use url::Url;
 
 mod link;
+mod reaper;
+mod recrawler;
 mod statistic;
 
+const DEFAULT_REQUEST_INTERVAL_MILLIS: u64 = 4000;
+const DEFAULT_MAX_CONCURRENT_TASKS: usize = 4096;
+const POLITE_REQUEST_INTERVAL_MILLIS: u64 = 10000;
+const POLITE_MAX_CONCURRENT_TASKS: usize = 64;
+
 #[derive(Debug)]
 pub struct UnexpectedStatusCodeErr(StatusCode);
 
@@ -25,6 +37,15 @@ impl fmt::Display for UnexpectedStatusCodeErr {
 
 impl Error for UnexpectedStatusCodeErr {}
 
+fn parse_fraction(value: &str) -> Result<f32, String> {
+    let fraction: f32 = value.parse()
+        .map_err(|_| format!("'{value}' is not a number"))?;
+    if !(0.0..=1.0).contains(&fraction) {
+        return Err(format!("{fraction} is not between 0 and 1"));
+    }
+    Ok(fraction)
+}
+
 #[derive(Parser, Debug)]
 struct Args {
     #[arg(long)]
@@ -37,21 +58,212 @@ struct Args {
     redis_links_url: String,
     #[arg(long)]
     redis_recipes_url: String,
+    /// Only follow links on the same site (registrable domain, so including subdomains) as the page
+    /// they were found on
+    #[arg(long)]
+    same_domain_only: bool,
+    /// Pages larger than this (in bytes) are not searched for links to follow
+    #[arg(long, default_value_t = 4 * 1024 * 1024)]
+    max_follow_content_size: usize,
+    /// Downloads larger than this (in bytes) are aborted
+    #[arg(long, default_value_t = download::DEFAULT_MAX_CONTENT_SIZE)]
+    max_content_size: usize,
+    /// Reject recipes unless at least one instruction has this many characters
+    #[arg(long)]
+    min_instruction_chars: Option<usize>,
+    /// Reject recipes unless their instructions have this many characters in total
+    #[arg(long)]
+    min_total_instruction_chars: Option<usize>,
+    /// Fraction (from 0 to 1) of a recipe's optional fields (images, authors, nutrition, etc)
+    /// that must be present for it to count as complete. Links on pages with complete recipes are
+    /// followed further and with a higher priority
+    #[arg(long, default_value_t = 1.0, value_parser = parse_fraction)]
+    min_completeness: f32,
+    /// Maximum number of follows away from a submitted link, regardless of how complete the
+    /// recipes along the way are
+    #[arg(long, default_value_t = 10)]
+    max_depth: u32,
+    /// Use conservative crawl defaults (slower per-domain requests, lower concurrency) and
+    /// require --contact. Explicitly given options still take precedence. robots.txt is obeyed
+    /// with or without this flag
+    #[arg(long)]
+    polite: bool,
+    /// Minimum time between requests to the same domain
+    /// [default: 4000, or 10000 with --polite]
+    #[arg(long)]
+    request_interval_millis: Option<u64>,
+    /// Maximum number of links processed at once
+    /// [default: 4096, or 64 with --polite]
+    #[arg(long, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    max_concurrent_tasks: Option<usize>,
+    /// Maximum number of requests in flight at once across all domains [default: no limit]
+    #[arg(long, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    max_concurrent_downloads: Option<usize>,
+    /// Contact details (eg an email address) to include in the user agent
+    #[arg(long, value_parser = download::parse_header_value)]
+    contact: Option<String>,
+    /// User agent to identify as (the contact details are appended to it)
+    #[arg(long, default_value = download::USER_AGENT, value_parser = download::parse_header_value)]
+    user_agent: String,
+    /// Accept-Language header to send with every request
+    #[arg(long, default_value = download::ACCEPT_LANGUAGE, value_parser = download::parse_header_value)]
+    accept_language: String,
+    /// Local or private address that links may resolve to, which are otherwise refused. Can be
+    /// given multiple times
+    #[arg(long = "allow-address")]
+    allowed_addresses: Vec<IpAddr>,
+    /// Maximum time to spend processing a single link, after which it is marked as failed
+    #[arg(long, default_value_t = 300)]
+    process_timeout_secs: u64,
+    /// How many times to retry a download after a 429/503 response (honouring Retry-After).
+    /// Retries stop early once they would take more than half the process timeout
+    #[arg(long, default_value_t = 3)]
+    max_retries: usize,
+    /// Maximum time a single request can take, after which the download fails
+    #[arg(long, default_value_t = 30)]
+    download_timeout_secs: u64,
+    /// Maximum number of links waiting for a single domain. Once reached, only followed links
+    /// with a higher priority than the lowest waiting one are added (replacing it). Also limits
+    /// how many failed links are requeued through the API
+    #[arg(long)]
+    max_domain_waiting_links: Option<usize>,
+    /// Links that have been processing for longer than this (eg because the finder crashed
+    /// mid-run) are put back in the queue
+    #[arg(long, default_value_t = 3600)]
+    stuck_task_timeout_secs: u64,
+    /// Processed links are re-queued once they are this old, so their recipes are kept up to
+    /// date. If not given, links are never recrawled
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    recrawl_ttl_secs: Option<u64>,
+    /// How often to look for processed links older than --recrawl-ttl-secs
+    #[arg(long, default_value_t = 3600, value_parser = clap::value_parser!(u64).range(1..))]
+    recrawl_interval_secs: u64,
+    /// Priority that recrawled links are re-queued with, which should be lower than new links
+    #[arg(long, default_value_t = -1.0, allow_negative_numbers = true)]
+    recrawl_priority: f32,
+    /// How many downloads from a single domain can happen at once. Each download still waits
+    /// for the request interval, so the domain is hit this many times as often
+    #[arg(long, default_value_t = 1, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    domain_download_slots: usize,
+    /// How often the link blacklist and allowlist are reloaded from Redis
+    #[arg(long, default_value_t = 60)]
+    blacklist_refresh_secs: u64,
+    /// How often link and recipe statistics are recorded
+    #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u64).range(1..))]
+    statistic_interval_secs: u64,
+    /// How long to wait for in-flight links to finish processing on shutdown, after which they
+    /// are put back in the queue
+    #[arg(long, default_value_t = 30)]
+    shutdown_timeout_secs: u64,
+    /// 'pretty' or 'json'
+    #[arg(long, default_value = "pretty")]
+    log_format: LogFormat,
+    /// Poll domains with higher priority waiting links more often, rather than uniformly at random
+    #[arg(long)]
+    prioritize_domains: bool,
+    /// Rewrite applied to every followed link, as 'regex=>replacement' (eg '/amp/?$=>'). Can be
+    /// given multiple times, and replaces the defaults (which strip print and AMP suffixes)
+    #[arg(long = "link-rewrite")]
+    link_rewrites: Vec<LinkRewrite>,
+}
+
+impl Args {
+    /// The proxy is usually a local service, so is exempt from the address checks
+    fn address_guard(&self) -> AddressGuard {
+        let guard = AddressGuard::new(self.allowed_addresses.clone());
+        // like reqwest, assume http if the proxy has no scheme
+        let proxy = if self.proxy.contains("://") {
+            Url::parse(&self.proxy)
+        } else {
+            Url::parse(&format!("http://{}", self.proxy))
+        };
+        match proxy.ok().and_then(|proxy| proxy.host_str().map(str::to_owned)) {
+            Some(host) => guard.with_exempt_host(&host),
+            None => guard,
+        }
+    }
+
+    /// Checks for combinations of options that clap can't express
+    fn validate(&self) -> Result<(), clap::Error> {
+        if self.polite && self.contact.is_none() {
+            return Err(Args::command()
+                .error(ErrorKind::MissingRequiredArgument, "--polite requires --contact so site owners can reach us"));
+        }
+        Ok(())
+    }
+
+    fn config(&self) -> link::Config {
+        let (request_interval_millis, max_concurrent_tasks) = if self.polite {
+            (POLITE_REQUEST_INTERVAL_MILLIS, POLITE_MAX_CONCURRENT_TASKS)
+        } else {
+            (DEFAULT_REQUEST_INTERVAL_MILLIS, DEFAULT_MAX_CONCURRENT_TASKS)
+        };
+
+        link::Config {
+            same_domain_only: self.same_domain_only,
+            max_follow_content_size: self.max_follow_content_size,
+            max_content_size: self.max_content_size,
+            min_instruction_chars: self.min_instruction_chars,
+            min_total_instruction_chars: self.min_total_instruction_chars,
+            min_completeness: self.min_completeness,
+            max_depth: self.max_depth,
+            request_interval: Duration::from_millis(self.request_interval_millis.unwrap_or(request_interval_millis)),
+            max_concurrent_tasks: self.max_concurrent_tasks.unwrap_or(max_concurrent_tasks),
+            max_concurrent_downloads: self.max_concurrent_downloads,
+            headers: HeaderConfig {
+                user_agent: self.user_agent.clone(),
+                accept_language: self.accept_language.clone(),
+                contact: self.contact.clone(),
+            },
+            address_guard: self.address_guard(),
+            process_timeout: Duration::from_secs(self.process_timeout_secs),
+            max_retries: self.max_retries,
+            download_timeout: Duration::from_secs(self.download_timeout_secs),
+            max_domain_waiting_links: self.max_domain_waiting_links,
+            domain_download_slots: self.domain_download_slots,
+            shutdown_timeout: Duration::from_secs(self.shutdown_timeout_secs),
+            prioritize_domains: self.prioritize_domains,
+            link_rewrites: if self.link_rewrites.is_empty() {
+                LinkRewrite::defaults()
+            } else {
+                self.link_rewrites.clone()
+            },
+        }
+    }
+}
+
+/// Resolves on Ctrl+C or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        signal::ctrl_c().await.expect("Failed to listen for Ctrl+C");
+    };
+
+    let terminate = async {
+        signal::unix::signal(SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+
+    tokio::select! {
+        _ = ctrl_c => (),
+        _ = terminate => (),
+    }
 }
 
 #[tokio::main]
 async fn main() {
-    let fmt_layer = tracing_subscriber::fmt::layer()
-        .with_line_number(true)
-        .with_filter(EnvFilter::new("recipe_finder=trace,recipe_common=trace"));
+    let args = Args::parse();
 
-    tracing_subscriber::registry()
-        .with(fmt_layer)
-        .init();
+    logging::init("recipe_finder=trace,recipe_common=trace", args.log_format);
 
     info!("Starting...");
+    if let Err(err) = args.validate() {
+        err.exit();
+    }
+    let config = args.config();
 
-    let args = Args::parse();
+    recipe_common::link_blacklist::set_refresh_interval(Duration::from_secs(args.blacklist_refresh_secs));
 
     let mut buf = vec![];
     File::open(args.crt_file)
@@ -81,10 +293,74 @@ async fn main() {
         .await
         .unwrap();
 
+    recipe_common::link::set_domain_slots(redis_links.clone(), args.domain_download_slots).await.expect("Failed to set domain slots");
+    recipe_common::link::set_max_domain_waiting_links(redis_links.clone(), args.max_domain_waiting_links).await.expect("Failed to set max domain waiting links");
     recipe_common::link::reset_tasks(redis_links.clone()).await.expect("Failed to reset link tasks");
+    if args.prioritize_domains {
+        recipe_common::link::rebuild_domain_priorities(redis_links.clone()).await.expect("Failed to rebuild domain priorities");
+    }
 
-    tokio::spawn(link::run(redis_links.clone(), redis_recipes.clone(), args.proxy, certificates));
-    // await to prevent program from exiting
-    let _ = tokio::spawn(statistic::run(redis_links.clone(), redis_recipes.clone(), mysql)).await;
+    tokio::spawn(reaper::run(redis_links.clone(), Duration::from_secs(args.stuck_task_timeout_secs)));
+    if let Some(recrawl_ttl_secs) = args.recrawl_ttl_secs {
+        tokio::spawn(recrawler::run(
+            redis_links.clone(), 
+            Duration::from_secs(args.recrawl_interval_secs), 
+            Duration::from_secs(recrawl_ttl_secs), 
+            args.recrawl_priority,
+        ));
+    }
+    tokio::spawn(statistic::run(redis_links.clone(), redis_recipes.clone(), mysql, Duration::from_secs(args.statistic_interval_secs)));
+    link::run(redis_links.clone(), redis_recipes.clone(), config, args.proxy, certificates, shutdown_signal()).await;
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(extra: &[&str]) -> Args {
+        let required = [
+            "recipe-finder",
+            "--proxy", "localhost:8080",
+            "--crt-file", "proxy.crt",
+            "--mysql-url", "mysql://localhost/recipes",
+            "--redis-links-url", "redis://localhost/0",
+            "--redis-recipes-url", "redis://localhost/1",
+        ];
+        Args::try_parse_from(required.iter().chain(extra)).unwrap()
+    }
+
+    #[test]
+    fn polite_uses_conservative_defaults() {
+        let args = args(&["--polite", "--contact", "crawler@example.com"]);
+        args.validate().unwrap();
+        let config = args.config();
+
+        assert_eq!(config.request_interval, Duration::from_millis(POLITE_REQUEST_INTERVAL_MILLIS));
+        assert_eq!(config.max_concurrent_tasks, POLITE_MAX_CONCURRENT_TASKS);
+        assert_eq!(config.headers.contact.as_deref(), Some("crawler@example.com"));
+    }
+
+    #[test]
+    fn explicit_options_take_precedence_over_polite() {
+        let config = args(&["--polite", "--contact", "crawler@example.com", "--request-interval-millis", "500", "--max-concurrent-tasks", "8"]).config();
+
+        assert_eq!(config.request_interval, Duration::from_millis(500));
+        assert_eq!(config.max_concurrent_tasks, 8);
+    }
+
+    #[test]
+    fn defaults_without_polite() {
+        let config = args(&[]).config();
+
+        assert_eq!(config.request_interval, Duration::from_millis(DEFAULT_REQUEST_INTERVAL_MILLIS));
+        assert_eq!(config.max_concurrent_tasks, DEFAULT_MAX_CONCURRENT_TASKS);
+    }
+
+    #[test]
+    fn polite_requires_contact() {
+        let err = args(&["--polite"]).validate().unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::MissingRequiredArgument);
+    }
+}
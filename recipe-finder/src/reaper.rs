@@ -0,0 +1,22 @@
+use std::{error::Error, time::Duration};
+
+use log::{info, warn};
+use recipe_common::link::reset_stuck_tasks;
+use redis::aio::MultiplexedConnection;
+use tokio::time::interval;
+
+pub async fn run(redis_links: MultiplexedConnection, stuck_task_timeout: Duration) {
+    info!("Started stuck task reaper");
+
+    let mut interval = interval(Duration::from_secs(60));
+
+    loop {
+        interval.tick().await;
+
+        match reset_stuck_tasks(redis_links.clone(), stuck_task_timeout).await {
+            Ok(0) => (),
+            Ok(reset) => info!("Re-queued {reset} stuck links"),
+            Err(err) => warn!("Error while resetting stuck tasks: {} (source: {:?})", err, err.source()),
+        }
+    }
+}
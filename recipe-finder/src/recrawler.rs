@@ -0,0 +1,26 @@
+use std::{error::Error, time::Duration};
+
+use log::{info, warn};
+use recipe_common::link::requeue_stale;
+use redis::aio::MultiplexedConnection;
+use tokio::time::interval;
+
+/// Maximum number of links re-queued at once, so a big backlog of stale links is spread out
+/// rather than flooding the queue
+const MAX_REQUEUED_PER_TICK: usize = 1000;
+
+pub async fn run(redis_links: MultiplexedConnection, recrawl_interval: Duration, ttl: Duration, priority: f32) {
+    info!("Started recrawler");
+
+    let mut interval = interval(recrawl_interval);
+
+    loop {
+        interval.tick().await;
+
+        match requeue_stale(redis_links.clone(), ttl, priority, MAX_REQUEUED_PER_TICK).await {
+            Ok(0) => (),
+            Ok(requeued) => info!("Re-queued {requeued} stale links"),
+            Err(err) => warn!("Error while re-queueing stale links: {} (source: {:?})", err, err.source()),
+        }
+    }
+}
@@ -45,11 +45,12 @@ timestamp, recipe_count
 pub async fn run(
     redis_links: MultiplexedConnection, 
     redis_recipes: MultiplexedConnection, 
-    mysql: Pool<MySql>
+    mysql: Pool<MySql>,
+    update_interval: Duration,
 ) {
     info!("Started statistic updater");
 
-    let mut interval = interval(Duration::from_secs(30));
+    let mut interval = interval(update_interval);
 
     loop {
         interval.tick().await;